@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The signatures of the files a scan has finished, --checkpoint, so an interrupted scan can pick
+//! up where it stopped with --resume instead of reading every file again.
+//!
+//! A checkpoint is JSON lines.  The first is the header:
+//! ```json
+//! {"version":1,"duplihere":"0.9.2","files":[{"name":"src/a.c","size":1234,"modified":1700000000}]}
+//! ```
+//! version is CHECKPOINT_VERSION, bumped whenever the format changes, a checkpoint of another
+//! version isn't resumed.  files is the whole set being scanned in scan order, with the size and
+//! modification time, seconds since the epoch, of each.  Those which aren't files on disk, the
+//! --deny-list snippets, have a content hash of their text in place of a modification time.
+//! Each line after it is a file which was
+//! finished: its id, the position in files, a hash of the settings it was scanned with and its
+//! signatures, null for a file skipped as generated.  A line is written as each file finishes and
+//! the lines are flushed to disk every CHECKPOINT_INTERVAL, a last line cut short by the
+//! interruption is ignored.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, BufReader, BufWriter};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// How often the finished files are flushed to the checkpoint.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A file in the set being scanned, to tell whether it changed since the checkpoint.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusFile {
+    name: String,
+    size: u64,
+    modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    duplihere: String,
+    files: Vec<CorpusFile>,
+}
+
+/// A finished file, S is the signatures, or a reference to them when writing.
+#[derive(Serialize, Deserialize)]
+pub struct Record<S> {
+    pub fid: u32,
+    pub settings: u64,
+    pub signatures: Option<S>,
+}
+
+/// The set of files being scanned, ids are their position.  virtual_file gives the size and a
+/// hash of the content of those which aren't on disk.
+pub fn corpus(
+    files: &[(u32, String)],
+    virtual_file: impl Fn(&str) -> Option<(u64, u64)>,
+) -> io::Result<Vec<CorpusFile>> {
+    files
+        .iter()
+        .map(|(_, name)| {
+            if let Some((size, content)) = virtual_file(name) {
+                return Ok(CorpusFile {
+                    name: name.clone(),
+                    size,
+                    modified: 0,
+                    content: Some(content),
+                });
+            }
+            let md = fs::metadata(name)?;
+            let modified = md
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            Ok(CorpusFile {
+                name: name.clone(),
+                size: md.len(),
+                modified,
+                content: None,
+            })
+        })
+        .collect()
+}
+
+pub struct Checkpoint {
+    out: Mutex<(BufWriter<File>, Instant)>,
+}
+
+impl Checkpoint {
+    /// Start a checkpoint for the files, replacing file once the header is written so resuming
+    /// from and checkpointing to the same file doesn't lose what was resumed.
+    pub fn create(file: &str, files: Vec<CorpusFile>) -> io::Result<Checkpoint> {
+        let header = Header {
+            version: CHECKPOINT_VERSION,
+            duplihere: env!("CARGO_PKG_VERSION").to_string(),
+            files,
+        };
+        let tmp = format!("{}.tmp", file);
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut out, &header)?;
+            writeln!(out)?;
+            out.flush()?;
+        }
+        fs::rename(&tmp, file)?;
+
+        let out = BufWriter::new(OpenOptions::new().append(true).open(file)?);
+        Ok(Checkpoint {
+            out: Mutex::new((out, Instant::now())),
+        })
+    }
+
+    /// Add a finished file, flushing if it's been CHECKPOINT_INTERVAL since the last flush.
+    pub fn record<S: Serialize>(&self, record: &Record<S>) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        serde_json::to_writer(&mut out.0, record)?;
+        writeln!(out.0)?;
+        if out.1.elapsed() >= CHECKPOINT_INTERVAL {
+            out.0.flush()?;
+            out.1 = Instant::now();
+        }
+        Ok(())
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        self.out.lock().unwrap().0.flush()
+    }
+}
+
+/// Read the finished files from a checkpoint.  It's an error if the files being scanned aren't
+/// those of the checkpoint, or any of them changed, or a file was scanned with other settings
+/// than settings gives for it now.
+pub fn resume<S: DeserializeOwned>(
+    file: &str,
+    files: &[CorpusFile],
+    settings: impl Fn(u32) -> u64,
+) -> Result<Vec<Record<S>>, String> {
+    let reader = BufReader::new(File::open(file).map_err(|e| e.to_string())?);
+    let mut lines = reader.lines();
+
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line.map_err(|e| e.to_string())?)
+            .map_err(|e| format!("not a checkpoint, {}", e))?,
+        None => return Err("not a checkpoint, it's empty".to_string()),
+    };
+    if header.version != CHECKPOINT_VERSION {
+        return Err(format!(
+            "checkpoint version {} written by duplihere {}, this is version {}",
+            header.version, header.duplihere, CHECKPOINT_VERSION
+        ));
+    }
+    if header.files.len() != files.len() {
+        return Err(format!(
+            "the checkpoint is of {} files, {} are being scanned",
+            header.files.len(),
+            files.len()
+        ));
+    }
+    if let Some((then, now)) = header.files.iter().zip(files).find(|(a, b)| a != b) {
+        return Err(if then.name != now.name {
+            format!("{} is being scanned in place of {}", now.name, then.name)
+        } else {
+            format!("{} changed since the checkpoint", now.name)
+        });
+    }
+
+    let mut records: Vec<Record<S>> = vec![];
+    let mut lines = lines.peekable();
+    while let Some(line) = lines.next() {
+        let line = line.map_err(|e| e.to_string())?;
+        let record: Record<S> = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            // Cut short by the interruption
+            Err(_) if lines.peek().is_none() => break,
+            Err(e) => return Err(format!("line {}, {}", records.len() + 2, e)),
+        };
+        if record.fid as usize >= files.len() {
+            return Err(format!(
+                "line {}, no file {}",
+                records.len() + 2,
+                record.fid
+            ));
+        }
+        if record.settings != settings(record.fid) {
+            return Err(format!(
+                "{} was scanned with other settings",
+                files[record.fid as usize].name
+            ));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    use crate::TestDir;
+
+    /// A directory for a test, with a file in it to scan.
+    fn setup(name: &str) -> (TestDir, Vec<(u32, String)>) {
+        let dir = TestDir::new(name);
+        let file = dir.write("a.c", "int a;\n");
+        (dir, vec![(0, file.to_string_lossy().to_string())])
+    }
+
+    fn no_virtual(_: &str) -> Option<(u64, u64)> {
+        None
+    }
+
+    /// A checkpoint of the files with a record for each of them.
+    fn write(dir: &Path, files: &[(u32, String)], settings: u64) -> String {
+        let name = dir.join("ck").to_string_lossy().to_string();
+        let ck = Checkpoint::create(&name, corpus(files, no_virtual).unwrap()).unwrap();
+        for (fid, _) in files {
+            ck.record(&Record {
+                fid: *fid,
+                settings,
+                signatures: Some(vec![1u64, 2, 3]),
+            })
+            .unwrap();
+        }
+        ck.finish().unwrap();
+        name
+    }
+
+    #[test]
+    fn resume_reads_records() {
+        let (dir, files) = setup("resume_reads_records");
+        let ck = write(&dir, &files, 7);
+        let corpus = corpus(&files, no_virtual).unwrap();
+        let records: Vec<Record<Vec<u64>>> = resume(&ck, &corpus, |_| 7).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].signatures, Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn resume_ignores_a_cut_short_last_line() {
+        let (dir, files) = setup("resume_ignores_a_cut_short_last_line");
+        let ck = write(&dir, &files, 7);
+        let mut f = OpenOptions::new().append(true).open(&ck).unwrap();
+        write!(f, "{{\"fid\":0,\"settings\":7,\"signa").unwrap();
+        drop(f);
+
+        let corpus = corpus(&files, no_virtual).unwrap();
+        let records: Vec<Record<Vec<u64>>> = resume(&ck, &corpus, |_| 7).unwrap();
+        assert_eq!(records.len(), 1);
+    }
+
+    #[test]
+    fn resume_rejects_changes() {
+        let (dir, files) = setup("resume_rejects_changes");
+        let ck = write(&dir, &files, 7);
+
+        let corpus_now = corpus(&files, no_virtual).unwrap();
+        let e = resume::<Vec<u64>>(&ck, &corpus_now, |_| 8).err().unwrap();
+        assert!(e.contains("other settings"), "{}", e);
+
+        fs::write(&files[0].1, "int a;\nint b;\n").unwrap();
+        let corpus_now = corpus(&files, no_virtual).unwrap();
+        let e = resume::<Vec<u64>>(&ck, &corpus_now, |_| 7).err().unwrap();
+        assert!(e.contains("changed since the checkpoint"), "{}", e);
+    }
+
+    #[test]
+    fn virtual_files_are_recorded_by_content() {
+        let files = vec![(0, "<deny-list>:snippet 1".to_string())];
+        let c = corpus(&files, |_| Some((10, 1234))).unwrap();
+        assert_eq!(c[0].size, 10);
+        assert_eq!(c[0].content, Some(1234));
+        let other = corpus(&files, |_| Some((10, 4321))).unwrap();
+        assert_ne!(c, other);
+    }
+}
@@ -0,0 +1,200 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Settings for a subtree from duplihere.toml files.  A duplihere.toml in a directory under the
+//! scan root, the current directory, applies to the files below it, one in a sub directory
+//! overrides it setting by setting for the files below that.  What's given on the command line
+//! takes precedence over every file, the caller leaves those settings out.  The normalization
+//! settings change a file's line signatures, so the same lines in two subtrees which normalize
+//! differently don't match.
+//!
+//! A file can set:
+//! ```toml
+//! lines = 10
+//! normalize = "collapse"
+//! normalize-unicode = "nfc"
+//! ignore-case = true
+//! ```
+
+use serde::{Deserialize, Deserializer};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::{Normalize, UnicodeForm};
+
+pub const CONFIG_FILE_NAME: &str = "duplihere.toml";
+
+/// The settings of a file, or those in effect for a file once they are merged.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub lines: Option<u32>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub normalize: Option<Normalize>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub normalize_unicode: Option<UnicodeForm>,
+    pub ignore_case: Option<bool>,
+}
+
+/// A setting given as a string which is parsed as the command line option is.
+fn parsed<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    Option::<String>::deserialize(d)?
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+impl Settings {
+    /// Take the settings a config nearer the file gives.
+    fn merge(&mut self, nearer: &Settings) {
+        self.lines = nearer.lines.or(self.lines);
+        self.normalize = nearer.normalize.or(self.normalize);
+        self.normalize_unicode = nearer.normalize_unicode.or(self.normalize_unicode);
+        self.ignore_case = nearer.ignore_case.or(self.ignore_case);
+    }
+}
+
+pub struct DirConfigs {
+    root: PathBuf,
+    // The settings of the config in each directory we've looked in, None if it has none.
+    dirs: HashMap<PathBuf, Option<Settings>>,
+}
+
+impl DirConfigs {
+    /// Configs are looked for in root and the directories below it, root must be canonical.
+    pub fn new(root: PathBuf) -> DirConfigs {
+        DirConfigs {
+            root,
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, dir: &Path) -> Result<Option<&Settings>, String> {
+        if !self.dirs.contains_key(dir) {
+            let file = dir.join(CONFIG_FILE_NAME);
+            let settings = match std::fs::read_to_string(&file) {
+                Ok(content) => Some(
+                    toml::from_str(&content).map_err(|e| format!("{}: {}", file.display(), e))?,
+                ),
+                Err(_) => None,
+            };
+            self.dirs.insert(dir.to_path_buf(), settings);
+        }
+        Ok(self.dirs[dir].as_ref())
+    }
+
+    /// The settings in effect for a file, a canonical path.  Files outside of root have none.
+    pub fn settings(&mut self, file: &Path) -> Result<Settings, String> {
+        let mut dirs: Vec<PathBuf> = file
+            .ancestors()
+            .skip(1)
+            .take_while(|d| d.starts_with(&self.root))
+            .map(|d| d.to_path_buf())
+            .collect();
+        dirs.reverse();
+
+        let mut rc = Settings::default();
+        for d in dirs {
+            if let Some(s) = self.load(&d)? {
+                rc.merge(s);
+            }
+        }
+        Ok(rc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::TestDir;
+
+    /// A directory for a test with the config files in it, in the sub directories.
+    fn setup(name: &str, configs: &[(&str, &str)]) -> TestDir {
+        let dir = TestDir::new(name);
+        for (sub, content) in configs {
+            dir.write(Path::new(sub).join(CONFIG_FILE_NAME), content);
+        }
+        dir
+    }
+
+    #[test]
+    fn nearer_settings_override() {
+        let dir = setup(
+            "nearer_settings_override",
+            &[
+                ("", "lines = 8\nignore-case = true\n"),
+                ("sub", "lines = 4\nnormalize = \"collapse\"\n"),
+                ("sub/deeper", "ignore-case = false\n"),
+            ],
+        );
+        let mut configs = DirConfigs::new(dir.to_path_buf());
+
+        assert_eq!(
+            configs.settings(&dir.join("a.c")).unwrap(),
+            Settings {
+                lines: Some(8),
+                ignore_case: Some(true),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            configs.settings(&dir.join("sub/deeper/b.c")).unwrap(),
+            Settings {
+                lines: Some(4),
+                normalize: Some(Normalize::Collapse),
+                normalize_unicode: None,
+                ignore_case: Some(false),
+            }
+        );
+        // A directory without a config has its parent's.
+        assert_eq!(
+            configs.settings(&dir.join("sub/other/c.c")).unwrap(),
+            configs.settings(&dir.join("sub/d.c")).unwrap()
+        );
+    }
+
+    #[test]
+    fn outside_of_root() {
+        let dir = setup("outside_of_root", &[("", "lines = 8\n"), ("root", "")]);
+        let mut configs = DirConfigs::new(dir.join("root"));
+        assert_eq!(
+            configs.settings(&dir.join("a.c")).unwrap(),
+            Settings::default()
+        );
+        // The config above root doesn't apply below it either.
+        assert_eq!(
+            configs.settings(&dir.join("root/a.c")).unwrap(),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn invalid_configs() {
+        for (name, content, error) in [
+            ("unknown_setting", "line = 8\n", "unknown field `line`"),
+            (
+                "bad_value",
+                "normalize = \"tabs\"\n",
+                "invalid normalization 'tabs'",
+            ),
+            ("bad_type", "lines = \"8\"\n", "invalid type"),
+        ] {
+            let dir = setup(name, &[("", content)]);
+            let mut configs = DirConfigs::new(dir.to_path_buf());
+            let e = configs.settings(&dir.join("a.c")).err().unwrap();
+            assert!(
+                e.starts_with(&dir.join(CONFIG_FILE_NAME).display().to_string()),
+                "{}",
+                e
+            );
+            assert!(e.contains(error), "{}", e);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The duplication graph as a Graphviz DOT file, --dot.  Each file with a reported duplicate is a
+//! node and an edge joins two files which share duplicates, weighted by the number of lines they
+//! share.  Copies within one file are a self loop.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{prelude::*, BufWriter};
+
+use crate::{Collision, FILE_LOOKUP};
+
+/// Quote a string for DOT.
+fn quoted(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The lines each pair of files share, keyed by their file ids, the smaller first.
+fn edges(collisions: &[&Collision]) -> BTreeMap<(u32, u32), u64> {
+    let mut edges: BTreeMap<(u32, u32), u64> = BTreeMap::new();
+    for c in collisions {
+        let mut copies: BTreeMap<u32, u64> = BTreeMap::new();
+        for f in &c.files {
+            *copies.entry(f.0).or_default() += 1;
+        }
+        for (&a, &count) in &copies {
+            if count > 1 {
+                *edges.entry((a, a)).or_default() += c.num_lines as u64 * (count - 1);
+            }
+            for &b in copies.range(a + 1..).map(|(b, _)| b) {
+                *edges.entry((a, b)).or_default() += c.num_lines as u64;
+            }
+        }
+    }
+    edges
+}
+
+/// Write the graph for the collisions.  The caller must not be holding the lock on FILE_LOOKUP.
+pub fn write_graph(file_name: &str, collisions: &[&Collision]) -> std::io::Result<()> {
+    let edges = edges(collisions);
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    let mut out = BufWriter::new(File::create(file_name)?);
+    writeln!(out, "graph duplihere {{")?;
+    writeln!(out, "  node [shape=box];")?;
+
+    let mut nodes: Vec<u32> = edges.keys().flat_map(|&(a, b)| [a, b]).collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+    for n in nodes {
+        writeln!(
+            out,
+            "  f{} [label={}];",
+            n,
+            quoted(&file_lookup_locked.id_to_name(n))
+        )?;
+    }
+    for ((a, b), lines) in edges {
+        writeln!(
+            out,
+            "  f{} -- f{} [weight={}, label=\"{}\"];",
+            a, b, lines, lines
+        )?;
+    }
+    writeln!(out, "}}")?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_lines_per_pair() {
+        let three = Collision::new(0, 10, vec![(2, 0), (0, 5), (1, 7)]);
+        let two = Collision::new(0, 6, vec![(1, 0), (0, 20)]);
+        let edges = edges(&[&three, &two]);
+        assert_eq!(
+            edges.into_iter().collect::<Vec<_>>(),
+            [((0, 1), 16), ((0, 2), 10), ((1, 2), 10)]
+        );
+    }
+
+    #[test]
+    fn copies_within_a_file_are_a_self_loop() {
+        let within = Collision::new(0, 8, vec![(3, 0), (3, 20), (3, 40), (1, 0)]);
+        let edges = edges(&[&within]);
+        assert_eq!(
+            edges.into_iter().collect::<Vec<_>>(),
+            [((1, 3), 8), ((3, 3), 16)]
+        );
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quoted("src/a.c"), "\"src/a.c\"");
+        assert_eq!(quoted("a \"b\"\\c"), "\"a \\\"b\\\"\\\\c\"");
+    }
+}
@@ -0,0 +1,193 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The text of each duplicate written to a file of its own, --extract-to, ready to be turned into
+//! shared code.  A file is named by the duplicate's id with the extension of its first copy, eg.
+//! 12.c, and manifest.json lists every file written with the duplicate's hash signature, number
+//! of lines and its locations as in the JSON report.
+//!
+//! Copies can differ in whitespace, or more with options like --ignore-case, so there's no one
+//! text to extract.  The first copy's text is written then, identical is false in the manifest and
+//! the caller warns about each such duplicate.
+
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufWriter};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{read_region, Collision, FILE_LOOKUP};
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Serialize)]
+struct Snippet {
+    file: String,
+    id: usize,
+    key: u64,
+    num_lines: u32,
+    identical: bool,
+    files: Vec<(String, u32)>,
+}
+
+/// Write the snippets and manifest to dir, creating it if needed, returning the ids of the
+/// duplicates whose copies differ, written as their first copy.  The caller must not be holding
+/// the lock on FILE_LOOKUP.
+pub fn write_snippets(
+    dir: &str,
+    collisions: &[&Collision],
+    separator: u8,
+    buffer_size: usize,
+) -> io::Result<Vec<usize>> {
+    // File name, start line and number of lines of each copy
+    let regions: Vec<Vec<(String, u32, u32)>> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        collisions
+            .iter()
+            .map(|c| {
+                c.files
+                    .iter()
+                    .map(|f| {
+                        let (start, end) = file_lookup_locked.line_range(f.0, f.1, c.num_lines);
+                        (
+                            file_lookup_locked.id_to_name(f.0).to_string(),
+                            start,
+                            end - start,
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    fs::create_dir_all(dir)?;
+
+    let mut manifest = vec![];
+    let mut differing = vec![];
+    for (c, regions) in collisions.iter().zip(regions) {
+        let copies: Vec<Vec<Vec<u8>>> = regions
+            .iter()
+            .map(|(name, start, count)| {
+                read_region(
+                    name,
+                    *start as usize,
+                    *count as usize,
+                    separator,
+                    buffer_size,
+                )
+            })
+            .collect();
+        let identical = copies.iter().all(|copy| *copy == copies[0]);
+        if !identical {
+            differing.push(c.id);
+        }
+
+        let file = match Path::new(&regions[0].0).extension() {
+            Some(ext) => format!("{}.{}", c.id, ext.to_string_lossy()),
+            None => c.id.to_string(),
+        };
+        let mut out = BufWriter::new(File::create(Path::new(dir).join(&file))?);
+        for line in &copies[0] {
+            out.write_all(line)?;
+        }
+        out.flush()?;
+
+        manifest.push(Snippet {
+            file,
+            id: c.id,
+            key: c.key,
+            num_lines: c.num_lines,
+            identical,
+            files: regions
+                .into_iter()
+                .map(|(name, start, _)| (name, start))
+                .collect(),
+        });
+    }
+
+    let mut out = BufWriter::new(File::create(Path::new(dir).join(MANIFEST_NAME))?);
+    serde_json::to_writer_pretty(&mut out, &manifest)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(differing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::TestDir;
+
+    /// The files written to dir, registered in FILE_LOOKUP, and their ids.
+    fn register(dir: &TestDir, files: &[(&str, &str)]) -> Vec<u32> {
+        let mut file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        files
+            .iter()
+            .map(|(f, content)| {
+                let path = dir.write(f, content);
+                file_lookup_locked
+                    .register_file(&path.to_string_lossy())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    fn collision(id: usize, num_lines: u32, files: Vec<(u32, u32)>) -> Collision {
+        let mut c = Collision::new(1000 + id as u64, num_lines, files);
+        c.id = id;
+        c
+    }
+
+    #[test]
+    fn snippets_and_manifest() {
+        let dir = TestDir::new("snippets_and_manifest");
+        let ids = register(
+            &dir,
+            &[
+                ("a.c", "int a;\nx = 1;\ny = 2;\n"),
+                ("b.c", "x = 1;\ny = 2;\nint b;\n"),
+                ("README", "one\ntwo\n"),
+                ("notes", "  one\ntwo\n"),
+            ],
+        );
+        let same = collision(1, 2, vec![(ids[0], 1), (ids[1], 0)]);
+        let differ = collision(2, 2, vec![(ids[2], 0), (ids[3], 0)]);
+        let out = dir.join("out");
+
+        let differing =
+            write_snippets(&out.to_string_lossy(), &[&same, &differ], b'\n', 8192).unwrap();
+        assert_eq!(differing, [2]);
+        assert_eq!(
+            fs::read_to_string(out.join("1.c")).unwrap(),
+            "x = 1;\ny = 2;\n"
+        );
+        // No extension, and the first copy's text.
+        assert_eq!(fs::read_to_string(out.join("2")).unwrap(), "one\ntwo\n");
+
+        let manifest: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(out.join(MANIFEST_NAME)).unwrap()).unwrap();
+        let name = |f: &str| dir.join(f).to_string_lossy().to_string();
+        assert_eq!(
+            manifest,
+            serde_json::json!([
+                {
+                    "file": "1.c",
+                    "id": 1,
+                    "key": 1001,
+                    "num_lines": 2,
+                    "identical": true,
+                    "files": [[name("a.c"), 1], [name("b.c"), 0]],
+                },
+                {
+                    "file": "2",
+                    "id": 2,
+                    "key": 1002,
+                    "num_lines": 2,
+                    "identical": false,
+                    "files": [[name("README"), 0], [name("notes"), 0]],
+                },
+            ])
+        );
+    }
+}
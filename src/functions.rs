@@ -0,0 +1,529 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Whole functions as the unit of duplication, --whole-functions.  Instead of windows of lines
+//! we find the functions in each file with a brace scanner and report functions which are
+//! duplicated in their entirety, header and body.
+//!
+//! The scanner is a heuristic, it doesn't parse the language.  It skips comments, string and
+//! character literals and balances braces.  A brace block at the top level, or in a container
+//! such as a class, namespace or impl block, is a function when the text before it looks like a
+//! function header: for Rust and Go when it has the fn or func keyword, for the other languages
+//! when it has a parameter list and doesn't start with a statement keyword like if or while.
+//! Blocks inside a function are part of it, so nested functions and closures aren't units of
+//! their own.  A function starts at the first line of its header, leading comments aren't part
+//! of it.
+//!
+//! Known failure modes, where functions are missed or mis-measured:
+//! * Macros which expand to braces or function headers, eg. BEGIN_NAMESPACE or a function
+//!   defined by a macro.
+//! * Preprocessor conditionals with unbalanced braces in their branches.
+//! * Literals the scanner doesn't know about, eg. Rust raw strings with quotes in them, C++
+//!   raw strings, JavaScript regular expression literals, containing braces or quotes.
+//! * K&R style C function definitions, the parameter declarations end the header.
+
+use std::fmt;
+use std::str::FromStr;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{Collision, SignatureArena};
+
+/// The language --whole-functions scans files as, None when it isn't in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    None,
+    C,
+    Cpp,
+    CSharp,
+    Go,
+    Java,
+    JavaScript,
+    Rust,
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Language::None),
+            "c" => Ok(Language::C),
+            "cpp" => Ok(Language::Cpp),
+            "csharp" => Ok(Language::CSharp),
+            "go" => Ok(Language::Go),
+            "java" => Ok(Language::Java),
+            "javascript" => Ok(Language::JavaScript),
+            "rust" => Ok(Language::Rust),
+            _ => Err(format!(
+                "invalid language '{}', expected c, cpp, csharp, go, java, javascript, rust or none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Language::None => write!(f, "none"),
+            Language::C => write!(f, "c"),
+            Language::Cpp => write!(f, "cpp"),
+            Language::CSharp => write!(f, "csharp"),
+            Language::Go => write!(f, "go"),
+            Language::Java => write!(f, "java"),
+            Language::JavaScript => write!(f, "javascript"),
+            Language::Rust => write!(f, "rust"),
+        }
+    }
+}
+
+/// Keywords which start a block that isn't a function even though it has parentheses.
+const STATEMENT_KEYWORDS: [&str; 14] = [
+    "if",
+    "else",
+    "for",
+    "foreach",
+    "while",
+    "do",
+    "switch",
+    "try",
+    "catch",
+    "finally",
+    "using",
+    "lock",
+    "synchronized",
+    "with",
+];
+
+/// A function found in a file, start and num_lines are in line signatures.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Function {
+    pub start: u32,
+    pub num_lines: u32,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Block {
+    // A class, namespace, impl block and such, which can hold functions.
+    Container,
+    Function,
+    // Anything else, including every block inside a function.
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lex {
+    Code,
+    BlockComment,
+    String(char),
+}
+
+/// Finds the functions in a file a line at a time.
+pub struct Scanner {
+    language: Language,
+    lex: Lex,
+    blocks: Vec<Block>,
+    // The text since the last statement or block at the level we look for functions at.
+    header: String,
+    header_start: Option<u32>,
+    // Start and name of the function we are in.
+    function: Option<(u32, Option<String>)>,
+    functions: Vec<Function>,
+}
+
+impl Scanner {
+    pub fn new(language: Language) -> Scanner {
+        Scanner {
+            language,
+            lex: Lex::Code,
+            blocks: vec![],
+            header: String::new(),
+            header_start: None,
+            function: None,
+            functions: vec![],
+        }
+    }
+
+    fn in_function(&self) -> bool {
+        self.blocks
+            .iter()
+            .any(|b| *b == Block::Function || *b == Block::Other)
+    }
+
+    fn reset_header(&mut self) {
+        self.header.clear();
+        self.header_start = None;
+    }
+
+    /// Scan a line, index is its position in the line signatures.  Lines which have no signature,
+    /// eg. ones with the skip directive, are scanned with the index of the next line which does.
+    /// end is the index just past the line.
+    pub fn line(&mut self, line: &str, index: u32, end: u32) {
+        if self.lex == Lex::Code
+            && matches!(
+                self.language,
+                Language::C | Language::Cpp | Language::CSharp
+            )
+            && line.trim_start().starts_with('#')
+        {
+            // Preprocessor directives, a #define can have any text in it.
+            if !self.in_function() {
+                self.reset_header();
+            }
+            return;
+        }
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let next = chars.get(i + 1).copied();
+            match self.lex {
+                Lex::BlockComment => {
+                    if c == '*' && next == Some('/') {
+                        self.lex = Lex::Code;
+                        i += 1;
+                    }
+                }
+                Lex::String(quote) => {
+                    if c == '\\' && quote != '`' {
+                        i += 1;
+                    } else if c == quote {
+                        self.lex = Lex::Code;
+                    }
+                }
+                Lex::Code => match c {
+                    '/' if next == Some('/') => break,
+                    '/' if next == Some('*') => {
+                        self.lex = Lex::BlockComment;
+                        i += 1;
+                    }
+                    '"' => self.lex = Lex::String('"'),
+                    '`' if matches!(self.language, Language::Go | Language::JavaScript) => {
+                        self.lex = Lex::String('`')
+                    }
+                    '\'' if self.language == Language::JavaScript => self.lex = Lex::String('\''),
+                    '\'' => {
+                        // A character literal, otherwise eg. a Rust lifetime.
+                        if next == Some('\\') {
+                            self.lex = Lex::String('\'');
+                            i += 2;
+                        } else if chars.get(i + 2) == Some(&'\'') {
+                            i += 2;
+                        } else {
+                            self.header_char(c, index);
+                        }
+                    }
+                    '{' => self.open(index),
+                    '}' => self.close(end),
+                    ';' if !self.in_function() => self.reset_header(),
+                    _ => self.header_char(c, index),
+                },
+            }
+            i += 1;
+        }
+        if self.lex == Lex::Code {
+            self.header_char(' ', index);
+            // Go ends a statement at the end of a line, a header only continues on the next in
+            // the parameter list.
+            if self.language == Language::Go
+                && !self.in_function()
+                && self.header.matches('(').count() == self.header.matches(')').count()
+            {
+                self.reset_header();
+            }
+        }
+    }
+
+    fn header_char(&mut self, c: char, index: u32) {
+        if self.in_function() {
+            return;
+        }
+        if self.header_start.is_none() && !c.is_whitespace() {
+            self.header_start = Some(index);
+        }
+        self.header.push(c);
+    }
+
+    fn open(&mut self, index: u32) {
+        if self.in_function() {
+            self.blocks.push(Block::Other);
+            return;
+        }
+
+        let header = self.header.trim();
+        let block = if is_function(self.language, header) {
+            self.function = Some((
+                self.header_start.unwrap_or(index),
+                function_name(self.language, header),
+            ));
+            Block::Function
+        } else if header.ends_with('=') {
+            Block::Other
+        } else {
+            Block::Container
+        };
+        self.blocks.push(block);
+        self.reset_header();
+    }
+
+    fn close(&mut self, end: u32) {
+        // An unbalanced closing brace is ignored.
+        if self.blocks.pop() == Some(Block::Function) {
+            if let Some((start, name)) = self.function.take() {
+                self.functions.push(Function {
+                    start,
+                    num_lines: end.saturating_sub(start),
+                    name,
+                });
+            }
+        }
+        if !self.in_function() {
+            self.reset_header();
+        }
+    }
+
+    /// The functions found, in order.  Only the first of the functions which start on the same
+    /// line is kept.
+    pub fn finish(mut self) -> Vec<Function> {
+        self.functions.dedup_by_key(|f| f.start);
+        self.functions
+    }
+}
+
+fn words(header: &str) -> impl Iterator<Item = &str> {
+    header
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+}
+
+/// Does the text before a block look like a function header.
+fn is_function(language: Language, header: &str) -> bool {
+    match language {
+        Language::Rust => words(header).any(|w| w == "fn"),
+        Language::Go => words(header).any(|w| w == "func"),
+        _ => {
+            header.contains('(')
+                && !header.ends_with('=')
+                && words(header)
+                    .next()
+                    .map(|w| !STATEMENT_KEYWORDS.contains(&w))
+                    .unwrap_or(false)
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || c == ':' || c == '~' || c == '.'
+}
+
+/// The name at the start of s, if there is one.
+fn leading_name(s: &str) -> Option<String> {
+    let name: String = s
+        .trim_start()
+        .chars()
+        .take_while(|c| is_name_char(*c))
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The name at the end of s, if there is one.
+fn trailing_name(s: &str) -> Option<&str> {
+    let s = s.trim_end();
+    let start = s
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_name_char(*c))
+        .last()
+        .map(|(i, _)| i)?;
+    Some(&s[start..])
+}
+
+/// The name of the function from its header, if we can tell.
+fn function_name(language: Language, header: &str) -> Option<String> {
+    match language {
+        Language::Rust => {
+            let at = header.find("fn ")?;
+            leading_name(&header[at + 3..])
+        }
+        Language::Go => {
+            let mut rest = header[header.find("func")? + 4..].trim_start();
+            if rest.starts_with('(') {
+                // The receiver of a method.
+                rest = &rest[rest.find(')')? + 1..];
+            }
+            leading_name(rest)
+        }
+        _ => {
+            // The name before the first parameter list which isn't an annotation's or keyword's.
+            let mut depth = 0;
+            for (i, c) in header.char_indices() {
+                match c {
+                    '(' => {
+                        if depth == 0 {
+                            if let Some(name) = trailing_name(&header[..i]) {
+                                let before = header[..i].trim_end();
+                                let annotation = before[..before.len() - name.len()].ends_with('@');
+                                if !annotation && name != "function" {
+                                    return Some(name.to_string());
+                                }
+                            }
+                        }
+                        depth += 1;
+                    }
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+            }
+            // eg. const add = (a, b) => {
+            header
+                .find('=')
+                .and_then(|at| trailing_name(&header[..at]))
+                .map(|n| n.to_string())
+        }
+    }
+}
+
+/// Report the functions which are duplicated, collision_hashes has the location of each function
+/// keyed by the hash of its line signatures.  Functions shorter than min_lines aren't reported.
+pub fn find_collisions(
+    collision_hashes: DashMap<u64, Vec<(u32, u32)>>,
+    file_hashes: &SignatureArena,
+    min_lines: u32,
+    results_hash: &DashMap<u64, Collision>,
+) {
+    for (key, locations) in collision_hashes {
+        if locations.len() < 2 {
+            continue;
+        }
+
+        let function = |l: &(u32, u32)| {
+            let functions = &file_hashes.functions[&l.0];
+            let i = functions.binary_search_by_key(&l.1, |f| f.start).unwrap();
+            &functions[i]
+        };
+        let first = function(&locations[0]);
+        if first.num_lines < min_lines {
+            continue;
+        }
+
+        // Leave out any location which only shares the hash.
+        let text = |l: &(u32, u32), num_lines: u32| {
+            &file_hashes.get(l.0)[l.1 as usize..(l.1 + num_lines) as usize]
+        };
+        let files: Vec<(u32, u32)> = locations
+            .iter()
+            .filter(|l| {
+                let f = function(l);
+                f.num_lines == first.num_lines
+                    && text(l, f.num_lines) == text(&locations[0], first.num_lines)
+            })
+            .copied()
+            .collect();
+        if files.len() < 2 {
+            continue;
+        }
+
+        let mut c = Collision::new(key, first.num_lines, files);
+        c.function = first.name.clone();
+        results_hash.insert(key, c);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The functions found in text, each line having a signature.
+    fn scan(language: Language, text: &str) -> Vec<(u32, u32, Option<String>)> {
+        let mut scanner = Scanner::new(language);
+        for (i, line) in text.lines().enumerate() {
+            scanner.line(line, i as u32, i as u32 + 1);
+        }
+        scanner
+            .finish()
+            .into_iter()
+            .map(|f| (f.start, f.num_lines, f.name))
+            .collect()
+    }
+
+    fn found(start: u32, num_lines: u32, name: &str) -> (u32, u32, Option<String>) {
+        (start, num_lines, Some(name.to_string()))
+    }
+
+    #[test]
+    fn c_functions() {
+        let text = "#include <stdio.h>\n\
+                    #define OPEN {\n\
+                    struct point { int x; int y; };\n\
+                    \n\
+                    static int\n\
+                    add(int a, int b)\n\
+                    {\n\
+                        if (a) { return a + b; }\n\
+                        printf(\"}\\\" {\");\n\
+                        return '}';\n\
+                    }\n\
+                    /* int fake(void) { */\n\
+                    void run(void) { add(1, 2); }\n";
+        assert_eq!(
+            scan(Language::C, text),
+            vec![found(4, 7, "add"), found(12, 1, "run")]
+        );
+    }
+
+    #[test]
+    fn methods_in_containers() {
+        let text = "namespace app {\n\
+                    class Cache {\n\
+                        @Override\n\
+                        public String get(String key) {\n\
+                            synchronized (this) {\n\
+                                return map.get(key);\n\
+                            }\n\
+                        }\n\
+                    }\n\
+                    }\n";
+        assert_eq!(scan(Language::Java, text), vec![found(2, 6, "get")]);
+    }
+
+    #[test]
+    fn rust_and_go() {
+        let text = "impl Point {\n\
+                    \x20   pub fn len<'a>(&'a self) -> f64 {\n\
+                    \x20       let f = |x: f64| { x * x };\n\
+                    \x20       f(self.x)\n\
+                    \x20   }\n\
+                    }\n\
+                    const A: [u8; 2] = [b'{', b'}'];\n";
+        assert_eq!(scan(Language::Rust, text), vec![found(1, 4, "len")]);
+
+        let text = "func (p *Point) Len() float64 {\n\
+                    \treturn `}`\n\
+                    }\n\
+                    var x = struct {\n\
+                    }{}\n";
+        assert_eq!(scan(Language::Go, text), vec![found(0, 3, "Len")]);
+    }
+
+    #[test]
+    fn javascript() {
+        let text = "function total(items) {\n\
+                    \x20 return items.reduce((a, b) => { return a + b; }, 0);\n\
+                    }\n\
+                    const add = (a, b) => {\n\
+                    \x20 return 'a}' + b;\n\
+                    };\n\
+                    const config = {\n\
+                    \x20 debug: true,\n\
+                    };\n";
+        assert_eq!(
+            scan(Language::JavaScript, text),
+            vec![found(0, 3, "total"), found(3, 3, "add")]
+        );
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Self contained HTML report.  The collisions are embedded as a JSON blob which a small inline
+//! script reads to build a table that can be filtered and sorted.  There are no external
+//! dependencies, so the file works when opened directly from a file:// URL.
+
+use serde::Serialize;
+
+use std::fs::File;
+use std::io::{prelude::*, BufWriter};
+
+use crate::{Collision, FILE_LOOKUP};
+
+/// What the script needs for each collision.  The key is a string as a u64 doesn't fit in a
+/// javascript number.  Files are the name, start line and end line (exclusive), 0 based.
+#[derive(Serialize)]
+struct Row {
+    key: String,
+    num_lines: u32,
+    files: Vec<(String, u32, u32)>,
+}
+
+static HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>duplihere report</title>
+<style>
+body { font-family: sans-serif; margin: 1em; }
+#controls { margin-bottom: 1em; }
+#controls label { margin-right: 1.5em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }
+th { background: #eee; cursor: pointer; user-select: none; }
+td.loc { font-family: monospace; white-space: pre; }
+</style>
+</head>
+<body>
+<h1>duplihere report</h1>
+<div id="controls">
+<label>Path filter <input id="path" type="search" placeholder="substring of a file path"></label>
+<label>Min lines <input id="lines" type="range" min="0" value="0"> <span id="lines_v">0</span></label>
+<label>Min copies <input id="copies" type="range" min="2" value="2"> <span id="copies_v">2</span></label>
+<span id="count"></span>
+</div>
+<table>
+<thead><tr>
+<th data-col="key">Hash signature</th>
+<th data-col="num_lines">Lines</th>
+<th data-col="copies">Copies</th>
+<th data-col="first">Locations</th>
+</tr></thead>
+<tbody id="rows"></tbody>
+</table>
+<script id="data" type="application/json">
+"#;
+
+static FOOTER: &str = r#"
+</script>
+<script>
+(function () {
+  var data = JSON.parse(document.getElementById("data").textContent);
+  var rows = data.map(function (d) {
+    return {
+      key: d.key,
+      num_lines: d.num_lines,
+      copies: d.files.length,
+      first: d.files.length ? d.files[0][0] : "",
+      files: d.files
+    };
+  });
+  var sortCol = "num_lines";
+  var sortDesc = true;
+  var path = document.getElementById("path");
+  var lines = document.getElementById("lines");
+  var copies = document.getElementById("copies");
+  var maxLines = 0, maxCopies = 2;
+  rows.forEach(function (r) {
+    maxLines = Math.max(maxLines, r.num_lines);
+    maxCopies = Math.max(maxCopies, r.copies);
+  });
+  lines.max = maxLines;
+  copies.max = maxCopies;
+
+  function render() {
+    var needle = path.value.toLowerCase();
+    var minLines = parseInt(lines.value, 10);
+    var minCopies = parseInt(copies.value, 10);
+    document.getElementById("lines_v").textContent = minLines;
+    document.getElementById("copies_v").textContent = minCopies;
+
+    var shown = rows.filter(function (r) {
+      return r.num_lines >= minLines && r.copies >= minCopies &&
+        (needle === "" || r.files.some(function (f) {
+          return f[0].toLowerCase().indexOf(needle) !== -1;
+        }));
+    });
+    shown.sort(function (a, b) {
+      var l = a[sortCol], r = b[sortCol];
+      // Keys are u64 values carried as strings, a longer string is a bigger number.
+      if (sortCol === "key" && l.length !== r.length) {
+        l = l.length;
+        r = r.length;
+      }
+      var c = l < r ? -1 : (l > r ? 1 : 0);
+      return sortDesc ? -c : c;
+    });
+
+    var body = document.getElementById("rows");
+    while (body.firstChild) {
+      body.removeChild(body.firstChild);
+    }
+    shown.forEach(function (r) {
+      var tr = document.createElement("tr");
+      [r.key, r.num_lines, r.copies].forEach(function (v) {
+        var td = document.createElement("td");
+        td.textContent = v;
+        tr.appendChild(td);
+      });
+      var loc = document.createElement("td");
+      loc.className = "loc";
+      loc.textContent = r.files.map(function (f) {
+        return f[0] + ":" + (f[1] + 1) + "-" + f[2];
+      }).join("\n");
+      tr.appendChild(loc);
+      body.appendChild(tr);
+    });
+    document.getElementById("count").textContent =
+      shown.length + " of " + rows.length + " duplicates shown";
+  }
+
+  Array.prototype.forEach.call(document.querySelectorAll("th"), function (th) {
+    th.addEventListener("click", function () {
+      var col = th.getAttribute("data-col");
+      sortDesc = (col === sortCol) ? !sortDesc : true;
+      sortCol = col;
+      render();
+    });
+  });
+  [path, lines, copies].forEach(function (e) {
+    e.addEventListener("input", render);
+  });
+  render();
+})();
+</script>
+</body>
+</html>
+"#;
+
+/// Write the collisions out as a single HTML file.  The caller must not be holding the lock on
+/// FILE_LOOKUP.
+pub fn write_report(file_name: &str, collisions: &[&Collision]) -> std::io::Result<()> {
+    let rows: Vec<Row> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        collisions
+            .iter()
+            .map(|c| Row {
+                key: c.key.to_string(),
+                num_lines: c.num_lines,
+                files: c
+                    .files
+                    .iter()
+                    .map(|f| {
+                        let (start, end) = file_lookup_locked.line_range(f.0, f.1, c.num_lines);
+                        (file_lookup_locked.id_to_name(f.0).to_string(), start, end)
+                    })
+                    .collect(),
+            })
+            .collect()
+    };
+    let data = serde_json::to_string(&rows)?;
+    let mut out = BufWriter::new(File::create(file_name)?);
+
+    out.write_all(HEADER.as_bytes())?;
+    // A "</script>" sequence inside a file name would terminate the data block early.
+    out.write_all(data.replace("</", "<\\/").as_bytes())?;
+    out.write_all(FOOTER.as_bytes())?;
+    out.flush()
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Just enough of an HTTP client to fetch an ignore file from a URL, `-i http://...`, without
+//! pulling in a dependency.  The request is HTTP/1.0 so the server sends the body as is, without
+//! chunked encoding, and closes the connection when it's done.  Only http is supported, there's
+//! no TLS, and redirects aren't followed.
+
+use std::io::{prelude::*, BufReader};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// How long to wait to connect and for each read.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The host, port and path of an http URL.
+fn parse_url(url: &str) -> Result<(&str, u16, &str), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| "only http:// URLs are supported".to_string())?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port \"{}\"", port))?,
+        ),
+        _ => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err("no host".to_string());
+    }
+    Ok((host, port, path))
+}
+
+/// GET the URL, the body if the response is 200 OK.
+pub fn get(url: &str) -> Result<Vec<u8>, String> {
+    let (host, port, path) = parse_url(url)?;
+
+    let addr = (host.trim_start_matches('[').trim_end_matches(']'), port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: duplihere/{}\r\n\r\n",
+        path,
+        host,
+        env!("CARGO_PKG_VERSION")
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status = String::new();
+    reader.read_line(&mut status).map_err(|e| e.to_string())?;
+    let code = status.split_whitespace().nth(1).unwrap_or("");
+    if code != "200" {
+        return Err(format!("server responded \"{}\"", status.trim_end()));
+    }
+
+    // Skip the headers
+    loop {
+        let mut header = String::new();
+        let n = reader.read_line(&mut header).map_err(|e| e.to_string())?;
+        if n == 0 || header == "\r\n" || header == "\n" {
+            break;
+        }
+    }
+
+    let mut body = vec![];
+    reader.read_to_end(&mut body).map_err(|e| e.to_string())?;
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_parts() {
+        assert_eq!(
+            parse_url("http://example.com/a/b.txt"),
+            Ok(("example.com", 80, "/a/b.txt"))
+        );
+        assert_eq!(
+            parse_url("http://example.com:8080"),
+            Ok(("example.com", 8080, "/"))
+        );
+        assert_eq!(parse_url("http://[::1]:81/x"), Ok(("[::1]", 81, "/x")));
+        assert_eq!(parse_url("http://[::1]/x"), Ok(("[::1]", 80, "/x")));
+    }
+
+    #[test]
+    fn bad_urls() {
+        assert!(parse_url("https://example.com/").is_err());
+        assert!(parse_url("http:///x").is_err());
+        assert!(parse_url("http://example.com:http/").is_err());
+    }
+}
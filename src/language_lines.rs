@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The --lines for the files of each language with --per-language-thresholds.  A line of Python
+//! says more than a line of Java, which spreads the same logic over more lines of braces,
+//! declarations and boilerplate, so one threshold is either too noisy for one or misses copies
+//! in the other.  The language is told by the file's extension, files of languages the table
+//! doesn't have use --lines.  The built-in lines are defaults, so --lines given on the command
+//! line takes precedence over them, only --language-lines takes precedence over it.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Language, its extensions and its --lines.
+static LANGUAGE_LINES: [(&str, &[&str], u32); 17] = [
+    ("c", &["c", "h"], 6),
+    (
+        "cpp",
+        &["cc", "cpp", "cxx", "c++", "hh", "hpp", "hxx", "h++"],
+        6,
+    ),
+    ("csharp", &["cs"], 10),
+    ("go", &["go"], 8),
+    ("html", &["html", "htm"], 12),
+    ("java", &["java"], 10),
+    ("javascript", &["js", "jsx", "mjs", "cjs"], 6),
+    ("kotlin", &["kt", "kts"], 8),
+    ("perl", &["pl", "pm"], 4),
+    ("python", &["py"], 4),
+    ("ruby", &["rb"], 4),
+    ("rust", &["rs"], 6),
+    ("shell", &["sh", "bash", "zsh"], 4),
+    ("sql", &["sql"], 6),
+    ("swift", &["swift"], 6),
+    ("typescript", &["ts", "tsx"], 6),
+    ("xml", &["xml"], 12),
+];
+
+/// The table with the --language-lines overrides, each language=lines, applied.  Without
+/// built_in only the languages of the overrides are in it.
+pub fn table(overrides: &[String], built_in: bool) -> Result<HashMap<&'static str, u32>, String> {
+    let mut rc: HashMap<&'static str, u32> = LANGUAGE_LINES
+        .iter()
+        .filter(|_| built_in)
+        .map(|(language, _, lines)| (*language, *lines))
+        .collect();
+    for o in overrides {
+        let (language, lines) = o
+            .split_once('=')
+            .ok_or_else(|| format!("--language-lines \"{}\" must be language=lines", o))?;
+        let language = LANGUAGE_LINES
+            .iter()
+            .map(|l| l.0)
+            .find(|l| *l == language.trim())
+            .ok_or_else(|| {
+                format!(
+                    "--language-lines \"{}\", {} isn't a language of {}",
+                    o,
+                    language.trim(),
+                    names()
+                )
+            })?;
+        match lines.trim().parse::<u32>() {
+            Ok(n) if n > 0 => rc.insert(language, n),
+            _ => {
+                return Err(format!(
+                    "--language-lines \"{}\", lines must be a number of at least 1",
+                    o
+                ))
+            }
+        };
+    }
+    Ok(rc)
+}
+
+/// The --lines of a file by its language, None if the table doesn't have it.
+pub fn lines_of(file: &str, table: &HashMap<&'static str, u32>) -> Option<u32> {
+    let ext = Path::new(file)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    LANGUAGE_LINES
+        .iter()
+        .find(|l| l.1.contains(&ext.as_str()))
+        .and_then(|l| table.get(l.0).copied())
+}
+
+/// The languages of the table, as listed in errors.
+pub fn names() -> String {
+    LANGUAGE_LINES
+        .iter()
+        .map(|l| l.0)
+        .collect::<Vec<&str>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn by_extension() {
+        let t = table(&[], true).unwrap();
+        assert_eq!(lines_of("a/b.py", &t), Some(4));
+        assert_eq!(lines_of("B.JAVA", &t), Some(10));
+        assert_eq!(lines_of("x.unknown", &t), None);
+        assert_eq!(lines_of("Makefile", &t), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let t = table(&["java=12".to_string(), " python = 5 ".to_string()], true).unwrap();
+        assert_eq!(lines_of("a.java", &t), Some(12));
+        assert_eq!(lines_of("a.py", &t), Some(5));
+        assert_eq!(lines_of("a.go", &t), Some(8));
+    }
+
+    #[test]
+    fn overrides_only() {
+        let t = table(&["java=12".to_string()], false).unwrap();
+        assert_eq!(lines_of("a.java", &t), Some(12));
+        assert_eq!(lines_of("a.py", &t), None);
+    }
+
+    #[test]
+    fn bad_overrides() {
+        for o in ["java", "cobol=5", "java=0", "java=many"] {
+            assert!(table(&[o.to_string()], true).is_err(), "{}", o);
+        }
+    }
+}
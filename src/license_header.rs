@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The comment block at the top of a file, --strip-license-header.  Most files of a project
+//! start with the same license header, which would otherwise be reported as a duplicate across
+//! all of them.  The header is the first comment block, from the top of the file up to the first
+//! blank line after a comment, or the first line which is neither, or at most
+//! --license-header-lines lines.  A doc comment after the blank line which ends the license is
+//! kept.
+//!
+//! The comment syntax is told by the file's extension.  A line comment counts when the line
+//! starts with it and a block comment runs from a line starting with its opening to the line
+//! which closes it, whatever else is on that line.  A "#!" first line is part of the header in
+//! every language.  Files of languages the table doesn't have keep their header.
+
+use std::path::Path;
+
+/// Line comment starts and block comment start and end of each set of extensions.
+type Syntax = (
+    &'static [&'static str],
+    &'static [&'static str],
+    Option<(&'static str, &'static str)>,
+);
+
+static COMMENT_SYNTAX: [Syntax; 5] = [
+    (
+        &[
+            "c", "h", "cc", "cpp", "cxx", "c++", "hh", "hpp", "hxx", "h++", "cs", "go", "java",
+            "js", "jsx", "mjs", "cjs", "kt", "kts", "rs", "scala", "swift", "ts", "tsx",
+        ],
+        &["//"],
+        Some(("/*", "*/")),
+    ),
+    (
+        &[
+            "py", "sh", "bash", "zsh", "pl", "pm", "rb", "r", "cmake", "toml", "yaml", "yml",
+        ],
+        &["#"],
+        None,
+    ),
+    (&["sql"], &["--"], Some(("/*", "*/"))),
+    (&["lua", "hs"], &["--"], None),
+    (&["html", "htm", "xml"], &[], Some(("<!--", "-->"))),
+];
+
+/// Follows the lines at the top of a file to tell which are the license header.
+pub struct LicenseHeader {
+    syntax: &'static Syntax,
+    // At most this many lines, 0 is no limit.
+    max_lines: u64,
+    lines: u64,
+    // The end of the block comment we're in.
+    block_end: Option<&'static str>,
+    // A comment has been seen, the next blank line ends the header.
+    commented: bool,
+    done: bool,
+}
+
+impl LicenseHeader {
+    /// None if the language of the file isn't known.
+    pub fn new(file: &str, max_lines: u64) -> Option<LicenseHeader> {
+        let ext = Path::new(file)
+            .extension()?
+            .to_string_lossy()
+            .to_lowercase();
+        let syntax = COMMENT_SYNTAX
+            .iter()
+            .find(|s| s.0.contains(&ext.as_str()))?;
+        Some(LicenseHeader {
+            syntax,
+            max_lines,
+            lines: 0,
+            block_end: None,
+            commented: false,
+            done: false,
+        })
+    }
+
+    /// Whether the next line of the file is part of the header, once a line isn't the rest aren't.
+    pub fn is_header(&mut self, line: &str) -> bool {
+        if self.done || (self.max_lines != 0 && self.lines >= self.max_lines) {
+            self.done = true;
+            return false;
+        }
+        let first = self.lines == 0;
+        self.lines += 1;
+
+        let l = line.trim();
+        if let Some(end) = self.block_end {
+            if l.contains(end) {
+                self.block_end = None;
+            }
+            return true;
+        }
+        if l.is_empty() {
+            // The blank line is the last of the header.
+            self.done = self.commented;
+            return true;
+        }
+        if first && l.starts_with("#!") {
+            return true;
+        }
+        if self.syntax.1.iter().any(|c| l.starts_with(c)) {
+            self.commented = true;
+            return true;
+        }
+        if let Some((start, end)) = self.syntax.2 {
+            if let Some(rest) = l.strip_prefix(start) {
+                if !rest.contains(end) {
+                    self.block_end = Some(end);
+                }
+                self.commented = true;
+                return true;
+            }
+        }
+
+        self.done = true;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many of the lines are the header.
+    fn header(file: &str, max_lines: u64, text: &str) -> usize {
+        let mut h = LicenseHeader::new(file, max_lines).unwrap();
+        text.lines().take_while(|l| h.is_header(l)).count()
+    }
+
+    #[test]
+    fn languages() {
+        assert!(LicenseHeader::new("a.RS", 0).is_some());
+        assert!(LicenseHeader::new("Makefile", 0).is_none());
+        assert!(LicenseHeader::new("a.txt", 0).is_none());
+    }
+
+    #[test]
+    fn stops_at_the_blank_line_after_the_license() {
+        let text = "// SPDX-License-Identifier: MIT\n//\n// Copyright\n\n//! The module.\nuse x;\n";
+        assert_eq!(header("a.rs", 0, text), 4);
+        assert_eq!(header("a.rs", 2, text), 2);
+        // Without the blank line the doc comment looks like more of the license.
+        assert_eq!(header("a.rs", 0, "// License\n/// Doc\nfn f() {}\n"), 2);
+    }
+
+    #[test]
+    fn block_comments_and_shebangs() {
+        let text = "\n/*\n * License\n\n * more\n */\n\nint x;\n";
+        assert_eq!(header("a.c", 0, text), 7);
+        assert_eq!(header("a.c", 0, "/* License */\nint x;\n"), 1);
+
+        let text = "#!/bin/sh\n\n# License\n# more\n\n# What it does\necho\n";
+        assert_eq!(header("a.sh", 0, text), 5);
+        // Only a first line is a shebang.
+        assert_eq!(header("a.c", 0, "int x;\n#!/bin/sh\n"), 0);
+        assert_eq!(header("a.html", 0, "<!-- License\n-->\n<html>\n"), 2);
+    }
+}
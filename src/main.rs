@@ -6,67 +6,627 @@ extern crate lazy_static;
 
 extern crate dashmap;
 extern crate rags_rs as rags;
-use glob::glob;
+
+mod checkpoint;
+mod dir_config;
+mod dot;
+mod extract;
+mod functions;
+mod html;
+#[cfg(feature = "http")]
+mod http;
+mod language_lines;
+mod license_header;
+#[cfg(unix)]
+mod mmap;
+mod path_ignore;
+mod progress;
+mod reorder;
+mod single_file;
+mod siphash;
+mod stats;
+#[cfg(unix)]
+mod syslog;
+mod trend;
+#[cfg(feature = "tui")]
+mod tui;
+mod walk;
+
+use glob::{glob, Pattern};
 use rags::argparse;
 use rayon::prelude::*;
 
 use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 
-use std::collections::{hash_map::DefaultHasher, HashMap, VecDeque};
+use std::borrow::Cow;
+use std::collections::{hash_map::Entry, BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::{canonicalize, File};
 use std::hash::{Hash, Hasher};
-use std::io::{prelude::*, BufReader};
+use std::io::{self, prelude::*, BufReader, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
+use checkpoint::{Checkpoint, Record};
 use dashmap::DashMap;
+use dir_config::{DirConfigs, Settings};
+use functions::{Function, Language, Scanner};
+use license_header::LicenseHeader;
+use path_ignore::PathIgnore;
+use progress::Progress;
+use siphash::SipHasher13;
+use stats::PhaseStats;
+#[cfg(feature = "unicode")]
+use unicode_normalization::UnicodeNormalization;
 
 lazy_static! {
     static ref FILE_LOOKUP: Mutex<FileId> = Mutex::new(FileId::new());
+    // Content which doesn't come from a file we can re-open, eg. stdin, by the name it's
+    // registered under.
+    static ref VIRTUAL_FILES: Mutex<HashMap<String, Arc<[u8]>>> = Mutex::new(HashMap::new());
+    // Problems we carried on past, for the JSON report.
+    static ref WARNINGS: Mutex<Vec<Warning>> = Mutex::new(vec![]);
+}
+
+/// What kind of problem a warning is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum WarningKind {
+    UnreadableFile,
+    InvalidUtf8,
+    InvalidIgnoreEntry,
+    FileLimit,
+    LocationLimit,
+    NonUtf8Name,
+    PairLimit,
+}
+
+/// A problem we carried on past, eg. a file we couldn't read, which is included in the JSON
+/// report so consumers don't have to scrape stderr.
+#[derive(Debug, Clone, Serialize)]
+struct Warning {
+    kind: WarningKind,
+    message: String,
+}
+
+/// Record a warning for the report, the caller is responsible for any message on stderr.  A
+/// warning which is already recorded, eg. by the first scan of --compare-normalized, isn't
+/// recorded again and false is returned, so the message isn't repeated either.
+fn record_warning(kind: WarningKind, message: String) -> bool {
+    let mut warnings = WARNINGS.lock().unwrap();
+    if warnings
+        .iter()
+        .any(|w| w.kind == kind && w.message == message)
+    {
+        return false;
+    }
+    warnings.push(Warning { kind, message });
+    true
+}
+
+/// Set by --json-errors.
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
+
+/// Report an error we can't carry on past and exit with code.  With --json-errors it's a line of
+/// JSON on stderr, {"error": message, "exit_code": code}, rather than "ERROR: message".
+fn fatal(code: i32, message: impl fmt::Display) -> ! {
+    if JSON_ERRORS.load(Ordering::Relaxed) {
+        eprintln!(
+            "{}",
+            serde_json::json!({"error": message.to_string(), "exit_code": code})
+        );
+    } else {
+        eprintln!("ERROR: {}", message);
+    }
+    process::exit(code);
+}
+
+/// Open a file for reading a line at a time with a read buffer of buffer_size bytes, virtual
+/// files are read from memory.
+fn open_text(filename: &str, buffer_size: usize) -> io::Result<Box<dyn BufRead>> {
+    if let Some(content) = VIRTUAL_FILES.lock().unwrap().get(filename) {
+        return Ok(Box::new(io::Cursor::new(content.clone())));
+    }
+    Ok(Box::new(BufReader::with_capacity(
+        buffer_size,
+        File::open(filename)?,
+    )))
+}
+
+/// The read buffer size when --read-buffer-size isn't given, the same as BufReader::new.
+const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The read buffer sizes we accept, anything larger only wastes memory across the IO threads.
+const READ_BUFFER_SIZES: std::ops::RangeInclusive<usize> = 512..=64 * 1024 * 1024;
+
+/// Size of a file in bytes, 0 if we can't tell.
+fn text_size(filename: &str) -> u64 {
+    if let Some(content) = VIRTUAL_FILES.lock().unwrap().get(filename) {
+        return content.len() as u64;
+    }
+    std::fs::metadata(filename).map(|m| m.len()).unwrap_or(0)
+}
+
+/// The --hash-seed, both keys of the SipHash-1-3 the hashes are made with.
+static HASH_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// A hasher for the line signatures, windows and hash signatures, seeded with --hash-seed.
+fn new_hasher() -> SipHasher13 {
+    let seed = HASH_SEED.load(Ordering::Relaxed);
+    SipHasher13::new_with_keys(seed, seed)
 }
 
 /// Generates the hash for 'T' which in this case is a utf-8 string.
 fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
+    let mut s = new_hasher();
     t.hash(&mut s);
     s.finish()
 }
 
+/// The signature of a line of text, normalized by normalize_line.  With --alpha-rename the
+/// identifiers are left out of it, see split_identifiers.
+fn line_signature(line: &str, opts: &Options) -> u64 {
+    let text = normalize_line(line, opts);
+    if opts.alpha_rename {
+        calculate_hash(&split_identifiers(&text).0.as_str())
+    } else {
+        calculate_hash(&text.as_ref())
+    }
+}
+
+/// Leading and trailing whitespace is removed, with --normalize collapse each run of whitespace
+/// within the line counts as a single space and with --ignore-case the line is lower cased.
+/// Whitespace is anything Unicode considers whitespace, eg. a non-breaking space, and lower
+/// casing is by the Unicode rules too.  With --normalize-unicode the line is first put in that
+/// normalization form.
+fn normalize_line<'a>(line: &'a str, opts: &Options) -> Cow<'a, str> {
+    let mut text = match opts.normalize_unicode {
+        UnicodeForm::None => Cow::Borrowed(line.trim()),
+        #[cfg(feature = "unicode")]
+        UnicodeForm::Nfc => Cow::Owned(line.trim().nfc().collect()),
+        #[cfg(feature = "unicode")]
+        UnicodeForm::Nfd => Cow::Owned(line.trim().nfd().collect()),
+        // UnicodeForm::from_str refuses them without the unicode feature.
+        #[cfg(not(feature = "unicode"))]
+        UnicodeForm::Nfc | UnicodeForm::Nfd => unreachable!(),
+    };
+    if opts.normalize == Normalize::Collapse {
+        text = Cow::Owned(text.split_whitespace().collect::<Vec<&str>>().join(" "));
+    }
+    if opts.ignore_case {
+        text = Cow::Owned(text.to_lowercase());
+    }
+    text
+}
+
+/// The keywords of the common languages, sorted for binary_search.  --alpha-rename keeps them in
+/// the text so copies where only they changed, eg. if became while, don't match.
+const KEYWORDS: [&str; 105] = [
+    "False",
+    "None",
+    "True",
+    "abstract",
+    "and",
+    "as",
+    "async",
+    "auto",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "continue",
+    "def",
+    "default",
+    "defer",
+    "delete",
+    "do",
+    "double",
+    "elif",
+    "else",
+    "enum",
+    "except",
+    "explicit",
+    "export",
+    "extends",
+    "extern",
+    "false",
+    "final",
+    "finally",
+    "float",
+    "fn",
+    "for",
+    "foreach",
+    "func",
+    "function",
+    "go",
+    "goto",
+    "if",
+    "impl",
+    "implements",
+    "import",
+    "in",
+    "inline",
+    "instanceof",
+    "int",
+    "interface",
+    "let",
+    "lock",
+    "long",
+    "loop",
+    "match",
+    "mod",
+    "move",
+    "mut",
+    "namespace",
+    "new",
+    "nil",
+    "not",
+    "null",
+    "operator",
+    "or",
+    "package",
+    "private",
+    "protected",
+    "pub",
+    "public",
+    "raise",
+    "range",
+    "ref",
+    "return",
+    "self",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "super",
+    "switch",
+    "synchronized",
+    "template",
+    "this",
+    "throw",
+    "throws",
+    "true",
+    "try",
+    "type",
+    "typedef",
+    "typeof",
+    "union",
+    "unsafe",
+    "unsigned",
+    "use",
+    "using",
+    "var",
+    "virtual",
+    "void",
+    "volatile",
+    "where",
+    "while",
+    "with",
+    "yield",
+];
+
+/// For --alpha-rename, the text with each identifier replaced by a NUL and the hashes of the
+/// identifiers in the order they appear.  An identifier is a word of letters, digits and '_'
+/// which doesn't start with a digit and isn't one of the KEYWORDS.
+fn split_identifiers(text: &str) -> (String, Vec<u64>) {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut blinded = String::with_capacity(text.len());
+    let mut identifiers = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if !is_word(c) {
+            blinded.push(c);
+            continue;
+        }
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, n)) = chars.peek() {
+            if !is_word(n) {
+                break;
+            }
+            end = i + n.len_utf8();
+            chars.next();
+        }
+        let word = &text[start..end];
+        if c.is_numeric() || KEYWORDS.binary_search(&word).is_ok() {
+            blinded.push_str(word);
+        } else {
+            blinded.push('\0');
+            identifiers.push(calculate_hash(&word));
+        }
+    }
+    (blinded, identifiers)
+}
+
+/// The identifiers on each line of a file for --alpha-rename, their hashes in the order they
+/// appear.  Lines are numbered as the signatures, so lines without one are left out.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Identifiers {
+    hashes: Vec<u64>,
+    ends: Vec<u32>,
+}
+
+impl Identifiers {
+    fn push_line(&mut self, identifiers: Vec<u64>) {
+        self.hashes.extend(identifiers);
+        self.ends.push(self.hashes.len() as u32);
+    }
+
+    fn line(&self, index: usize) -> &[u64] {
+        let start = if index == 0 { 0 } else { self.ends[index - 1] };
+        &self.hashes[start as usize..self.ends[index] as usize]
+    }
+}
+
+/// For --alpha-rename, how many of the first num_lines lines of two regions, whose signatures
+/// match, still match when the identifiers of one are renamed to those of the other.  The
+/// renaming has to be consistent and one to one over the whole region, eg. 'a' can't become 'x'
+/// on one line and 'y' on the next, and 'a' and 'b' can't both become 'x'.
+fn renamed_match_len(
+    left: (&Identifiers, usize),
+    right: (&Identifiers, usize),
+    num_lines: u32,
+) -> u32 {
+    let mut forward: HashMap<u64, u64> = HashMap::new();
+    let mut backward: HashMap<u64, u64> = HashMap::new();
+
+    for n in 0..num_lines {
+        let l_ids = left.0.line(left.1 + n as usize);
+        let r_ids = right.0.line(right.1 + n as usize);
+        if l_ids.len() != r_ids.len() {
+            return n;
+        }
+        for (l, r) in l_ids.iter().zip(r_ids) {
+            if *forward.entry(*l).or_insert(*r) != *r || *backward.entry(*r).or_insert(*l) != *l {
+                return n;
+            }
+        }
+    }
+    num_lines
+}
+
+/// The hash signature of a duplicate covering its text and where each copy is, for
+/// --fingerprint-mode position.  We use the file names and line numbers rather than the
+/// position based signature, file ids depend on the order the files were found in.
+fn position_key(c: &Collision, file_lookup: &FileId) -> u64 {
+    let mut s = new_hasher();
+    c.key.hash(&mut s);
+    for f in &c.files {
+        file_lookup.id_to_name(f.0).hash(&mut s);
+        file_lookup.line_range(f.0, f.1, c.num_lines).0.hash(&mut s);
+    }
+    s.finish()
+}
+
+/// Mix the --salt into a hash signature, so signatures can be shared without the content of the
+/// duplicate being recognizable from them.
+fn salted_key(key: u64, salt: &str) -> u64 {
+    calculate_hash(&(salt, key))
+}
+
+/// The line signatures of a file.  Lines can be left out, eg. ones with the skip directive, in
+/// which case line_map records the line number in the file for each signature.
+#[derive(Serialize, Deserialize)]
+struct Signatures {
+    hashes: Vec<u64>,
+    line_map: Option<Vec<u32>>,
+    identifiers: Option<Identifiers>,
+    functions: Vec<Function>,
+    boundaries: Vec<u32>,
+}
+
+/// Extensions of the C family files whose preprocessor directives --treat-includes-as-boundaries
+/// applies to.
+static C_FAMILY_EXTENSIONS: [&str; 14] = [
+    "c", "h", "cc", "cpp", "cxx", "c++", "hh", "hpp", "hxx", "h++", "inl", "ipp", "m", "mm",
+];
+
+fn is_c_family(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .map(|e| {
+            C_FAMILY_EXTENSIONS
+                .iter()
+                .any(|c| e.eq_ignore_ascii_case(c))
+        })
+        .unwrap_or(false)
+}
+
+/// Builds the signatures of a file a line at a time, whichever way the file is read.
+struct SignatureBuilder<'a> {
+    filename: &'a str,
+    opts: &'a Options,
+    hashes: Vec<u64>,
+    line_map: Vec<u32>,
+    line_number: u32,
+    invalid_utf8: bool,
+    identifiers: Identifiers,
+    // With --whole-functions
+    scanner: Option<Scanner>,
+    // With --treat-includes-as-boundaries in a C family file, preprocessor directives end a
+    // duplicate.
+    directives: bool,
+    // With it or --blank-line-boundary, the indexes of the lines which end a duplicate.
+    boundaries: Option<Vec<u32>>,
+    // With --strip-license-header, when the file's comment syntax is known.
+    license: Option<LicenseHeader>,
+}
+
+impl<'a> SignatureBuilder<'a> {
+    fn new(filename: &'a str, opts: &'a Options) -> SignatureBuilder<'a> {
+        SignatureBuilder {
+            filename,
+            opts,
+            hashes: Vec::new(),
+            line_map: Vec::new(),
+            line_number: 0,
+            invalid_utf8: false,
+            identifiers: Identifiers::default(),
+            scanner: (opts.whole_functions != Language::None)
+                .then(|| Scanner::new(opts.whole_functions)),
+            directives: opts.treat_includes_as_boundaries && is_c_family(filename),
+            boundaries: ((opts.treat_includes_as_boundaries && is_c_family(filename))
+                || opts.blank_line_boundary)
+                .then(Vec::new),
+            license: opts
+                .strip_license_header
+                .then(|| LicenseHeader::new(filename, opts.license_header_lines))
+                .flatten(),
+        }
+    }
+
+    /// When --first-n-lines is in use we stop reading once we have that many lines.
+    fn full(&self) -> bool {
+        self.opts.first_n_lines != 0 && self.line_number as u64 >= self.opts.first_n_lines
+    }
+
+    /// Add a line, with or without its record separator.
+    fn add_line(&mut self, mut line: &[u8]) {
+        // trim() takes care of '\n' and '\r', but not a separator like NUL.
+        if line.last() == Some(&self.opts.record_separator.0) {
+            line = &line[..line.len() - 1];
+        }
+        let l = String::from_utf8_lossy(line);
+        if !self.invalid_utf8 && std::str::from_utf8(line).is_err() {
+            self.invalid_utf8 = true;
+            record_warning(
+                WarningKind::InvalidUtf8,
+                format!(
+                    "{} line {} isn't valid UTF-8, invalid sequences were replaced",
+                    self.filename,
+                    self.line_number + 1
+                ),
+            );
+        }
+        let index = self.hashes.len() as u32;
+        // The license header is looked for after the --ignore-first-line lines.
+        let header = (self.line_number as u64) < self.opts.ignore_first_line
+            || self
+                .license
+                .as_mut()
+                .map(|h| h.is_header(&l))
+                .unwrap_or(false);
+        if !header
+            && (self.opts.skip_directive.is_empty() || !l.contains(&self.opts.skip_directive))
+        {
+            let hash = line_signature(&l, self.opts);
+            if !self.opts.boilerplate_hashes.contains(&hash) {
+                if let Some(boundaries) = &mut self.boundaries {
+                    if (self.directives && l.trim_start().starts_with('#'))
+                        || (self.opts.blank_line_boundary && l.trim().is_empty())
+                    {
+                        boundaries.push(self.hashes.len() as u32);
+                    }
+                }
+                self.hashes.push(hash);
+                self.line_map.push(self.line_number);
+                if self.opts.alpha_rename {
+                    let text = normalize_line(&l, self.opts);
+                    self.identifiers.push_line(split_identifiers(&text).1);
+                }
+            }
+        }
+        if let Some(scanner) = &mut self.scanner {
+            scanner.line(&l, index, self.hashes.len() as u32);
+        }
+        self.line_number += 1;
+    }
+
+    fn finish(self) -> Signatures {
+        let line_map = if self.line_map.len() as u32 == self.line_number {
+            None
+        } else {
+            Some(self.line_map)
+        };
+
+        Signatures {
+            hashes: self.hashes,
+            line_map,
+            identifiers: self.opts.alpha_rename.then_some(self.identifiers),
+            functions: self.scanner.map(|s| s.finish()).unwrap_or_default(),
+            boundaries: self.boundaries.unwrap_or_default(),
+        }
+    }
+}
+
+/// Files smaller than this are read rather than memory mapped with --mmap, setting up the
+/// mapping costs more than it saves.
+#[cfg(unix)]
+const MMAP_MIN_BYTES: u64 = 64 * 1024;
+
+/// Memory map the file for --mmap, None when it's small, virtual or can't be mapped, in which
+/// case it's read as usual.
+#[cfg(unix)]
+fn map_text(filename: &str, opts: &Options) -> Option<mmap::Mmap> {
+    if !opts.mmap || VIRTUAL_FILES.lock().unwrap().contains_key(filename) {
+        return None;
+    }
+    let file = File::open(filename).ok()?;
+    if file.metadata().ok()?.len() < MMAP_MIN_BYTES {
+        return None;
+    }
+    mmap::Mmap::map(&file).ok()
+}
+
+#[cfg(not(unix))]
+fn map_text(_filename: &str, _opts: &Options) -> Option<Vec<u8>> {
+    None
+}
+
 /// For a given file, walk it line by line calculating, removing leading and trailing WS and
 /// calculating the signatures for each line, return the information as a vector of hash signatures.
-fn file_signatures(filename: &str) -> Vec<u64> {
-    let mut rc: Vec<u64> = Vec::new();
-
-    match File::open(filename) {
-        Ok(file) => {
-            let mut reader = BufReader::new(file);
-
-            loop {
-                let mut buf: Vec<u8> = vec![];
-                match reader.read_until(0xA, &mut buf) {
-                    Ok(num_bytes) => {
-                        if num_bytes == 0 {
-                            return rc;
-                        } else {
-                            let l = String::from_utf8_lossy(&buf);
-                            rc.push(calculate_hash(&l.trim()));
-                            buf.truncate(0);
-                        }
-                    }
+/// Lines which contain the skip directive, are --boilerplate, are within --ignore-first-line or
+/// are the --strip-license-header are left out.
+fn file_signatures(filename: &str, opts: &Options) -> Signatures {
+    let mut builder = SignatureBuilder::new(filename, opts);
+
+    if let Some(mapped) = map_text(filename, opts) {
+        for line in mapped.split_inclusive(|b| *b == opts.record_separator.0) {
+            if builder.full() {
+                break;
+            }
+            builder.add_line(line);
+        }
+        return builder.finish();
+    }
+
+    match open_text(filename, opts.read_buffer_size) {
+        Ok(mut reader) => {
+            let mut buf: Vec<u8> = vec![];
+            while !builder.full() {
+                buf.truncate(0);
+                match reader.read_until(opts.record_separator.0, &mut buf) {
+                    Ok(0) => break,
+                    Ok(_) => builder.add_line(&buf),
                     Err(e) => {
-                        eprintln!("WARNING: Error processing file {} reason {}", filename, e);
-                        return rc;
+                        let msg = format!("Error processing file {} reason {}", filename, e);
+                        if record_warning(WarningKind::UnreadableFile, msg.clone()) {
+                            eprintln!("WARNING: {}", msg);
+                        }
+                        break;
                     }
                 }
             }
         }
         Err(e) => {
-            eprintln!("ERROR: Unable to open {}, reason {}", filename, e);
+            let msg = format!("Unable to open {}, reason {}", filename, e);
+            if record_warning(WarningKind::UnreadableFile, msg.clone()) {
+                eprintln!("ERROR: {}", msg);
+            }
         }
     }
 
-    rc
+    builder.finish()
 }
 
 /// For a specific file, calculate the hash signature for 'min_lines' in size using a sliding window
@@ -76,11 +636,14 @@ fn file_signatures(filename: &str) -> Vec<u64> {
 fn rolling_hashes(file_signatures: &[u64], min_lines: usize) -> Vec<(u64, u32)> {
     let mut rc = vec![];
 
-    if file_signatures.len() > min_lines {
-        let num_lines = file_signatures.len() - min_lines;
+    // A zero line window is meaningless, main rejects it, but don't loop over empty windows.  The
+    // last window ends on the last line, without it a file of exactly min_lines lines, eg. a
+    // --deny-list snippet, has none and a copy which ends at the end of both files isn't found.
+    if min_lines > 0 && file_signatures.len() >= min_lines {
+        let num_lines = file_signatures.len() - min_lines + 1;
         let mut prev_hash: u64 = 0;
         for i in 0..num_lines {
-            let mut s = DefaultHasher::new();
+            let mut s = new_hasher();
             for n in file_signatures.iter().skip(i).take(min_lines) {
                 n.hash(&mut s);
             }
@@ -96,17 +659,120 @@ fn rolling_hashes(file_signatures: &[u64], min_lines: usize) -> Vec<(u64, u32)>
     rc
 }
 
+/// Markers which identify a generated file, used with --ignore-generated when the user doesn't
+/// supply their own with --generated-marker.
+static GENERATED_MARKERS: [&str; 5] = [
+    "Code generated",
+    "DO NOT EDIT",
+    "@generated",
+    "auto-generated",
+    "autogenerated",
+];
+
+/// How many lines at the start of a file we look at for a generated file marker.
+const GENERATED_HEADER_LINES: usize = 10;
+
+/// Number of files skipped because they were identified as generated.
+static GENERATED_SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of lines scanned, those with a signature, for the --summary-json ratio.
+static LINES_SCANNED: AtomicU64 = AtomicU64::new(0);
+
+/// Check the first few lines of a file for a marker which indicates it was generated.
+fn is_generated(filename: &str, opts: &Options) -> bool {
+    let mut reader = match open_text(filename, opts.read_buffer_size) {
+        Ok(reader) => reader,
+        Err(_) => return false, // file_signatures will report the problem
+    };
+
+    for _ in 0..GENERATED_HEADER_LINES {
+        let mut buf: Vec<u8> = vec![];
+        match reader.read_until(opts.record_separator.0, &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let l = String::from_utf8_lossy(&buf);
+                let found = if opts.generated_markers.is_empty() {
+                    GENERATED_MARKERS.iter().any(|m| l.contains(m))
+                } else {
+                    opts.generated_markers
+                        .iter()
+                        .any(|m| l.contains(m.as_str()))
+                };
+                if found {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
 fn process_file(
     fid: u32,
     filename: &str,
-    min_lines: usize,
-    file_hashes: &Mutex<Vec<Vec<u64>>>,
+    opts: &Options,
+    file_hashes: &Mutex<SignatureArena>,
+    collision_hashes: &DashMap<u64, Vec<(u32, u32)>>,
+    checkpoint: Option<&Checkpoint>,
+) {
+    let file_signatures = if opts.ignore_generated && is_generated(filename, opts) {
+        GENERATED_SKIPPED.fetch_add(1, Ordering::Relaxed);
+        None
+    } else {
+        Some(file_signatures(filename, opts))
+    };
+
+    if let Some(checkpoint) = checkpoint {
+        let record = Record {
+            fid,
+            settings: settings_hash(opts),
+            signatures: file_signatures.as_ref(),
+        };
+        if let Err(e) = checkpoint.record(&record) {
+            fatal(1, format!("Unable to write the checkpoint, reason {}", e));
+        }
+    }
+
+    if let Some(file_signatures) = file_signatures {
+        add_signatures(fid, file_signatures, opts, file_hashes, collision_hashes);
+    }
+}
+
+/// Add the signatures of a file, read or resumed from a checkpoint, to those of the scan and
+/// register its windows in the collision hash.
+fn add_signatures(
+    fid: u32,
+    file_signatures: Signatures,
+    opts: &Options,
+    file_hashes: &Mutex<SignatureArena>,
     collision_hashes: &DashMap<u64, Vec<(u32, u32)>>,
 ) {
-    let file_signatures = file_signatures(filename);
-    let file_rolling_hashes = rolling_hashes(&file_signatures, min_lines);
+    LINES_SCANNED.fetch_add(file_signatures.hashes.len() as u64, Ordering::Relaxed);
+    let file_rolling_hashes = if opts.whole_functions != Language::None {
+        function_hashes(&file_signatures.hashes, &file_signatures.functions)
+    } else {
+        rolling_hashes(&file_signatures.hashes, opts.lines as usize)
+    };
 
-    file_hashes.lock().unwrap()[fid as usize] = file_signatures;
+    if let Some(line_map) = file_signatures.line_map {
+        FILE_LOOKUP.lock().unwrap().set_line_map(fid, line_map);
+    }
+    let digest = SignatureArena::digest(&file_signatures.hashes);
+    {
+        let mut file_hashes = file_hashes.lock().unwrap();
+        file_hashes.insert_digested(fid, file_signatures.hashes, digest);
+        if let Some(identifiers) = file_signatures.identifiers {
+            file_hashes.identifiers.insert(fid, identifiers);
+        }
+        if opts.whole_functions != Language::None {
+            file_hashes.functions.insert(fid, file_signatures.functions);
+        }
+        if !file_signatures.boundaries.is_empty() {
+            file_hashes
+                .boundaries
+                .insert(fid, file_signatures.boundaries);
+        }
+    }
 
     {
         for e in file_rolling_hashes {
@@ -122,16 +788,67 @@ fn process_file(
     }
 }
 
+/// With --whole-functions the hash of each function in a file and where it starts, in place of
+/// the rolling hashes.
+fn function_hashes(file_signatures: &[u64], functions: &[Function]) -> Vec<(u64, u32)> {
+    functions
+        .iter()
+        .map(|f| {
+            let mut s = new_hasher();
+            for n in &file_signatures[f.start as usize..(f.start + f.num_lines) as usize] {
+                n.hash(&mut s);
+            }
+            (s.finish(), f.start)
+        })
+        .collect()
+}
+
 /// Used to record a section of duplicated text.  We store the hash signature, how many lines
 /// match and a vector of file ids and the starting line in the file.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Collision {
     key: u64,
     num_lines: u32,
     files: Vec<(u32, u32)>,
     sig: u64,
+    whitespace_differs: Option<bool>,
+    // With --flag-trailing-ws, whether the copies only match once trailing whitespace is removed.
+    trailing_ws_differs: Option<bool>,
+    // With --flag-reindent, whether the copies only match once indentation is removed.
+    reindented: Option<bool>,
+    repeats: Vec<Repeat>,
+    // Short identifier, the position in the report starting at 1, 0 when it's ignored.
+    id: usize,
+    // With --whole-functions, the name of the function if we could tell.
+    function: Option<String>,
+    // With --detect-rotations, the copies have the same lines in different orders.
+    reordered: bool,
+    // With --report-relative-savings, the percentage of the duplicated lines of the report
+    // this duplicate accounts for.
+    savings: Option<f64>,
+    // With --location-hashes, the hash of the text of each copy, by the index in files.
+    location_hashes: Vec<u64>,
+}
+
+/// A run of copies in one file at a regular stride, eg. a block repeated back to back, which
+/// --collapse-consecutive reports once.  Indexes are into Collision::files, members are the
+/// copies after the first.
+#[derive(Debug, Clone)]
+struct Repeat {
+    first: usize,
+    members: Vec<usize>,
+    stride: u32,
+}
+
+impl Repeat {
+    fn times(&self) -> usize {
+        self.members.len() + 1
+    }
 }
 
+/// The fewest copies at a regular stride we collapse into a repeat.
+const MIN_REPEAT_RUN: usize = 3;
+
 /// Used to convert a collision in our results to JSON for it.
 impl Serialize for Collision {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -139,21 +856,88 @@ impl Serialize for Collision {
         S: Serializer,
     {
         let file_lookup_lock = FILE_LOOKUP.lock().unwrap();
-        let files_infos: Vec<(String, u32)> = self
-            .files
+        let location = |i: &(u32, u32)| {
+            (
+                file_lookup_lock.json_name(i.0),
+                file_lookup_lock.line_range(i.0, i.1, self.num_lines).0,
+            )
+        };
+        let shown = self.shown_locations();
+        let files_infos: Vec<(String, u32)> = shown
+            .iter()
+            .map(|(i, _)| location(&self.files[*i]))
+            .collect();
+        let location_hashes: Vec<u64> = if self.location_hashes.is_empty() {
+            vec![]
+        } else {
+            shown
+                .iter()
+                .map(|(i, _)| self.location_hashes[*i])
+                .collect()
+        };
+        let repeats: Vec<(String, u32, usize, u32)> = self
+            .repeats
             .iter()
-            .map(|i| (file_lookup_lock.id_to_name(i.0).to_string(), i.1))
+            .map(|r| {
+                let (name, line) = location(&self.files[r.first]);
+                (name, line, r.times(), r.stride)
+            })
             .collect();
 
-        let mut fid = serializer.serialize_struct("Collision", 3)?;
+        let mut fid = serializer.serialize_struct("Collision", 4)?;
+        fid.serialize_field("id", &self.id)?;
         fid.serialize_field("key", &self.key)?;
         fid.serialize_field("num_lines", &self.num_lines)?;
         fid.serialize_field("files", &files_infos)?;
+        if !location_hashes.is_empty() {
+            fid.serialize_field("location_hashes", &location_hashes)?;
+        }
+        if !repeats.is_empty() {
+            fid.serialize_field("repeats", &repeats)?;
+        }
+        if let Some(whitespace_differs) = self.whitespace_differs {
+            fid.serialize_field("whitespace_differs", &whitespace_differs)?;
+        }
+        if let Some(trailing_ws_differs) = self.trailing_ws_differs {
+            fid.serialize_field("trailing_ws_differs", &trailing_ws_differs)?;
+        }
+        if let Some(reindented) = self.reindented {
+            fid.serialize_field("reindented", &reindented)?;
+        }
+        if let Some(function) = &self.function {
+            fid.serialize_field("function", function)?;
+        }
+        if self.reordered {
+            fid.serialize_field("reordered", &self.reordered)?;
+        }
+        if let Some(savings) = self.savings {
+            fid.serialize_field("savings_percent", &savings)?;
+        }
         fid.end()
     }
 }
 
 impl Collision {
+    /// A duplicate of num_lines with copies at the file ids and starting signatures in files, none
+    /// of the optional markers set.
+    fn new(key: u64, num_lines: u32, files: Vec<(u32, u32)>) -> Collision {
+        Collision {
+            key,
+            num_lines,
+            files,
+            sig: 0,
+            whitespace_differs: None,
+            trailing_ws_differs: None,
+            reindented: None,
+            repeats: vec![],
+            id: 0,
+            function: None,
+            reordered: false,
+            savings: None,
+            location_hashes: vec![],
+        }
+    }
+
     /// A signature for a collision is the hash value of the data that represents the collision,
     /// this is used to identify duplicate result collisions, see _signature for calculation.
     fn signature(&self) -> u64 {
@@ -161,7 +945,7 @@ impl Collision {
     }
 
     fn _signature(&mut self) {
-        let mut s = DefaultHasher::new();
+        let mut s = new_hasher();
 
         for i in &self.files {
             let file_n = &i.0;
@@ -178,7 +962,8 @@ impl Collision {
     // less than the number that are duplicated.
     // A good example of this is:
     // linux/drivers/net/wireless/broadcom/brcm80211/brcmsmac/phy/phytbl_n.c
-    fn remove_overlap_same_file(&mut self) {
+    // Copies sharing up to --max-overlap-ratio of their lines don't count as overlapping.
+    fn remove_overlap_same_file(&mut self, max_overlap_ratio: f64) {
         let first = &self.files[0].0;
         let mut keep: VecDeque<(u32, u32)> = VecDeque::new();
 
@@ -186,7 +971,7 @@ impl Collision {
         if self.files.iter().all(|(file, _)| file == first) {
             while let Some(cur) = self.files.pop() {
                 if let Some(next_one) = self.files.last() {
-                    if !(cur.1 >= next_one.1 && cur.1 <= next_one.1 + self.num_lines) {
+                    if !overlap(*next_one, cur, self.num_lines, max_overlap_ratio) {
                         keep.push_front(cur);
                     }
                 } else {
@@ -198,66 +983,256 @@ impl Collision {
         }
     }
 
+    /// Find the runs of copies in a file at a regular stride, for --collapse-consecutive.  The
+    /// stride is in lines of the file, a run needs MIN_REPEAT_RUN copies which don't overlap.
+    fn find_repeats(&mut self, file_lookup: &FileId) {
+        let mut by_file: HashMap<u32, Vec<(u32, usize)>> = HashMap::new();
+        for (i, f) in self.files.iter().enumerate() {
+            let start = file_lookup.line_range(f.0, f.1, self.num_lines).0;
+            by_file.entry(f.0).or_default().push((start, i));
+        }
+
+        let mut repeats = vec![];
+        for starts in by_file.values_mut() {
+            starts.sort_unstable();
+            let mut i = 0;
+            while i + 1 < starts.len() {
+                let stride = starts[i + 1].0 - starts[i].0;
+                let mut end = i + 1;
+                while end + 1 < starts.len() && starts[end + 1].0 - starts[end].0 == stride {
+                    end += 1;
+                }
+                if stride >= self.num_lines && end - i + 1 >= MIN_REPEAT_RUN {
+                    repeats.push(Repeat {
+                        first: starts[i].1,
+                        members: starts[i + 1..=end].iter().map(|s| s.1).collect(),
+                        stride,
+                    });
+                    i = end + 1;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        repeats.sort_unstable_by_key(|r| r.first);
+        self.repeats = repeats;
+    }
+
+    /// Indexes of the locations to show, with the repeat each one starts if any.  The copies of a
+    /// repeat after the first are left out.
+    fn shown_locations(&self) -> Vec<(usize, Option<&Repeat>)> {
+        let hidden: HashSet<usize> = self
+            .repeats
+            .iter()
+            .flat_map(|r| r.members.iter().copied())
+            .collect();
+        (0..self.files.len())
+            .filter(|i| !hidden.contains(i))
+            .map(|i| (i, self.repeats.iter().find(|r| r.first == i)))
+            .collect()
+    }
+
+    /// True when every copy is in the same file, repetition within a file rather than copy and
+    /// paste between files.
+    fn within_one_file(&self) -> bool {
+        self.files.iter().all(|f| f.0 == self.files[0].0)
+    }
+
+    /// Number of duplicated lines that could be removed, counting each line only once.  The first
+    /// location is the copy which would remain, every other location contributes the lines which
+    /// aren't part of that copy and haven't already been counted for another collision.
+    fn distinct_removable_lines(&self, counted: &mut HashMap<u32, HashSet<u32>>) -> u64 {
+        let (keep_file, keep_start) = self.files[0];
+        let keep_end = keep_start + self.num_lines;
+        let mut removable: u64 = 0;
+
+        for &(file_id, start) in self.files.iter().skip(1) {
+            let lines = counted.entry(file_id).or_default();
+            for line in start..(start + self.num_lines) {
+                let kept = file_id == keep_file && line >= keep_start && line < keep_end;
+                if !kept && lines.insert(line) {
+                    removable += 1;
+                }
+            }
+        }
+        removable
+    }
+
+    /// Number of lines the collision adds to the duplicated line count of the summary, see
+    /// --line-count-mode and --dedup-identical-locations.
+    fn counted_lines(&self, opts: &Options, counted: &mut HashMap<u32, HashSet<u32>>) -> u64 {
+        if opts.line_count_mode == LineCountMode::Involved {
+            self.involved_lines(counted)
+        } else if opts.dedup_identical_locations {
+            self.distinct_removable_lines(counted)
+        } else {
+            (self.num_lines as usize * (self.files.len() - 1)) as u64
+        }
+    }
+
+    /// Number of lines which are part of any copy, for --line-count-mode involved.  Every copy
+    /// counts, including the first, but each line only once however many copies or collisions it
+    /// is part of.
+    fn involved_lines(&self, counted: &mut HashMap<u32, HashSet<u32>>) -> u64 {
+        let mut involved: u64 = 0;
+        for &(file_id, start) in &self.files {
+            let lines = counted.entry(file_id).or_default();
+            for line in start..(start + self.num_lines) {
+                if lines.insert(line) {
+                    involved += 1;
+                }
+            }
+        }
+        involved
+    }
+
     /// Given a collision, remove duplicate files from it, any overlaps for the same file
     /// and then generate it's signature.  This is done because we can run into some very
     /// interesting text patterns for firmware blobs stored as hex text which have repeating
     /// sequences.  TODO: Revisit the need for this code with actual examples to explain it better.
     /// I should have taken better notes in the code when I was running into these very interesting
     /// results and wondering what the input looked like.
-    fn scrub(&mut self) {
+    fn scrub(&mut self, max_overlap_ratio: f64, order: LocationOrder) {
         // Remove duplicates from each by sorting and then dedup
-        self.files.sort_by(|a, b| {
-            if a.1 == b.1 {
-                a.0.cmp(&b.0) // Number match, order by file name
-            } else {
-                a.1.cmp(&b.1) // Numbers don't match, order by number
+        self.files.sort_by(|a, b| match order {
+            LocationOrder::Line => {
+                if a.1 == b.1 {
+                    a.0.cmp(&b.0) // Number match, order by file name
+                } else {
+                    a.1.cmp(&b.1) // Numbers don't match, order by number
+                }
             }
+            LocationOrder::File => a.0.cmp(&b.0).then(a.1.cmp(&b.1)),
         });
         self.files.dedup();
-        self.remove_overlap_same_file();
+        self.remove_overlap_same_file(max_overlap_ratio);
 
         self._signature()
     }
 }
 
+/// How significant a duplicate is, based on how many times larger it is than --lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Info,
+    Minor,
+    Major,
+    Critical,
+}
+
+impl Severity {
+    const ALL: [Severity; 4] = [
+        Severity::Critical,
+        Severity::Major,
+        Severity::Minor,
+        Severity::Info,
+    ];
+
+    fn classify(num_lines: u32, min_lines: u32) -> Severity {
+        let ratio = num_lines / min_lines.max(1);
+        if ratio >= 8 {
+            Severity::Critical
+        } else if ratio >= 4 {
+            Severity::Major
+        } else if ratio >= 2 {
+            Severity::Minor
+        } else {
+            Severity::Info
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Minor => write!(f, "minor"),
+            Severity::Major => write!(f, "major"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Version of the JSON report layout, bump this when existing fields change meaning or go away.
+const REPORT_VERSION: u32 = 1;
+
 /// Some stats on what we processed and found.
 #[derive(Debug, Serialize)]
 struct ReportResults<'a> {
+    version: u32,
+    generated_at: u64,
     num_lines: u64,
+    num_lines_cross_file: u64,
+    num_lines_within_file: u64,
     num_ignored: u64,
+    num_generated_skipped: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_omitted: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    by_extension: Option<BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    histogram: Option<Vec<Bucket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a BTreeMap<String, String>>,
+    warnings: Vec<Warning>,
     duplicates: &'a [Collision],
 }
 
+/// The summary counts alone, written to the --summary-json file whatever the report's format.
+#[derive(Debug, Serialize)]
+struct Summary<'a> {
+    version: u32,
+    generated_at: u64,
+    num_lines: u64,
+    num_lines_cross_file: u64,
+    num_lines_within_file: u64,
+    num_duplicates: usize,
+    num_files: u32,
+    num_ignored: u64,
+    num_generated_skipped: u64,
+    num_lines_scanned: u64,
+    duplicated_ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a BTreeMap<String, String>>,
+}
+
+/// Seconds since the epoch, for the generated_at of the reports.
+fn generated_at() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
 // Check to see if we are checking for duplicate text in the same file and that one or more lines
 // overlap with each other.  There is nothing useful to report when this occurs, because the same
-// lines of text match each other in the same file.
-fn overlap(left: (u32, u32), right: (u32, u32), end: u32) -> bool {
-    left.0 == right.0
-        && (left.1 == right.1
-            || (right.1 >= left.1 && right.1 <= (left.1 + end))
-            || (left.1 >= right.1 && left.1 <= (right.1 + end)))
+// lines of text match each other in the same file.  With --max-overlap-ratio the copies may share
+// up to that fraction of their lines, at the default of 0 they can't even be adjacent.
+fn overlap(left: (u32, u32), right: (u32, u32), end: u32, max_overlap_ratio: f64) -> bool {
+    left.0 == right.0 && left.1.abs_diff(right.1) as f64 <= end as f64 * (1.0 - max_overlap_ratio)
 }
 
 /// Find the largest number of matching lines by going line by line from a known duplication point
 /// and recording it if it's bigger than the default number of matching lines
 fn maximize_collision(
-    file_hashes: &[Vec<u64>],
-    l_info: (u32, u32), // File id (index into file_hashes), line start
-    r_info: (u32, u32), // File id (index into file_hashes, line start
+    file_hashes: &SignatureArena,
+    l_info: (u32, u32), // File id, line start
+    r_info: (u32, u32), // File id, line start
     min_lines: u32,
+    max_overlap_ratio: f64,
 ) -> Option<Collision> {
-    let l_h = &file_hashes[l_info.0 as usize];
-    let r_h = &file_hashes[r_info.0 as usize];
+    let l_h = file_hashes.get(l_info.0);
+    let r_h = file_hashes.get(r_info.0);
 
     // If we have collisions and we overlap, skip
-    if overlap(l_info, r_info, min_lines) {
+    if overlap(l_info, r_info, min_lines, max_overlap_ratio) {
         return None;
     }
 
     let mut offset: u32 = 0;
     let l_num = l_h.len();
     let r_num = r_h.len();
-    let mut s = DefaultHasher::new();
 
     loop {
         let l_index: usize = (l_info.1 + offset) as usize;
@@ -265,7 +1240,6 @@ fn maximize_collision(
 
         if l_index < l_num && r_index < r_num {
             if l_h[l_index] == r_h[r_index] {
-                l_h[l_index].hash(&mut s);
                 offset += 1;
             } else {
                 break;
@@ -275,36 +1249,71 @@ fn maximize_collision(
         }
     }
 
+    if let (Some(l_ids), Some(r_ids)) = (
+        file_hashes.identifiers.get(&l_info.0),
+        file_hashes.identifiers.get(&r_info.0),
+    ) {
+        offset = renamed_match_len(
+            (l_ids, l_info.1 as usize),
+            (r_ids, r_info.1 as usize),
+            offset,
+        );
+        if offset < min_lines {
+            return None;
+        }
+    }
+
+    // With --treat-includes-as-boundaries a duplicate ends before a preprocessor directive, with
+    // --blank-line-boundary before a blank line.
+    for (fid, start) in [l_info, r_info] {
+        if let Some(boundaries) = file_hashes.boundaries.get(&fid) {
+            let i = boundaries.partition_point(|b| *b < start);
+            if let Some(b) = boundaries.get(i) {
+                offset = offset.min(b - start);
+            }
+        }
+    }
+    if offset < min_lines {
+        return None;
+    }
+
+    let mut s = new_hasher();
+    for h in &l_h[l_info.1 as usize..(l_info.1 + offset) as usize] {
+        h.hash(&mut s);
+    }
+
     // If after walking we overlap skip too
-    if overlap(l_info, r_info, offset) {
+    if overlap(l_info, r_info, offset, max_overlap_ratio) {
         return None;
     }
 
     let files: Vec<(u32, u32)> = vec![(l_info.0, l_info.1), (r_info.0, r_info.1)];
-    Some(Collision {
-        key: s.finish(),
-        num_lines: offset,
-        files,
-        sig: 0,
-    })
+    Some(Collision::new(s.finish(), offset, files))
 }
 
-/// Given a file name, a start line number, and number of lines, dump the text into the output.
-fn print_dup_text(filename: &str, start_line: usize, count: usize) {
-    let file = File::open(filename)
+/// Given a file name, a start line number, and number of lines, return the raw lines including
+/// their terminating record separator.
+fn read_region(
+    filename: &str,
+    start_line: usize,
+    count: usize,
+    separator: u8,
+    buffer_size: usize,
+) -> Vec<Vec<u8>> {
+    let mut reader = open_text(filename, buffer_size)
         .unwrap_or_else(|_| panic!("Unable to open file we have already opened {:?}", filename));
-    let mut reader = BufReader::new(file);
     let mut line_number = 0;
     let end = start_line + count;
+    let mut rc = Vec::with_capacity(count);
 
     while line_number < end {
         let mut buf: Vec<u8> = vec![];
-        match reader.read_until(0xA, &mut buf) {
+        match reader.read_until(separator, &mut buf) {
             Ok(num_bytes) => {
                 if num_bytes == 0 {
                     break;
                 } else if line_number >= start_line {
-                    print!("{}", String::from_utf8_lossy(&buf));
+                    rc.push(buf);
                 }
 
                 line_number += 1;
@@ -315,72 +1324,784 @@ fn print_dup_text(filename: &str, start_line: usize, count: usize) {
             }
         }
     }
+    rc
 }
 
-/// Display the output as text or structured JSON.
-fn print_report(
-    printable_results: &[Collision],
-    opts: &Options,
+/// The largest size of duplicate in each bucket of --report-histogram, the last bucket has the
+/// larger ones.
+const HISTOGRAM_BUCKETS: [u32; 3] = [10, 25, 100];
+
+/// Duplicates of min to max lines, max is None for the last bucket, and the lines they count in
+/// the summary.
+#[derive(Debug, Serialize)]
+struct Bucket {
+    min: u32,
+    max: Option<u32>,
+    duplicates: u64,
+    lines: u64,
+}
+
+impl Bucket {
+    /// The buckets for duplicates of at least min_lines, those for smaller ones are left out.
+    fn histogram(min_lines: u32) -> Vec<Bucket> {
+        let mut rc = vec![];
+        let mut min = min_lines;
+        for max in HISTOGRAM_BUCKETS.iter().map(|&m| Some(m)).chain([None]) {
+            if max.map(|m| m >= min).unwrap_or(true) {
+                rc.push(Bucket {
+                    min,
+                    max,
+                    duplicates: 0,
+                    lines: 0,
+                });
+                min = max.unwrap_or(min) + 1;
+            }
+        }
+        rc
+    }
+
+    fn add(histogram: &mut [Bucket], num_lines: u32, lines: u64) {
+        if let Some(b) = histogram
+            .iter_mut()
+            .find(|b| b.max.map(|m| num_lines <= m).unwrap_or(true))
+        {
+            b.duplicates += 1;
+            b.lines += lines;
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.max {
+            Some(max) => format!("{}-{}", self.min, max),
+            None => format!("{}+", self.min),
+        }
+    }
+}
+
+/// The extension of a file name for --count-by-extension, "(none)" if it doesn't have one.
+fn extension_of(file_name: &str) -> String {
+    Path::new(file_name)
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+/// Text added to a location which starts a run of repeats.
+fn repeat_note(repeat: Option<&Repeat>) -> String {
+    match repeat {
+        Some(r) => format!(", repeated {} times with stride {}", r.times(), r.stride),
+        None => "".to_string(),
+    }
+}
+
+/// Given a file name, a start line number, and number of lines, dump the text into the output.
+/// Lines are terminated by the record separator so the text matches what was reported.
+fn write_dup_text(
+    out: &mut dyn Write,
+    filename: &str,
+    start_line: usize,
+    count: usize,
+    separator: u8,
+    buffer_size: usize,
+) -> io::Result<()> {
+    for line in read_region(filename, start_line, count, separator, buffer_size) {
+        out.write_all(String::from_utf8_lossy(&line).as_bytes())?;
+    }
+    Ok(())
+}
+
+/// A line without its record separator, and with a newline separator the carriage return before
+/// it, so copies with different line endings, or a last line without one, compare equal.
+fn without_terminator(mut line: Vec<u8>, separator: u8) -> Vec<u8> {
+    if line.last() == Some(&separator) {
+        line.pop();
+        if separator == b'\n' && line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+/// Compare the actual text of each copy of a duplicate.  Duplicates are found after removing
+/// leading and trailing whitespace, so the copies can still differ in whitespace, record this on
+/// the collision, and whether trimming the end, or the start, of each line is all it takes to
+/// make them identical.  Line endings aren't compared.  With --location-hashes also record the
+/// hash of each copy's text, copies with the same hash are identical.
+fn verify_copies(printable_results: &mut [Collision], opts: &Options) {
+    // File name, start line and number of lines of each copy
+    let regions: Vec<Vec<(Arc<str>, u32, u32)>> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        printable_results
+            .iter()
+            .map(|c| {
+                c.files
+                    .iter()
+                    .map(|f| {
+                        let (start, end) = file_lookup_locked.line_range(f.0, f.1, c.num_lines);
+                        (file_lookup_locked.id_to_name(f.0), start, end - start)
+                    })
+                    .collect()
+            })
+            .collect()
+    };
+
+    printable_results
+        .par_iter_mut()
+        .zip(regions.par_iter())
+        .for_each(|(c, regions)| {
+            let copies: Vec<Vec<Vec<u8>>> = regions
+                .iter()
+                .map(|(name, start, count)| {
+                    read_region(
+                        name,
+                        *start as usize,
+                        *count as usize,
+                        opts.record_separator.0,
+                        opts.read_buffer_size,
+                    )
+                    .into_iter()
+                    .map(|line| without_terminator(line, opts.record_separator.0))
+                    .collect()
+                })
+                .collect();
+
+            if opts.note_whitespace_diffs {
+                c.whitespace_differs = Some(copies.iter().any(|copy| *copy != copies[0]));
+            }
+
+            if opts.location_hashes {
+                c.location_hashes = copies.iter().map(calculate_hash).collect();
+            }
+
+            if opts.flag_trailing_ws {
+                let trimmed: Vec<Vec<String>> = copies
+                    .iter()
+                    .map(|copy| {
+                        copy.iter()
+                            .map(|line| String::from_utf8_lossy(line).trim_end().to_string())
+                            .collect()
+                    })
+                    .collect();
+                c.trailing_ws_differs = Some(
+                    copies.iter().any(|copy| *copy != copies[0])
+                        && trimmed.iter().all(|copy| *copy == trimmed[0]),
+                );
+            }
+
+            if opts.flag_reindent {
+                let dedented: Vec<Vec<String>> = copies
+                    .iter()
+                    .map(|copy| {
+                        copy.iter()
+                            .map(|line| String::from_utf8_lossy(line).trim_start().to_string())
+                            .collect()
+                    })
+                    .collect();
+                c.reindented = Some(
+                    copies.iter().any(|copy| *copy != copies[0])
+                        && dedented.iter().all(|copy| *copy == dedented[0]),
+                );
+            }
+        });
+}
+
+/// Write the report to out as text or structured JSON, syslog and the HTML report are written to
+/// where they go.
+fn print_report(
+    printable_results: &[Collision],
+    opts: &Options,
     ignore_hashes: &HashMap<u64, bool>,
-) {
+    out: &mut dyn Write,
+) -> io::Result<()> {
     let mut num_lines: u64 = 0;
+    let mut num_lines_within_file: u64 = 0;
+    let mut by_extension: BTreeMap<String, u64> = BTreeMap::new();
+    let mut histogram = Bucket::histogram(opts.lines);
     let mut ignored: u64 = 0;
+    let mut counted_lines: HashMap<u32, HashSet<u32>> = HashMap::new();
+    // The text or quickfix output for each reported duplicate, held back so we can tell how much
+    // fits within --report-limit-bytes.
+    let mut blocks: Vec<Vec<u8>> = vec![];
     let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
 
     for p in printable_results.iter() {
         if ignore_hashes.contains_key(&p.key) {
             ignored += 1;
+
+            if opts.explain_ignored {
+                let locations: Vec<String> = p
+                    .files
+                    .iter()
+                    .map(|f| {
+                        format!(
+                            "{}:{}",
+                            file_lookup_locked.id_to_name(f.0),
+                            file_lookup_locked.line_range(f.0, f.1, p.num_lines).0 + 1
+                        )
+                    })
+                    .collect();
+                eprintln!(
+                    "Ignored hash signature {}, {} lines in {}",
+                    p.key,
+                    p.num_lines,
+                    locations.join(", ")
+                );
+            }
         } else {
-            num_lines += (p.num_lines as usize * (p.files.len() - 1)) as u64;
+            let removable = p.counted_lines(opts, &mut counted_lines);
+            num_lines += removable;
+            if p.within_one_file() {
+                num_lines_within_file += removable;
+            }
+            Bucket::add(&mut histogram, p.num_lines, removable);
+            if opts.count_by_extension {
+                for f in &p.files {
+                    *by_extension
+                        .entry(extension_of(&file_lookup_locked.id_to_name(f.0)))
+                        .or_default() += p.num_lines as u64;
+                }
+            }
 
-            if !opts.json {
-                println!(
-                    "{}\nHash signature = {}\nFound {} copy & pasted lines in the following files:",
+            let mut block: Vec<u8> = vec![];
+            if opts.quickfix {
+                for (i, repeat) in p.shown_locations() {
+                    let spec_file = &p.files[i];
+                    let _ = writeln!(
+                        block,
+                        "{}:{}:1: {} duplicate lines in {} locations, hash signature {}{}",
+                        file_lookup_locked.id_to_name(spec_file.0),
+                        file_lookup_locked
+                            .line_range(spec_file.0, spec_file.1, p.num_lines)
+                            .0
+                            + 1,
+                        p.num_lines,
+                        p.files.len(),
+                        p.key,
+                        repeat_note(repeat)
+                    );
+                }
+            } else if !opts.json && !opts.syslog {
+                let _ = writeln!(
+                    block,
+                    "{}\nDuplicate {}\nHash signature = {}\n\
+                    Found {} copy & pasted lines in the following files:",
                     "*".repeat(80),
+                    p.id,
                     p.key,
                     p.num_lines
                 );
 
-                for spec_file in &p.files {
+                if let Some(function) = &p.function {
+                    let _ = writeln!(block, "The whole function {}", function);
+                }
+
+                if p.reordered {
+                    let _ = writeln!(block, "Copies have the same lines in a different order");
+                }
+
+                if let Some(savings) = p.savings {
+                    let _ = writeln!(block, "{:.1}% of the duplicated lines", savings);
+                }
+
+                if p.whitespace_differs == Some(true) {
+                    // With --alpha-rename the copies can differ in their identifiers too.
+                    let _ = writeln!(
+                        block,
+                        "{}",
+                        if opts.alpha_rename {
+                            "Copies differ, in whitespace or renamed identifiers"
+                        } else {
+                            "Copies only match after whitespace normalization"
+                        }
+                    );
+                }
+
+                if p.trailing_ws_differs == Some(true) {
+                    let _ = writeln!(block, "Copies only differ in trailing whitespace");
+                }
+
+                if p.reindented == Some(true) {
+                    let _ = writeln!(block, "Copies only differ in indentation");
+                }
+
+                for (i, repeat) in p.shown_locations() {
+                    let spec_file = &p.files[i];
                     let filename = file_lookup_locked.id_to_name(spec_file.0);
-                    let start_line = spec_file.1;
-                    let end_line = start_line + p.num_lines;
-                    println!(
-                        "Between lines {} and {} in {}",
+                    let (start_line, end_line) =
+                        file_lookup_locked.line_range(spec_file.0, spec_file.1, p.num_lines);
+                    let _ = writeln!(
+                        block,
+                        "Between lines {} and {} in {}{}",
                         start_line + 1,
                         end_line,
-                        filename
+                        filename,
+                        repeat_note(repeat)
                     );
                 }
 
-                if opts.print {
-                    print_dup_text(
-                        &*file_lookup_locked.id_to_name(p.files[0usize].0),
-                        p.files[0usize].1 as usize,
-                        p.num_lines as usize,
-                    );
+                if opts.print && p.num_lines >= opts.min_block_lines_for_print {
+                    let (start_line, end_line) =
+                        file_lookup_locked.line_range(p.files[0].0, p.files[0].1, p.num_lines);
+                    write_dup_text(
+                        &mut block,
+                        &file_lookup_locked.id_to_name(p.files[0usize].0),
+                        start_line as usize,
+                        (end_line - start_line) as usize,
+                        opts.record_separator.0,
+                        opts.read_buffer_size,
+                    )?;
                 }
             }
+            if !block.is_empty() {
+                blocks.push(block);
+            }
         }
     }
 
-    if !opts.json {
-        println!(
-            "Found {} duplicate lines in {} chunks in {} files, {} chunks ignored.\n\
-            https://github.com/tasleson/duplihere",
+    let number_files = file_lookup_locked.number_files();
+
+    if opts.syslog {
+        // The report goes to syslog in place of out, the outputs to files are still written.
+        drop(file_lookup_locked);
+        write_side_outputs(printable_results, opts, ignore_hashes);
+
+        let reported: Vec<&Collision> = printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+            .collect();
+        log_report(
+            &reported,
+            &FILE_LOOKUP.lock().unwrap(),
+            opts,
             num_lines,
-            printable_results.len() - ignored as usize,
-            file_lookup_locked.number_files(),
-            ignored
-        )
+            number_files,
+            ignored,
+        );
+        return Ok(());
+    }
+
+    // Serializing a collision needs to acquire the lock itself.
+    drop(file_lookup_locked);
+
+    if opts.quiet_on_clean && !opts.json && printable_results.len() == ignored as usize {
+        if !opts.quickfix {
+            writeln!(out, "No duplicates found")?;
+        }
+    } else if opts.quickfix || !opts.json {
+        let mut summary = String::new();
+        if !opts.quickfix {
+            if opts.ignore_generated {
+                summary += &format!(
+                    "Skipped {} generated files.\n",
+                    GENERATED_SKIPPED.load(Ordering::Relaxed)
+                );
+            }
+            summary += &format!(
+                "Found {} duplicate lines in {} chunks in {} files, {} chunks ignored.\n\
+                {} duplicate lines are copies across files, {} within a file.\n",
+                num_lines,
+                printable_results.len() - ignored as usize,
+                number_files,
+                ignored,
+                num_lines - num_lines_within_file,
+                num_lines_within_file
+            );
+            if opts.count_by_extension {
+                let mut counts: Vec<(&String, &u64)> = by_extension.iter().collect();
+                counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+                summary += "Duplicated lines by extension, every copy counted:\n";
+                for (ext, count) in counts {
+                    summary += &format!("{:>12} {:>10}\n", ext, count);
+                }
+            }
+            if opts.report_histogram {
+                summary += "Duplicates by size in lines:\n";
+                summary += &format!("{:>12} {:>10} {:>10}\n", "size", "duplicates", "lines");
+                for b in &histogram {
+                    summary += &format!("{:>12} {:>10} {:>10}\n", b.label(), b.duplicates, b.lines);
+                }
+            }
+            summary += "https://github.com/tasleson/duplihere\n";
+        }
+        write_text_output(&blocks, &summary, opts, out)?;
     } else {
-        let r = ReportResults {
+        let mut r = ReportResults {
+            version: REPORT_VERSION,
+            generated_at: generated_at(),
             num_lines,
+            num_lines_cross_file: num_lines - num_lines_within_file,
+            num_lines_within_file,
             num_ignored: ignored,
+            num_generated_skipped: GENERATED_SKIPPED.load(Ordering::Relaxed),
+            num_omitted: None,
+            by_extension: opts.count_by_extension.then_some(by_extension),
+            histogram: opts.report_histogram.then_some(histogram),
+            context: (!opts.report_context.is_empty()).then_some(&opts.report_context),
+            warnings: WARNINGS.lock().unwrap().clone(),
             duplicates: printable_results,
         };
-        println!("{}", serde_json::to_string_pretty(&r).unwrap());
+        if opts.report_limit_bytes != 0 {
+            limit_json_report(&mut r, opts.report_limit_bytes);
+        }
+        if opts.output_json_array_only {
+            serde_json::to_writer_pretty(&mut *out, &r.duplicates)?;
+        } else {
+            serde_json::to_writer_pretty(&mut *out, &r)?;
+        }
+        writeln!(out)?;
+    }
+
+    write_side_outputs(printable_results, opts, ignore_hashes);
+    Ok(())
+}
+
+/// Write the --html, --dot and --extract-to outputs, which go to files of their own whatever
+/// the report is.
+fn write_side_outputs(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) {
+    if !opts.html.is_empty() {
+        let reported: Vec<&Collision> = printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+            .collect();
+
+        if let Err(e) = html::write_report(&opts.html, &reported) {
+            fatal(
+                1,
+                format!("Unable to write HTML report {}, reason {}", opts.html, e),
+            );
+        }
+    }
+
+    if !opts.dot.is_empty() {
+        let reported: Vec<&Collision> = printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+            .collect();
+
+        if let Err(e) = dot::write_graph(&opts.dot, &reported) {
+            fatal(
+                1,
+                format!("Unable to write DOT file {}, reason {}", opts.dot, e),
+            );
+        }
+    }
+
+    if !opts.extract_to.is_empty() {
+        let reported: Vec<&Collision> = printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+            .collect();
+
+        match extract::write_snippets(
+            &opts.extract_to,
+            &reported,
+            opts.record_separator.0,
+            opts.read_buffer_size,
+        ) {
+            Ok(differing) => {
+                for id in differing {
+                    eprintln!(
+                        "WARNING: the copies of duplicate {} differ, its first copy was extracted",
+                        id
+                    );
+                }
+            }
+            Err(e) => {
+                fatal(
+                    1,
+                    format!(
+                        "Unable to extract duplicates to {}, reason {}",
+                        opts.extract_to, e
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// The number of leading entries of sizes, smallest duplicates first, to leave out so the rest
+/// fit in budget.  We keep the largest duplicates as they are the most significant.
+fn num_to_omit(sizes: &[usize], budget: usize) -> usize {
+    let mut used = 0;
+    for (i, size) in sizes.iter().enumerate().rev() {
+        used += size;
+        if used > budget {
+            return i + 1;
+        }
+    }
+    0
+}
+
+/// Write the text or quickfix output followed by the summary to out.  With --report-limit-bytes the
+/// smallest duplicates are left out until everything fits, and a notice says how many.  The
+/// notice goes to stderr for quickfix, so every line of the output is still a location.
+fn write_text_output(
+    blocks: &[Vec<u8>],
+    summary: &str,
+    opts: &Options,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let limit = opts.report_limit_bytes as usize;
+    let notice = |omitted: usize| {
+        format!(
+            "Report limited to {} bytes, {} smallest duplicates omitted.\n",
+            limit, omitted
+        )
+    };
+
+    // The --report-comment context, quickfix output has no place for it.
+    let header = if opts.quickfix {
+        String::new()
+    } else {
+        context_header(&opts.report_context)
+    };
+
+    let mut omitted = 0;
+    if limit != 0 {
+        let sizes: Vec<usize> = blocks.iter().map(|b| b.len()).collect();
+        let mut reserved = header.len() + summary.len();
+        if !opts.quickfix {
+            // The count in the notice can't be longer than this one.
+            reserved += notice(blocks.len()).len();
+        }
+        omitted = num_to_omit(&sizes, limit.saturating_sub(reserved));
+    }
+
+    out.write_all(header.as_bytes())?;
+    for b in &blocks[omitted..] {
+        out.write_all(b)?;
+    }
+    if omitted != 0 {
+        if opts.quickfix {
+            eprint!("{}", notice(omitted));
+        } else {
+            out.write_all(notice(omitted).as_bytes())?;
+        }
+    }
+    out.write_all(summary.as_bytes())
+}
+
+/// The --report-comment context as "# key=value" lines for the start of a text report.  A
+/// backslash, carriage return or newline in a key or value is escaped so each pair stays on one
+/// line.
+fn context_header(context: &BTreeMap<String, String>) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('\r', "\\r")
+            .replace('\n', "\\n")
+    };
+    context
+        .iter()
+        .map(|(k, v)| format!("# {}={}\n", escape(k), escape(v)))
+        .collect()
+}
+
+/// Start $PAGER, or less if it isn't set, to show the report.  None if it can't be started, we
+/// then write to stdout.  less is told to pass color escape sequences through and to exit
+/// straight away when the report fits on one screen, unless $LESS says otherwise.
+fn spawn_pager() -> Option<process::Child> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+    let mut words = pager.split_whitespace();
+    let program = words.next()?;
+
+    let mut cmd = process::Command::new(program);
+    cmd.args(words).stdin(process::Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    cmd.spawn().ok()
+}
+
+/// Leave out the smallest duplicates until the pretty printed JSON report fits in limit bytes,
+/// num_omitted records how many were left out.  The caller must not be holding the lock on
+/// FILE_LOOKUP.
+fn limit_json_report(r: &mut ReportResults, limit: u64) {
+    let all = r.duplicates;
+    let size = |r: &ReportResults| serde_json::to_vec_pretty(r).unwrap().len() as u64 + 1;
+    if size(r) <= limit {
+        return;
+    }
+
+    // The size shrinks as more are omitted, find the fewest to omit which fits.  If nothing
+    // fits we are left with just the summary.
+    let (mut lo, mut hi) = (1, all.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        r.duplicates = &all[mid..];
+        r.num_omitted = Some(mid as u64);
+        if size(r) <= limit {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    r.duplicates = &all[lo..];
+    r.num_omitted = Some(lo as u64);
+}
+
+/// Where a report is written, stdout, the --pager or the --output file, with --tee the file and
+/// stdout.  A failure writing to stdout or the pager, eg. the reader of a pipe went away, is
+/// remembered and we stop writing there, it doesn't stop the file from being written or the exit
+/// status from reflecting what was found.
+struct Sink {
+    console: Option<Box<dyn Write>>,
+    console_error: Option<io::Error>,
+    file: Option<BufWriter<File>>,
+    pager: Option<process::Child>,
+}
+
+impl Sink {
+    fn new(opts: &Options) -> Sink {
+        let file = (!opts.output.is_empty()).then(|| {
+            BufWriter::new(File::create(&opts.output).unwrap_or_else(|e| {
+                fatal(1, format!("Unable to create {}, reason {}", opts.output, e));
+            }))
+        });
+
+        let mut pager = if opts.pager && !opts.json && !opts.syslog && io::stdout().is_terminal() {
+            spawn_pager()
+        } else {
+            None
+        };
+        let console: Option<Box<dyn Write>> = if file.is_some() && !opts.tee {
+            None
+        } else {
+            match pager.as_mut().and_then(|p| p.stdin.take()) {
+                Some(stdin) => Some(Box::new(BufWriter::new(stdin))),
+                None => Some(Box::new(BufWriter::new(io::stdout().lock()))),
+            }
+        };
+
+        Sink {
+            console,
+            console_error: None,
+            file,
+            pager,
+        }
+    }
+
+    /// Flush everything out and wait for the user to quit the pager.  An error is from writing
+    /// the file.
+    fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+
+        // Closing its input lets the pager know there is no more.
+        drop(self.console.take());
+        if let Some(mut p) = self.pager.take() {
+            let _ = p.wait();
+        }
+
+        if let Some(e) = self.console_error {
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("WARNING: Unable to write report to stdout, reason {}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let (Some(console), None) = (&mut self.console, &self.console_error) {
+            if let Err(e) = console.write_all(buf) {
+                self.console_error = Some(e);
+            }
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let (Some(console), None) = (&mut self.console, &self.console_error) {
+            if let Err(e) = console.flush() {
+                self.console_error = Some(e);
+            }
+        }
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Send the summary to syslog along with a warning for each duplicate which is classified as
+/// major (LOG_WARNING) or critical (LOG_ERR), the rest are only counted in the summary (LOG_INFO)
+/// so we don't flood the log.
+#[cfg(unix)]
+fn log_report(
+    reported: &[&Collision],
+    file_lookup: &FileId,
+    opts: &Options,
+    num_lines: u64,
+    number_files: u32,
+    ignored: u64,
+) {
+    let log = syslog::Syslog::connect().unwrap_or_else(|e| {
+        fatal(1, format!("Unable to connect to syslog, reason {}", e));
+    });
+
+    let mut rc = Ok(());
+    for p in reported {
+        let priority = match Severity::classify(p.num_lines, opts.lines) {
+            Severity::Critical => syslog::Priority::Err,
+            Severity::Major => syslog::Priority::Warning,
+            _ => continue,
+        };
+
+        let locations: Vec<String> = p
+            .files
+            .iter()
+            .map(|f| {
+                format!(
+                    "{}:{}",
+                    file_lookup.id_to_name(f.0),
+                    file_lookup.line_range(f.0, f.1, p.num_lines).0 + 1
+                )
+            })
+            .collect();
+        rc = rc.and(log.log(
+            priority,
+            &format!(
+                "{} duplicate lines, hash signature {} in {}",
+                p.num_lines,
+                p.key,
+                locations.join(", ")
+            ),
+        ));
     }
+
+    rc = rc.and(log.log(
+        syslog::Priority::Info,
+        &format!(
+            "Found {} duplicate lines in {} chunks in {} files, {} chunks ignored.",
+            num_lines,
+            reported.len(),
+            number_files,
+            ignored
+        ),
+    ));
+
+    if let Err(e) = rc {
+        fatal(1, format!("Unable to write to syslog, reason {}", e));
+    }
+}
+
+#[cfg(not(unix))]
+fn log_report(
+    _reported: &[&Collision],
+    _file_lookup: &FileId,
+    _opts: &Options,
+    _num_lines: u64,
+    _number_files: u32,
+    _ignored: u64,
+) {
+    fatal(1, "--syslog is only supported on unix like systems");
 }
 
 /// When we have more than one region of text that matches another we will walk all combination
@@ -388,12 +2109,20 @@ fn print_report(
 /// store in in the results hash.
 fn walk_collision(
     collisions: &[(u32, u32)],
-    file_hashes: &[Vec<u64>],
-    min_lines: u32,
+    file_hashes: &SignatureArena,
+    opts: &Options,
     results_hash: &DashMap<u64, Collision>,
 ) {
+    let max_locations = opts.max_locations;
     for l_idx in 0..(collisions.len() - 1) {
         for r_idx in l_idx..collisions.len() {
+            if opts.max_pairs != 0
+                && PAIRS_COMPARED.fetch_add(1, Ordering::Relaxed) >= opts.max_pairs
+            {
+                PAIRS_CAPPED.store(true, Ordering::Relaxed);
+                return;
+            }
+
             let (l_file, l_start) = &collisions[l_idx];
             let (r_file, r_start) = &collisions[r_idx];
 
@@ -401,10 +2130,16 @@ fn walk_collision(
                 file_hashes,
                 (*l_file, *l_start),
                 (*r_file, *r_start),
-                min_lines,
+                opts.lines,
+                opts.max_overlap_ratio,
             ) {
                 match results_hash.get_mut(&coll.key) {
-                    Some(mut existing) => existing.files.append(&mut coll.files),
+                    Some(mut existing) => {
+                        existing.files.append(&mut coll.files);
+                        if max_locations != 0 && existing.files.len() > max_locations {
+                            cap_locations(&mut existing.files, max_locations);
+                        }
+                    }
                     None => {
                         results_hash.insert(coll.key, coll);
                     }
@@ -414,6 +2149,28 @@ fn walk_collision(
     }
 }
 
+/// Number of pairs of locations walk_collision has compared, counted with --max-pairs.
+static PAIRS_COMPARED: AtomicU64 = AtomicU64::new(0);
+
+/// Set when --max-pairs stopped walk_collision before every pair was compared.
+static PAIRS_CAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Set when --max-locations left locations out of a duplicate.
+static LOCATIONS_CAPPED: AtomicBool = AtomicBool::new(false);
+
+/// Every pair of locations walk_collision compares adds both to the collision, so when a window
+/// is shared by many locations the same ones are added over and over until scrub removes them.
+/// With --max-locations, once there are more than that we remove the repeats early, and if there
+/// are still too many the extra ones are dropped.
+fn cap_locations(files: &mut Vec<(u32, u32)>, max_locations: usize) {
+    files.sort_unstable();
+    files.dedup();
+    if files.len() > max_locations {
+        LOCATIONS_CAPPED.store(true, Ordering::Relaxed);
+        files.truncate(max_locations);
+    }
+}
+
 /// At this point in time we have a vector of vectors which contains the line hash signatures and
 /// we have also calculated the rolling hash signatures for each file and registered them in the
 /// collision_hash.  We now remove any hash entries where the value for the key is 1 and for all
@@ -421,7 +2178,7 @@ fn walk_collision(
 /// text number of lines.
 fn find_collisions(
     collision_hash: DashMap<u64, Vec<(u32, u32)>>,
-    file_hashes: &mut [Vec<u64>],
+    file_hashes: &SignatureArena,
     opts: &Options,
 ) -> DashMap<u64, Collision> {
     let results_hash: DashMap<u64, Collision> = DashMap::new();
@@ -437,42 +2194,633 @@ fn find_collisions(
     collision_hash.shrink_to_fit();
 
     let collision_vec: Vec<Vec<(u32, u32)>> = collision_hash.into_iter().map(|(_, v)| v).collect();
+    PAIRS_COMPARED.store(0, Ordering::Relaxed);
+
+    if opts.rayon_stats {
+        let stats = PhaseStats::new("find collisions");
+        collision_vec
+            .par_iter()
+            .for_each(|e| stats.record(|| walk_collision(e, file_hashes, opts, &results_hash)));
+        stats.report();
+    } else {
+        collision_vec
+            .par_iter()
+            .for_each(|e| walk_collision(e, file_hashes, opts, &results_hash));
+    }
 
-    collision_vec
-        .par_iter()
-        .for_each(|e| walk_collision(e, file_hashes, opts.lines, &results_hash));
+    if LOCATIONS_CAPPED.swap(false, Ordering::Relaxed) {
+        let msg = format!(
+            "--max-locations {} reached, some duplicates are missing locations",
+            opts.max_locations
+        );
+        if record_warning(WarningKind::LocationLimit, msg.clone()) {
+            eprintln!("WARNING: {}", msg);
+        }
+    }
+
+    if PAIRS_CAPPED.swap(false, Ordering::Relaxed) {
+        let msg = format!(
+            "--max-pairs {} reached, the results are incomplete",
+            opts.max_pairs
+        );
+        if record_warning(WarningKind::PairLimit, msg.clone()) {
+            eprintln!("WARNING: {}", msg);
+        }
+    }
 
     results_hash
 }
 
-/// We have all the data, we now need to do some sorting and duplicate removals and then
-/// dump the end data.
-fn process_report(
-    results_hash: DashMap<u64, Collision>,
+/// Run the collision finding phase over the same data with a number of different thread counts
+/// and report how long each one took, so users can pick a --threads value which suits their
+/// machine and source tree.  The results of each run are thrown away.
+fn benchmark_threads(
+    collision_hash: &DashMap<u64, Vec<(u32, u32)>>,
+    file_hashes: &SignatureArena,
     opts: &Options,
-    ignore_hashes: &HashMap<u64, bool>,
 ) {
-    let mut final_report: Vec<Collision> = results_hash.into_iter().map(|(_, v)| v).collect();
-    final_report.par_sort_unstable_by(|a, b| a.num_lines.cmp(&b.num_lines).reverse());
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut thread_counts = vec![1, 2, 4, 8, cores];
+    thread_counts.sort_unstable();
+    thread_counts.dedup();
 
-    let mut printable_results: Vec<Collision> = Vec::new();
-
-    {
-        let mut chunk_processed: HashMap<u64, bool> = HashMap::new();
+    eprintln!("{:>8} {:>12} {:>10}", "threads", "seconds", "duplicates");
+    for threads in thread_counts {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let snapshot = collision_hash.clone();
 
-        final_report.par_iter_mut().for_each(|ea| ea.scrub());
+        let start = Instant::now();
+        let results = pool.install(|| find_collisions(snapshot, file_hashes, opts));
+        let elapsed = start.elapsed();
 
-        for ea in final_report {
-            let cs = ea.signature();
-            if chunk_processed.get(&cs).is_none() {
-                chunk_processed.insert(cs, true);
-                printable_results.push(ea);
-            }
-        }
+        eprintln!(
+            "{:>8} {:>12.3} {:>10}",
+            threads,
+            elapsed.as_secs_f64(),
+            results.len()
+        );
     }
+}
 
-    printable_results.par_sort_unstable_by(|a, b| {
-        if a.num_lines == b.num_lines {
+/// Which of the --left and --right trees a file pattern is for.
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Files which were given a role by how they were found, by file id.
+struct FileTags {
+    // Snippets from the --deny-list file.
+    deny: HashSet<u32>,
+    // Files from the --left and --right patterns.
+    left: HashSet<u32>,
+    right: HashSet<u32>,
+    // The --lines of each file when a duplihere.toml sets it for some, empty otherwise.
+    lines: HashMap<u32, u32>,
+}
+
+/// The options for the files which have settings of their own from duplihere.toml files.
+#[derive(Default)]
+struct FileOptions {
+    sets: Vec<Options>,
+    of_file: HashMap<u32, usize>,
+}
+
+impl FileOptions {
+    fn get<'a>(&'a self, fid: u32, opts: &'a Options) -> &'a Options {
+        self.of_file
+            .get(&fid)
+            .map(|&i| &self.sets[i])
+            .unwrap_or(opts)
+    }
+}
+
+/// Read the files finished before the interruption with --resume, and start the --checkpoint
+/// with them.  Ids are the position in files_to_process, which the checkpoint relies on.
+fn start_checkpoint(
+    files_to_process: &[(u32, String)],
+    opts: &Options,
+    file_options: &FileOptions,
+) -> (Option<Checkpoint>, Vec<Record<Signatures>>) {
+    if opts.checkpoint.is_empty() && opts.resume.is_empty() {
+        return (None, vec![]);
+    }
+
+    let files = checkpoint::corpus(files_to_process, |name| {
+        VIRTUAL_FILES
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|content| (content.len() as u64, calculate_hash(content)))
+    })
+    .unwrap_or_else(|e| {
+        fatal(
+            1,
+            format!("Unable to record the files being scanned, reason {}", e),
+        );
+    });
+
+    let resumed = if opts.resume.is_empty() {
+        vec![]
+    } else {
+        checkpoint::resume(&opts.resume, &files, |fid| {
+            settings_hash(file_options.get(fid, opts))
+        })
+        .unwrap_or_else(|e| {
+            fatal(2, format!("Unable to resume from {}, {}", opts.resume, e));
+        })
+    };
+
+    let checkpoint = (!opts.checkpoint.is_empty()).then(|| {
+        Checkpoint::create(&opts.checkpoint, files).unwrap_or_else(|e| {
+            fatal(
+                1,
+                format!(
+                    "Unable to write the checkpoint {}, reason {}",
+                    opts.checkpoint, e
+                ),
+            );
+        })
+    });
+    (checkpoint, resumed)
+}
+
+/// Add a file finished before the interruption as process_file would have, carrying it over to
+/// the new checkpoint.
+fn resume_file(
+    record: Record<Signatures>,
+    opts: &Options,
+    file_options: &FileOptions,
+    file_hashes: &Mutex<SignatureArena>,
+    collision_hashes: &DashMap<u64, Vec<(u32, u32)>>,
+    checkpoint: Option<&Checkpoint>,
+) {
+    if let Some(checkpoint) = checkpoint {
+        let carried = Record {
+            fid: record.fid,
+            settings: record.settings,
+            signatures: record.signatures.as_ref(),
+        };
+        if let Err(e) = checkpoint.record(&carried) {
+            fatal(1, format!("Unable to write the checkpoint, reason {}", e));
+        }
+    }
+
+    match record.signatures {
+        Some(file_signatures) => add_signatures(
+            record.fid,
+            file_signatures,
+            file_options.get(record.fid, opts),
+            file_hashes,
+            collision_hashes,
+        ),
+        None => {
+            GENERATED_SKIPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// The value of an option which duplihere.toml can also set, parsed into this to tell whether it
+/// was given on the command line, it then takes precedence over duplihere.toml.
+struct Given<T> {
+    value: T,
+    given: bool,
+}
+
+impl<T> Given<T> {
+    fn new(value: T) -> Given<T> {
+        Given {
+            value,
+            given: false,
+        }
+    }
+}
+
+impl<T: FromStr> FromStr for Given<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Given {
+            value: s.parse()?,
+            given: true,
+        })
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Given<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+/// Apply the duplihere.toml files in the scan root and below it to the files.  Collisions are
+/// found with windows of the smallest --lines in effect, so opts.lines is lowered to it and the
+/// --lines of each file recorded in the file tags to leave out the shorter copies afterwards.
+/// Files with their own normalization get options of their own.  With --per-language-thresholds
+/// a file without lines from a duplihere.toml gets the lines of its language.
+fn apply_dir_configs(
+    files_to_process: &[(u32, String)],
+    opts: &mut Options,
+    file_tags: &mut FileTags,
+) -> FileOptions {
+    let mut rc = FileOptions::default();
+    let mut configs = if opts.no_dir_config {
+        None
+    } else {
+        scan_root(opts).ok().map(DirConfigs::new)
+    };
+    let lines_given = opts.given.contains("lines");
+    let normalize_given = opts.given.contains("normalize");
+    let unicode_given = opts.given.contains("normalize-unicode");
+    let ignore_case_given = opts.given.contains("ignore-case");
+
+    let mut found: Vec<(u32, Settings)> = vec![];
+    for (fid, name) in files_to_process {
+        let mut settings = match configs.as_mut().map(|c| c.settings(Path::new(name))) {
+            Some(Ok(s)) => s,
+            Some(Err(e)) => {
+                fatal(2, e);
+            }
+            None => Settings::default(),
+        };
+        if settings.lines == Some(0) {
+            fatal(
+                2,
+                format!(
+                    "lines must be at least 1 in the {} for {}",
+                    dir_config::CONFIG_FILE_NAME,
+                    name
+                ),
+            );
+        }
+        if lines_given {
+            settings.lines = None;
+        }
+        if settings.lines.is_none() && opts.per_language_thresholds {
+            settings.lines = language_lines::lines_of(name, &opts.language_table);
+        }
+        if normalize_given {
+            settings.normalize = None;
+        }
+        if unicode_given {
+            settings.normalize_unicode = None;
+        }
+        if ignore_case_given {
+            settings.ignore_case = None;
+        }
+        if settings != Settings::default() {
+            found.push((*fid, settings));
+        }
+    }
+
+    if found.iter().any(|f| f.1.lines.is_some()) {
+        file_tags.lines = files_to_process.iter().map(|f| (f.0, opts.lines)).collect();
+        for (fid, settings) in &found {
+            if let Some(lines) = settings.lines {
+                file_tags.lines.insert(*fid, lines);
+                opts.lines = opts.lines.min(lines);
+            }
+        }
+    }
+
+    let mut sets: HashMap<Settings, usize> = HashMap::new();
+    for (fid, settings) in found {
+        let normalization = Settings {
+            lines: None,
+            ..settings
+        };
+        if normalization == Settings::default() {
+            continue;
+        }
+        let i = *sets.entry(normalization).or_insert_with(|| {
+            let mut file_opts = opts.clone();
+            file_opts.normalize = settings.normalize.unwrap_or(opts.normalize);
+            file_opts.normalize_unicode =
+                settings.normalize_unicode.unwrap_or(opts.normalize_unicode);
+            file_opts.ignore_case = settings.ignore_case.unwrap_or(opts.ignore_case);
+            if !file_opts.boilerplate.is_empty() {
+                file_opts.boilerplate_hashes =
+                    get_boilerplate_hashes(&file_opts.boilerplate, &file_opts);
+            }
+            rc.sets.push(file_opts);
+            rc.sets.len() - 1
+        });
+        rc.of_file.insert(fid, i);
+    }
+    rc
+}
+
+/// We have all the data, we now need to do some sorting and duplicate removals and then
+/// dump the end data.
+fn process_report(
+    results_hash: DashMap<u64, Collision>,
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    file_tags: &FileTags,
+    file_lines: &[(u32, usize)],
+) {
+    let mut printable_results = prepare_results(results_hash, opts, file_tags);
+
+    // Number the duplicates before anything is written, ignored ones aren't reported and get
+    // none, so the ids of those which are don't have gaps.
+    let mut id = 0;
+    for p in printable_results.iter_mut() {
+        if ignore_hashes.contains_key(&p.key) {
+            p.id = 0;
+        } else {
+            id += 1;
+            p.id = id;
+        }
+    }
+
+    if !opts.group_output.is_empty() {
+        emit_groups(&printable_results, opts, ignore_hashes);
+    }
+
+    if opts.report_relative_savings {
+        relative_savings(&mut printable_results, opts, ignore_hashes);
+    }
+
+    if !opts.signatures_only.is_empty() {
+        write_signatures(&printable_results, opts, ignore_hashes);
+    }
+
+    if !opts.emit_ignore_template.is_empty() {
+        if let Err(e) = write_ignore_template(&printable_results, opts, ignore_hashes) {
+            fatal(
+                1,
+                format!(
+                    "Unable to write ignore template {}, reason {}",
+                    opts.emit_ignore_template, e
+                ),
+            );
+        }
+    }
+
+    if !opts.files_manifest.is_empty() {
+        if let Err(e) = write_files_manifest(&printable_results, opts, ignore_hashes, file_lines) {
+            fatal(
+                1,
+                format!(
+                    "Unable to write files manifest {}, reason {}",
+                    opts.files_manifest, e
+                ),
+            );
+        }
+    }
+
+    if !opts.summary_json.is_empty() {
+        write_summary(&printable_results, opts, ignore_hashes);
+    }
+
+    if opts.tui {
+        browse(&printable_results, opts, ignore_hashes);
+        return;
+    }
+
+    if !opts.output_split_by_severity.is_empty() {
+        if let Err(e) = write_by_severity(&printable_results, opts, ignore_hashes) {
+            fatal(
+                1,
+                format!(
+                    "Unable to write the reports by severity to {}, reason {}",
+                    opts.output_split_by_severity, e
+                ),
+            );
+        }
+    }
+
+    let mut sink = Sink::new(opts);
+    let rc = write_report(&printable_results, opts, ignore_hashes, &mut sink)
+        .and_then(|check_failed| sink.finish().map(|_| check_failed));
+    match rc {
+        Ok(true) => process::exit(EXIT_CHECK_FAILED),
+        Ok(false) => {}
+        Err(e) => {
+            fatal(1, format!("Unable to write {}, reason {}", opts.output, e));
+        }
+    }
+}
+
+/// Find the collisions among the locations of every window hash, in whichever way the options
+/// call for.
+fn collide(
+    collision_hashes: DashMap<u64, Vec<(u32, u32)>>,
+    file_hashes: &SignatureArena,
+    files_to_process: &[(u32, String)],
+    opts: &Options,
+) -> DashMap<u64, Collision> {
+    let results = collide_exact(collision_hashes, file_hashes, files_to_process, opts);
+    if opts.detect_rotations {
+        reorder::find_collisions(file_hashes, files_to_process, opts.lines, &results);
+    }
+    if opts.fingerprint_mode == FingerprintMode::Position {
+        by_position(results, opts.max_overlap_ratio)
+    } else {
+        results
+    }
+}
+
+/// Group the copies by where they are rather than by their text, for --fingerprint-mode
+/// position.  The ways of finding collisions all gather every copy of the same text into one,
+/// here each pair of copies becomes a duplicate of its own, keyed by its text and the two
+/// locations.  n copies of a block are n * (n - 1) / 2 duplicates.
+fn by_position(
+    results: DashMap<u64, Collision>,
+    max_overlap_ratio: f64,
+) -> DashMap<u64, Collision> {
+    let rc = DashMap::new();
+    for (_, mut c) in results {
+        c.files.sort_unstable();
+        c.files.dedup();
+        for (i, l) in c.files.iter().enumerate() {
+            for r in &c.files[i + 1..] {
+                if l.0 == r.0 && overlap(*l, *r, c.num_lines, max_overlap_ratio) {
+                    continue;
+                }
+                rc.insert(
+                    calculate_hash(&(c.key, l, r)),
+                    Collision {
+                        files: vec![*l, *r],
+                        ..c.clone()
+                    },
+                );
+            }
+        }
+    }
+    rc
+}
+
+/// The exact duplicates, whichever way they are found.
+fn collide_exact(
+    collision_hashes: DashMap<u64, Vec<(u32, u32)>>,
+    file_hashes: &SignatureArena,
+    files_to_process: &[(u32, String)],
+    opts: &Options,
+) -> DashMap<u64, Collision> {
+    if opts.whole_functions != Language::None {
+        let results = DashMap::new();
+        functions::find_collisions(collision_hashes, file_hashes, opts.lines, &results);
+        results
+    } else if opts.suffix_array && files_to_process.len() == 1 {
+        // One file, the suffix array approach avoids the pairwise compares.
+        let results = DashMap::new();
+        let fid = files_to_process[0].0;
+        single_file::find_collisions(
+            fid,
+            file_hashes.get(fid),
+            opts.lines,
+            opts.max_overlap_ratio,
+            &results,
+        );
+        results
+    } else {
+        let cpu_threads = if opts.threads == Threads::Auto && opts.cpu_threads == 0 {
+            let threads = auto_threads(&collision_hashes);
+            if opts.verbose {
+                eprintln!(
+                    "--threads auto: finding collisions with {} threads",
+                    threads
+                );
+            }
+            threads
+        } else {
+            opts.cpu_threads
+        };
+        let cpu_pool = phase_pool(cpu_threads);
+        in_pool(&cpu_pool, || {
+            find_collisions(collision_hashes, file_hashes, opts)
+        })
+    }
+}
+
+/// Scan the files and find the duplicates with the options, returning how many are reported and
+/// their duplicated line count, as in the summary.
+fn count_duplicates(
+    files_to_process: &[(u32, String)],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    file_tags: &FileTags,
+    io_pool: &Option<rayon::ThreadPool>,
+) -> (usize, u64) {
+    // Left by an earlier scan with other options.  Warnings are only recorded once, the counts
+    // start again.
+    FILE_LOOKUP.lock().unwrap().line_maps.clear();
+    GENERATED_SKIPPED.store(0, Ordering::Relaxed);
+    LINES_SCANNED.store(0, Ordering::Relaxed);
+
+    let collision_hashes: DashMap<u64, Vec<(u32, u32)>> = DashMap::new();
+    let file_hashes: Mutex<SignatureArena> =
+        Mutex::new(SignatureArena::new(files_to_process.len()));
+    in_pool(io_pool, || {
+        files_to_process.par_iter().for_each(|e| {
+            process_file(e.0, &e.1, opts, &file_hashes, &collision_hashes, None);
+        })
+    });
+    let results_hash = collide(
+        collision_hashes,
+        &file_hashes.lock().unwrap(),
+        files_to_process,
+        opts,
+    );
+
+    let mut counted_lines: HashMap<u32, HashSet<u32>> = HashMap::new();
+    let reported: Vec<Collision> = prepare_results(results_hash, opts, file_tags)
+        .into_iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+        .collect();
+    let num_lines = reported
+        .iter()
+        .map(|p| p.counted_lines(opts, &mut counted_lines))
+        .sum();
+    (reported.len(), num_lines)
+}
+
+/// For --compare-normalized, find the duplicates with the normalization options and without
+/// them and print how they differ to stderr.
+fn compare_normalized(
+    files_to_process: &[(u32, String)],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    file_tags: &FileTags,
+    io_pool: &Option<rayon::ThreadPool>,
+) {
+    let mut raw = opts.clone();
+    raw.normalize = Normalize::None;
+    raw.normalize_unicode = UnicodeForm::None;
+    raw.ignore_case = false;
+    raw.alpha_rename = false;
+    if !raw.boilerplate.is_empty() {
+        raw.boilerplate_hashes = get_boilerplate_hashes(&raw.boilerplate, &raw);
+    }
+
+    let (raw_duplicates, raw_lines) =
+        count_duplicates(files_to_process, &raw, ignore_hashes, file_tags, io_pool);
+    let (duplicates, lines) =
+        count_duplicates(files_to_process, opts, ignore_hashes, file_tags, io_pool);
+
+    eprintln!("{:<12}{:>12}{:>12}", "", "duplicates", "lines");
+    eprintln!("{:<12}{:>12}{:>12}", "raw", raw_duplicates, raw_lines);
+    eprintln!("{:<12}{:>12}{:>12}", "normalized", duplicates, lines);
+    eprintln!(
+        "{:<12}{:>12}{:>12}",
+        "difference",
+        format!("{:+}", duplicates as i64 - raw_duplicates as i64),
+        format!("{:+}", lines as i64 - raw_lines as i64)
+    );
+}
+
+/// Sort the collisions, remove the ones which are really part of another and those the options
+/// leave out.
+fn prepare_results(
+    results_hash: DashMap<u64, Collision>,
+    opts: &Options,
+    file_tags: &FileTags,
+) -> Vec<Collision> {
+    let mut final_report: Vec<Collision> = results_hash.into_iter().map(|(_, v)| v).collect();
+    final_report.par_sort_unstable_by(|a, b| a.num_lines.cmp(&b.num_lines).reverse());
+
+    let mut printable_results: Vec<Collision> = Vec::new();
+
+    {
+        // Results are keyed by the hash of their text, so identical text found at different
+        // places has already been merged into one collision by walk_collision, or with
+        // --fingerprint-mode position split into one for each pair of places by by_position.
+        // What is left to remove are the shorter tails of a longer duplicate, which end at the
+        // same lines, so we de-duplicate on the position based signature, either way.
+        let mut chunk_processed: HashMap<u64, bool> = HashMap::new();
+
+        final_report
+            .par_iter_mut()
+            .for_each(|ea| ea.scrub(opts.max_overlap_ratio, opts.sort_locations_by));
+
+        for ea in final_report {
+            let cs = ea.signature();
+            if let Entry::Vacant(e) = chunk_processed.entry(cs) {
+                e.insert(true);
+                printable_results.push(ea);
+            }
+        }
+    }
+
+    if !file_tags.lines.is_empty() {
+        // A copy only counts in a file whose duplihere.toml --lines it meets.
+        for c in printable_results.iter_mut() {
+            let num_lines = c.num_lines;
+            c.files
+                .retain(|f| num_lines >= file_tags.lines.get(&f.0).copied().unwrap_or(0));
+        }
+        printable_results.retain(|c| c.files.len() > 1);
+    }
+
+    printable_results.par_sort_unstable_by(|a, b| {
+        if a.num_lines == b.num_lines {
             if a.files[0].1 == b.files[0].1 {
                 a.files[0].0.cmp(&b.files[0].0)
             } else {
@@ -483,212 +2831,3198 @@ fn process_report(
         }
     });
 
-    print_report(&printable_results, opts, ignore_hashes);
+    if opts.collapse_consecutive {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        for p in printable_results.iter_mut() {
+            p.find_repeats(&file_lookup_locked);
+        }
+    }
+
+    if opts.fingerprint_mode == FingerprintMode::Position {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        for p in printable_results.iter_mut() {
+            p.key = position_key(p, &file_lookup_locked);
+        }
+    }
+
+    if !opts.salt.is_empty() {
+        for p in printable_results.iter_mut() {
+            p.key = salted_key(p.key, &opts.salt);
+        }
+    }
+
+    if !opts.deny_list.is_empty() {
+        // Only copies of a deny list snippet in a scanned file are of interest.
+        printable_results.retain(|c| {
+            c.files.iter().any(|f| file_tags.deny.contains(&f.0))
+                && c.files.iter().any(|f| !file_tags.deny.contains(&f.0))
+        });
+    }
+
+    if !opts.left.is_empty() {
+        // Only copies between the two trees are of interest.
+        printable_results.retain(|c| {
+            c.files.iter().any(|f| file_tags.left.contains(&f.0))
+                && c.files.iter().any(|f| file_tags.right.contains(&f.0))
+        });
+    }
+
+    if opts.ignore_tests {
+        drop_test_only(&mut printable_results, opts);
+    }
+
+    if opts.min_distinct_dirs > 1 {
+        drop_few_dirs(&mut printable_results, opts);
+    }
+
+    if opts.note_whitespace_diffs
+        || opts.flag_trailing_ws
+        || opts.flag_reindent
+        || opts.location_hashes
+    {
+        verify_copies(&mut printable_results, opts);
+    }
+    printable_results
+}
+
+/// Browse the duplicates which aren't ignored with --tui.  Those the user marks are added to the
+/// ignore file, or printed when there isn't one.
+#[cfg(feature = "tui")]
+fn browse(printable_results: &[Collision], opts: &Options, ignore_hashes: &HashMap<u64, bool>) {
+    let separator = opts.record_separator.0;
+    let buffer_size = opts.read_buffer_size;
+    let mut entries: Vec<tui::Entry> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+            .map(|p| {
+                let regions: Vec<(Arc<str>, u32, u32)> = p
+                    .files
+                    .iter()
+                    .map(|f| {
+                        let (start, end) = file_lookup_locked.line_range(f.0, f.1, p.num_lines);
+                        (file_lookup_locked.id_to_name(f.0), start, end)
+                    })
+                    .collect();
+                let (name, start, end) = regions[0].clone();
+                tui::Entry {
+                    key: p.key,
+                    num_lines: p.num_lines,
+                    locations: regions
+                        .iter()
+                        .map(|(name, start, end)| format!("{}:{}-{}", name, start + 1, end))
+                        .collect(),
+                    text: Box::new(move || {
+                        read_region(
+                            &name,
+                            start as usize,
+                            (end - start) as usize,
+                            separator,
+                            buffer_size,
+                        )
+                        .iter()
+                        .map(|l| {
+                            String::from_utf8_lossy(l)
+                                .trim_end_matches(|c| {
+                                    c == '\n' || c == '\r' || c == separator as char
+                                })
+                                .replace('\t', "    ")
+                        })
+                        .collect()
+                    }),
+                }
+            })
+            .collect()
+    };
+    // Largest first, the sort is stable so otherwise in report order.
+    entries.sort_by_key(|e| std::cmp::Reverse(e.num_lines));
+
+    let marked = tui::run(&entries).unwrap_or_else(|e| {
+        fatal(1, format!("Unable to run the TUI, reason {}", e));
+    });
+    if marked.is_empty() {
+        return;
+    }
+
+    let keys: String = marked.iter().map(|k| format!("{}\n", k)).collect();
+    if opts.ignore.is_empty() {
+        print!("{}", keys);
+        return;
+    }
+    let rc = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&opts.ignore)
+        .and_then(|mut f| f.write_all(keys.as_bytes()));
+    match rc {
+        Ok(_) => eprintln!("Added {} hash signatures to {}", marked.len(), opts.ignore),
+        Err(e) => {
+            fatal(1, format!("Unable to add to {}, reason {}", opts.ignore, e));
+        }
+    }
 }
 
-/// Open the user supplied file which contains the hash signatures for text that we don't
-/// want to report on.
-fn get_ignore_hashes(file_name: &str) -> HashMap<u64, bool> {
-    let mut ignores: HashMap<u64, bool> = HashMap::new();
+// main refuses --tui when we are built without the tui feature.
+#[cfg(not(feature = "tui"))]
+fn browse(_: &[Collision], _: &Options, _: &HashMap<u64, bool>) {}
 
-    let fh = File::open(file_name);
+/// Write a report of the duplicates of each severity to a file of its own in the
+/// --output-split-by-severity directory, named for the severity, eg. critical.json with --json
+/// and critical.txt otherwise.  Each is the report print_report writes, in the format selected,
+/// for just those duplicates, the HTML, DOT and other extra outputs only come from the main
+/// report.
+fn write_by_severity(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) -> io::Result<()> {
+    let mut split_opts = opts.clone();
+    split_opts.syslog = false;
+    split_opts.html.clear();
+    split_opts.dot.clear();
+    split_opts.extract_to.clear();
 
-    match fh {
-        Ok(fh) => {
-            let buf = BufReader::new(fh);
+    std::fs::create_dir_all(&opts.output_split_by_severity)?;
+    for severity in Severity::ALL {
+        let results: Vec<Collision> = printable_results
+            .iter()
+            .filter(|p| Severity::classify(p.num_lines, opts.lines) == severity)
+            .cloned()
+            .collect();
+        let name = format!(
+            "{}.{}",
+            severity,
+            if opts.json && !opts.quickfix {
+                "json"
+            } else {
+                "txt"
+            }
+        );
+        let mut out = BufWriter::new(File::create(
+            Path::new(&opts.output_split_by_severity).join(name),
+        )?);
+        print_report(&results, &split_opts, ignore_hashes, &mut out)?;
+        out.flush()?;
+    }
+    Ok(())
+}
 
-            for line in buf.lines() {
-                let t = line.unwrap();
-                let l = t.trim();
+/// Write the --summary-json file, whichever report is written.
+fn write_summary(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) {
+    let mut num_lines: u64 = 0;
+    let mut num_lines_within_file: u64 = 0;
+    let mut ignored: u64 = 0;
+    let mut counted_lines: HashMap<u32, HashSet<u32>> = HashMap::new();
+    for p in printable_results {
+        if ignore_hashes.contains_key(&p.key) {
+            ignored += 1;
+        } else {
+            let removable = p.counted_lines(opts, &mut counted_lines);
+            num_lines += removable;
+            if p.within_one_file() {
+                num_lines_within_file += removable;
+            }
+        }
+    }
+
+    let lines_scanned = LINES_SCANNED.load(Ordering::Relaxed);
+    let summary = Summary {
+        version: REPORT_VERSION,
+        generated_at: generated_at(),
+        num_lines,
+        num_lines_cross_file: num_lines - num_lines_within_file,
+        num_lines_within_file,
+        num_duplicates: printable_results.len() - ignored as usize,
+        num_files: FILE_LOOKUP.lock().unwrap().number_files(),
+        num_ignored: ignored,
+        num_generated_skipped: GENERATED_SKIPPED.load(Ordering::Relaxed),
+        num_lines_scanned: lines_scanned,
+        duplicated_ratio: if lines_scanned == 0 {
+            0.0
+        } else {
+            num_lines as f64 / lines_scanned as f64
+        },
+        context: (!opts.report_context.is_empty()).then_some(&opts.report_context),
+    };
+    let written = File::create(&opts.summary_json).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        serde_json::to_writer_pretty(&mut w, &summary)?;
+        writeln!(w)?;
+        w.flush()
+    });
+    if let Err(e) = written {
+        fatal(
+            1,
+            format!(
+                "Unable to write summary {}, reason {}",
+                opts.summary_json, e
+            ),
+        );
+    }
+}
+
+/// Write the report, or what was asked for instead of it, to out.  True when a check failed,
+/// --validate-ignore --strict found stale entries, something matched the --deny-list or a
+/// duplicate has a copy in a --fail-on-file file.
+fn write_report(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    out: &mut dyn Write,
+) -> io::Result<bool> {
+    if opts.validate_ignore {
+        let stale = validate_ignore(printable_results, ignore_hashes, out)?;
+        return Ok(opts.strict && stale != 0);
+    }
+
+    if opts.report_only_files {
+        report_files(printable_results, opts, ignore_hashes, out)?;
+        return Ok(false);
+    }
+
+    if opts.emit_line_coverage {
+        line_coverage(printable_results, ignore_hashes, out)?;
+        return Ok(false);
+    }
+
+    if opts.fix_plan {
+        fix_plan(printable_results, opts, ignore_hashes, out)?;
+        return Ok(false);
+    }
+
+    print_report(printable_results, opts, ignore_hashes, out)?;
+
+    let deny_listed = !opts.deny_list.is_empty()
+        && printable_results
+            .iter()
+            .any(|p| !ignore_hashes.contains_key(&p.key));
+    let critical =
+        !opts.fail_on_file.is_empty() && in_critical_files(printable_results, opts, ignore_hashes);
+    Ok(deny_listed || critical)
+}
+
+/// The directory relative globs are resolved against and which .duplihereignore and
+/// duplihere.toml files apply below, --base-dir or the current directory, canonical.
+fn scan_root(opts: &Options) -> io::Result<PathBuf> {
+    if opts.base_dir.is_empty() {
+        std::env::current_dir().and_then(canonicalize)
+    } else {
+        canonicalize(&opts.base_dir)
+    }
+}
+
+/// Check for duplicates with a copy in a file matching --fail-on-file, printing each to stderr.
+/// Relative patterns are relative to the scan root, as with -f.
+fn in_critical_files(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) -> bool {
+    let cwd = scan_root(opts)
+        .map(|d| Pattern::escape(&d.to_string_lossy()))
+        .unwrap_or_default();
+    let patterns: Vec<Pattern> = opts
+        .fail_on_file
+        .iter()
+        .map(|p| {
+            let full = if Path::new(p).is_absolute() {
+                p.clone()
+            } else {
+                format!("{}/{}", cwd, p)
+            };
+            Pattern::new(&full).unwrap_or_else(|e| {
+                fatal(
+                    1,
+                    format!("Bad --fail-on-file pattern supplied '{}', error: {}", p, e),
+                );
+            })
+        })
+        .collect();
+
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    let mut found = false;
+    for p in printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+    {
+        let critical = p.files.iter().find(|f| {
+            let name = file_lookup_locked.id_to_name(f.0);
+            patterns.iter().any(|pat| pat.matches(&name))
+        });
+        if let Some(f) = critical {
+            eprintln!(
+                "Duplicate {} has a copy in {}, which matches --fail-on-file",
+                p.id,
+                file_lookup_locked.id_to_name(f.0)
+            );
+            found = true;
+        }
+    }
+    found
+}
+
+/// Line which separates the snippets in a --deny-list file.
+const DENY_LIST_SEPARATOR: &str = "---";
+
+/// Read the snippets from a --deny-list file, returning a name for each, eg. "deny.txt:snippet 2",
+/// and its text.
+fn read_deny_list(file_name: &str, min_lines: u32) -> Vec<(String, Vec<u8>)> {
+    let content = std::fs::read(file_name).unwrap_or_else(|e| {
+        fatal(
+            2,
+            format!("Unable to open deny list {}, reason {}", file_name, e),
+        );
+    });
+
+    let mut snippets: Vec<Vec<u8>> = vec![vec![]];
+    for line in content.split_inclusive(|b| *b == b'\n') {
+        if String::from_utf8_lossy(line).trim() == DENY_LIST_SEPARATOR {
+            snippets.push(vec![]);
+        } else {
+            snippets.last_mut().unwrap().extend_from_slice(line);
+        }
+    }
+
+    let mut rc = vec![];
+    for (i, snippet) in snippets.into_iter().enumerate() {
+        let num_lines = snippet
+            .split(|b| *b == b'\n')
+            .filter(|l| !String::from_utf8_lossy(l).trim().is_empty())
+            .count();
+        if num_lines == 0 {
+            continue;
+        }
+        let name = format!("{}:snippet {}", file_name, i + 1);
+        if num_lines < min_lines as usize {
+            eprintln!(
+                "WARNING: {} has {} lines, fewer than --lines {}, it can't match",
+                name, num_lines, min_lines
+            );
+        }
+        rc.push((name, snippet));
+    }
+    rc
+}
+
+/// Most threads worth using to find collisions, past this dashmap contention means they add
+/// little.
+const AUTO_MAX_THREADS: usize = 4;
+
+/// Pick the number of threads to find collisions with for --threads auto, from how many pairs of
+/// locations will be compared.  With little work the cost of starting threads and contending on
+/// dashmap outweighs the gain.
+fn auto_threads(collision_hash: &DashMap<u64, Vec<(u32, u32)>>) -> usize {
+    let pairs: u64 = collision_hash
+        .iter()
+        .map(|e| {
+            let n = e.value().len() as u64;
+            n * n.saturating_sub(1) / 2
+        })
+        .sum();
+    let cores = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+
+    let wanted = if pairs < 10_000 {
+        1
+    } else if pairs < 1_000_000 {
+        2
+    } else {
+        AUTO_MAX_THREADS
+    };
+    wanted.min(cores)
+}
+
+/// A thread pool for one phase of the work, None to use the global pool sized by --threads.
+fn phase_pool(threads: usize) -> Option<rayon::ThreadPool> {
+    if threads == 0 {
+        None
+    } else {
+        Some(
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap(),
+        )
+    }
+}
+
+/// Run f in the pool if there is one, otherwise in the global pool.
+fn in_pool<R: Send>(pool: &Option<rayon::ThreadPool>, f: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(p) => p.install(f),
+        None => f(),
+    }
+}
+
+/// Write the hash signature of every duplicate which isn't ignored to the --signatures-only
+/// file, one per line.  They are sorted as text, not numerically, so the file can be compared
+/// with comm(1).
+fn write_signatures(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) {
+    let mut keys: Vec<String> = printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+        .map(|p| p.key.to_string())
+        .collect();
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut content = keys.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    if let Err(e) = std::fs::write(&opts.signatures_only, content) {
+        fatal(
+            1,
+            format!(
+                "Unable to write signatures to {}, reason {}",
+                opts.signatures_only, e
+            ),
+        );
+    }
+}
+
+/// The settings which affect the line signatures and their values.
+fn signature_settings(opts: &Options) -> [(&'static str, String); 12] {
+    [
+        ("hash-seed", opts.hash_seed.to_string()),
+        ("record-separator", opts.record_separator.to_string()),
+        ("skip-directive", opts.skip_directive.clone()),
+        ("boilerplate", opts.boilerplate.clone()),
+        ("first-n-lines", opts.first_n_lines.to_string()),
+        ("ignore-first-line", opts.ignore_first_line.to_string()),
+        (
+            "strip-license-header",
+            opts.strip_license_header.to_string(),
+        ),
+        (
+            "license-header-lines",
+            opts.license_header_lines.to_string(),
+        ),
+        ("normalize", opts.normalize.to_string()),
+        ("normalize-unicode", opts.normalize_unicode.to_string()),
+        ("ignore-case", opts.ignore_case.to_string()),
+        ("alpha-rename", opts.alpha_rename.to_string()),
+    ]
+}
+
+/// A hash of the settings which affect what's recorded of a file in a checkpoint, the line
+/// signatures and with them the functions, boundaries and whether the file is skipped as
+/// generated.
+fn settings_hash(opts: &Options) -> u64 {
+    calculate_hash(&(
+        signature_settings(opts),
+        opts.whole_functions.to_string(),
+        opts.treat_includes_as_boundaries,
+        opts.blank_line_boundary,
+        opts.ignore_generated,
+        &opts.generated_markers,
+    ))
+}
+
+/// Set the --report-relative-savings of each duplicate which isn't ignored, its lines counted
+/// in the summary as a percentage of the total.  They're counted in report order, as the
+/// summary does, so with --dedup-identical-locations or --line-count-mode involved a line
+/// shared by duplicates goes to the first and the percentages still add up to 100.
+fn relative_savings(
+    printable_results: &mut [Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) {
+    let mut counted_lines: HashMap<u32, HashSet<u32>> = HashMap::new();
+    let removable: Vec<Option<u64>> = printable_results
+        .iter()
+        .map(|p| {
+            (!ignore_hashes.contains_key(&p.key)).then(|| p.counted_lines(opts, &mut counted_lines))
+        })
+        .collect();
+    let total: u64 = removable.iter().flatten().sum();
+    for (p, r) in printable_results.iter_mut().zip(removable) {
+        p.savings = r.map(|r| {
+            if total == 0 {
+                0.0
+            } else {
+                r as f64 * 100.0 / total as f64
+            }
+        });
+    }
+}
+
+/// A scanned file for --files-manifest, the number of lines scanned and whether it has a copy
+/// of any duplicate which isn't ignored.
+#[derive(Serialize)]
+struct ManifestFile {
+    file: String,
+    lines: usize,
+    duplicated: bool,
+}
+
+/// Write the --files-manifest file, every file scanned in name order, as JSON with --json and
+/// CSV otherwise.
+fn write_files_manifest(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    file_lines: &[(u32, usize)],
+) -> io::Result<()> {
+    let duplicated: HashSet<u32> = printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+        .flat_map(|p| p.files.iter().map(|f| f.0))
+        .collect();
+
+    let mut files: Vec<ManifestFile> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        file_lines
+            .iter()
+            .map(|(fid, lines)| ManifestFile {
+                file: if opts.json {
+                    file_lookup_locked.json_name(*fid)
+                } else {
+                    file_lookup_locked.id_to_name(*fid).to_string()
+                },
+                lines: *lines,
+                duplicated: duplicated.contains(fid),
+            })
+            .collect()
+    };
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+
+    let mut out = BufWriter::new(File::create(&opts.files_manifest)?);
+    if opts.json {
+        #[derive(Serialize)]
+        struct Manifest {
+            files: Vec<ManifestFile>,
+        }
+        serde_json::to_writer_pretty(&mut out, &Manifest { files })?;
+        writeln!(out)?;
+    } else {
+        write!(out, "{}", context_header(&opts.report_context))?;
+        writeln!(out, "file,lines,duplicated")?;
+        for f in files {
+            writeln!(
+                out,
+                "{},{},{}",
+                trend::csv_field(&f.file),
+                f.lines,
+                f.duplicated
+            )?;
+        }
+    }
+    out.flush()
+}
+
+/// Write the --emit-ignore-template file, an ignore file with the hash signature of every
+/// duplicate which isn't ignored already commented out, each after a comment saying where the
+/// duplicate is, so the ones to ignore can be uncommented.
+fn write_ignore_template(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(&opts.emit_ignore_template)?);
+    writeln!(
+        out,
+        "# duplihere ignore file, remove the '#' before the hash signature of each duplicate to ignore"
+    )?;
+
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    for p in printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+    {
+        let locations: Vec<String> = p
+            .files
+            .iter()
+            .map(|f| {
+                format!(
+                    "{}:{}",
+                    file_lookup_locked.id_to_name(f.0),
+                    file_lookup_locked.line_range(f.0, f.1, p.num_lines).0 + 1
+                )
+            })
+            .collect();
+        writeln!(out, "#")?;
+        writeln!(
+            out,
+            "# {} lines in {} places: {}",
+            p.num_lines,
+            p.files.len(),
+            locations.join(", ")
+        )?;
+        writeln!(out, "# {}", p.key)?;
+    }
+    out.flush()
+}
+
+/// Write the line signatures of every file to stdout for --emit-hashes.  A header of '#' lines
+/// records the settings which affect the signatures, then each file is a "file <name>" line
+/// followed by a "<line> <signature>" line for each of its lines which has a signature, the line
+/// numbers start at 1 and signatures are 16 hex digits.
+fn emit_hashes(
+    files: &[(u32, String)],
+    file_hashes: &SignatureArena,
+    opts: &Options,
+    file_tags: &FileTags,
+) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    writeln!(
+        out,
+        "# duplihere {} line signatures",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(
+        out,
+        "# hash SipHash-1-3 of the line without leading and trailing whitespace"
+    )?;
+    for (setting, value) in signature_settings(opts) {
+        writeln!(out, "{}", format!("# {} {}", setting, value).trim_end())?;
+    }
+
+    let file_lookup = FILE_LOOKUP.lock().unwrap();
+    for (fid, name) in files.iter().filter(|f| !file_tags.deny.contains(&f.0)) {
+        let hashes = file_hashes.get(*fid);
+        if hashes.is_empty() {
+            continue;
+        }
+        writeln!(out, "file {}", name)?;
+        for (i, h) in hashes.iter().enumerate() {
+            let line = file_lookup.line_range(*fid, i as u32, 1).0;
+            writeln!(out, "{} {:016x}", line + 1, h)?;
+        }
+    }
+    out.flush()
+}
+
+/// Print the n files with the most lines to stderr, for --largest-files.  Lines left out of the
+/// scan, eg. ones with the skip directive, aren't counted.
+fn print_largest_files(
+    files: &[(u32, String)],
+    file_hashes: &SignatureArena,
+    file_tags: &FileTags,
+    n: usize,
+) {
+    let mut sizes: Vec<(usize, &str)> = files
+        .iter()
+        .filter(|f| !file_tags.deny.contains(&f.0))
+        .map(|f| (file_hashes.get(f.0).len(), f.1.as_str()))
+        .collect();
+    sizes.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+    eprintln!("Largest files by lines scanned:");
+    eprintln!("{:>10} file", "lines");
+    for (lines, name) in sizes.iter().take(n) {
+        eprintln!("{:>10} {}", lines, name);
+    }
+}
+
+/// Print to stderr how many windows of --lines the scan produced and how many of them collide,
+/// for --dump-collision-hash-stats.  Windows which repeat the one before them aren't recorded and
+/// aren't counted.
+fn print_collision_hash_stats(collision_hash: &DashMap<u64, Vec<(u32, u32)>>, lines: u32) {
+    let mut windows: u64 = 0;
+    let mut colliding: u64 = 0;
+    let mut colliding_windows: u64 = 0;
+    let mut largest: usize = 0;
+    for e in collision_hash.iter() {
+        let n = e.value().len();
+        windows += n as u64;
+        if n > 1 {
+            colliding += 1;
+            colliding_windows += n as u64;
+        }
+        largest = largest.max(n);
+    }
+
+    eprintln!("Collision hash statistics for --lines {}:", lines);
+    eprintln!("{:>12} windows", windows);
+    eprintln!("{:>12} distinct window hashes", collision_hash.len());
+    eprintln!("{:>12} hashes with more than one window", colliding);
+    eprintln!("{:>12} windows in them", colliding_windows);
+    eprintln!("{:>12} windows in the largest", largest);
+}
+
+/// A file picked by --fix-plan, the duplicated lines removing its copies saves on top of the files
+/// picked before it and the lines saved by it and those together.
+#[derive(Serialize)]
+struct PlanStep {
+    file: String,
+    lines_saved: u64,
+    cumulative: u64,
+}
+
+/// For --fix-plan, pick the files whose copies of duplicates would remove the most duplicated
+/// lines, one at a time, each the file which saves the most on top of the ones picked before.
+/// A duplicate of n lines with c copies saves n for each copy removed, until one copy is left.
+/// Picking the smallest set of files is NP-hard, this is the greedy approximation.  The gain of
+/// a file only goes down as others are picked, so stale gains in the heap are recomputed when
+/// they come to the top rather than after every pick.
+fn fix_plan(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let reported: Vec<&Collision> = printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+        .collect();
+
+    // Index of the duplicate and the number of its copies, for each file.
+    let mut copies_in: HashMap<u32, BTreeMap<usize, u64>> = HashMap::new();
+    for (d, p) in reported.iter().enumerate() {
+        for f in &p.files {
+            *copies_in.entry(f.0).or_default().entry(d).or_default() += 1;
+        }
+    }
+    // Copies of each duplicate which can still be removed.
+    let mut removable: Vec<u64> = reported.iter().map(|p| p.files.len() as u64 - 1).collect();
+    let gain = |fid: u32, removable: &[u64]| -> u64 {
+        copies_in[&fid]
+            .iter()
+            .map(|(&d, &count)| reported[d].num_lines as u64 * count.min(removable[d]))
+            .sum()
+    };
+
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    let mut heap: BinaryHeap<(u64, std::cmp::Reverse<Arc<str>>, u32)> = copies_in
+        .keys()
+        .map(|&fid| {
+            (
+                gain(fid, &removable),
+                std::cmp::Reverse(file_lookup_locked.id_to_name(fid)),
+                fid,
+            )
+        })
+        .collect();
+
+    let mut plan: Vec<PlanStep> = vec![];
+    let mut cumulative: u64 = 0;
+    while let Some((stale, name, fid)) = heap.pop() {
+        let current = gain(fid, &removable);
+        if current == 0 {
+            continue;
+        }
+        if current < stale {
+            heap.push((current, name, fid));
+            continue;
+        }
+        for (&d, &count) in &copies_in[&fid] {
+            removable[d] -= count.min(removable[d]);
+        }
+        cumulative += current;
+        plan.push(PlanStep {
+            file: name.0.to_string(),
+            lines_saved: current,
+            cumulative,
+        });
+    }
+    drop(file_lookup_locked);
+
+    if opts.json {
+        #[derive(Serialize)]
+        struct Plan {
+            files: Vec<PlanStep>,
+        }
+        serde_json::to_writer_pretty(&mut *out, &Plan { files: plan })?;
+        writeln!(out)
+    } else {
+        writeln!(out, "{:>6} {:>10} {:>10} file", "rank", "saved", "total")?;
+        for (i, step) in plan.iter().enumerate() {
+            writeln!(
+                out,
+                "{:>6} {:>10} {:>10} {}",
+                i + 1,
+                step.lines_saved,
+                step.cumulative,
+                step.file
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A file and how many of its lines are duplicated, for --report-only-files.
+#[derive(Serialize)]
+struct FileCount {
+    file: String,
+    duplicated_lines: usize,
+}
+
+/// For --report-only-files, list the files which have any duplicated lines, with the number of
+/// distinct lines which are part of a duplicate, most first.
+fn report_files(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut lines: HashMap<u32, HashSet<u32>> = HashMap::new();
+    for p in printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+    {
+        for f in &p.files {
+            lines.entry(f.0).or_default().extend(f.1..f.1 + p.num_lines);
+        }
+    }
+
+    let mut counts: Vec<FileCount> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        lines
+            .iter()
+            .map(|(fid, l)| FileCount {
+                file: file_lookup_locked.id_to_name(*fid).to_string(),
+                duplicated_lines: l.len(),
+            })
+            .collect()
+    };
+    counts.sort_by(|a, b| {
+        b.duplicated_lines
+            .cmp(&a.duplicated_lines)
+            .then_with(|| a.file.cmp(&b.file))
+    });
+
+    if opts.json {
+        #[derive(Serialize)]
+        struct Files {
+            files: Vec<FileCount>,
+        }
+        serde_json::to_writer_pretty(&mut *out, &Files { files: counts })?;
+        writeln!(out)
+    } else {
+        for c in counts {
+            writeln!(out, "{:>8} {}", c.duplicated_lines, c.file)?;
+        }
+        Ok(())
+    }
+}
+
+/// The lines of a file which are part of any duplicate, for --emit-line-coverage.  Ranges are of
+/// line numbers starting at 1, inclusive, in order and merged when they overlap or touch.
+#[derive(Serialize)]
+struct FileCoverage {
+    file: String,
+    ranges: Vec<(u32, u32)>,
+}
+
+/// For --emit-line-coverage, write the lines of each file covered by a duplicate which isn't
+/// ignored as JSON.
+fn line_coverage(
+    printable_results: &[Collision],
+    ignore_hashes: &HashMap<u64, bool>,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut files: Vec<FileCoverage> = {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        let mut ranges: HashMap<u32, Vec<(u32, u32)>> = HashMap::new();
+        for p in printable_results
+            .iter()
+            .filter(|p| !ignore_hashes.contains_key(&p.key))
+        {
+            for f in &p.files {
+                let (start, end) = file_lookup_locked.line_range(f.0, f.1, p.num_lines);
+                ranges.entry(f.0).or_default().push((start + 1, end));
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|(fid, mut r)| {
+                r.sort_unstable();
+                let mut merged: Vec<(u32, u32)> = vec![];
+                for (start, end) in r {
+                    match merged.last_mut() {
+                        Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                        _ => merged.push((start, end)),
+                    }
+                }
+                FileCoverage {
+                    file: file_lookup_locked.json_name(fid),
+                    ranges: merged,
+                }
+            })
+            .collect()
+    };
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+
+    #[derive(Serialize)]
+    struct Coverage {
+        files: Vec<FileCoverage>,
+    }
+    serde_json::to_writer_pretty(&mut *out, &Coverage { files })?;
+    writeln!(out)
+}
+
+/// Exit code when a check, eg. --validate-ignore --strict, fails.
+const EXIT_CHECK_FAILED: i32 = 3;
+
+/// For --validate-ignore, list the ignore file entries which still match a duplicate (active)
+/// and the ones which don't (stale) and can be removed from it.  Returns the number of stale ones.
+fn validate_ignore(
+    printable_results: &[Collision],
+    ignore_hashes: &HashMap<u64, bool>,
+    out: &mut dyn Write,
+) -> io::Result<usize> {
+    let found: HashSet<u64> = printable_results.iter().map(|p| p.key).collect();
+    let mut keys: Vec<u64> = ignore_hashes.keys().copied().collect();
+    keys.sort_unstable();
+
+    let mut stale = 0;
+    for k in keys {
+        if found.contains(&k) {
+            writeln!(out, "active {}", k)?;
+        } else {
+            writeln!(out, "stale {}", k)?;
+            stale += 1;
+        }
+    }
+    eprintln!(
+        "{} of {} ignore file entries are stale",
+        stale,
+        ignore_hashes.len()
+    );
+    Ok(stale)
+}
+
+/// Paths which hold tests and their fixtures, used by --ignore-tests along with any given with
+/// --test-pattern.
+static TEST_PATTERNS: [&str; 4] = [
+    "**/tests/**",
+    "**/__tests__/**",
+    "**/testdata/**",
+    "**/*_test.*",
+];
+
+/// Remove duplicates which are only found in test files.  Test files are still scanned, so text
+/// copied out of a test into other source is still reported.
+fn drop_test_only(printable_results: &mut Vec<Collision>, opts: &Options) {
+    let mut patterns: Vec<Pattern> = vec![];
+    for p in TEST_PATTERNS
+        .iter()
+        .copied()
+        .chain(opts.test_patterns.iter().map(|p| p.as_str()))
+    {
+        match Pattern::new(p) {
+            Ok(pattern) => patterns.push(pattern),
+            Err(e) => {
+                fatal(
+                    1,
+                    format!("Bad test pattern supplied '{}', error: {}", p, e),
+                );
+            }
+        }
+    }
+
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    let is_test: Vec<bool> = (0..file_lookup_locked.number_files())
+        .map(|i| {
+            let name = file_lookup_locked.id_to_name(i);
+            patterns.iter().any(|p| p.matches(&name))
+        })
+        .collect();
+
+    printable_results.retain(|c| !c.files.iter().all(|f| is_test[f.0 as usize]));
+}
+
+/// Remove duplicates whose copies are in fewer than --min-distinct-dirs directories.  With
+/// --dir-depth n only the first n directories below the directory all the scanned files have in
+/// common are considered, so copies in a/x and a/y are in the same directory at depth 1.
+fn drop_few_dirs(printable_results: &mut Vec<Collision>, opts: &Options) {
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    let parents: Vec<Vec<String>> = (0..file_lookup_locked.number_files())
+        .map(|i| {
+            let name = file_lookup_locked.id_to_name(i);
+            Path::new(&*name)
+                .parent()
+                .map(|p| {
+                    p.components()
+                        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut common = parents.first().map(|p| p.len()).unwrap_or(0);
+    for p in &parents {
+        common = common.min(p.len());
+        while common > 0 && p[..common] != parents[0][..common] {
+            common -= 1;
+        }
+    }
+
+    let dirs: Vec<&[String]> = parents
+        .iter()
+        .map(|p| {
+            if opts.dir_depth == 0 {
+                &p[..]
+            } else {
+                &p[..p.len().min(common + opts.dir_depth)]
+            }
+        })
+        .collect();
+
+    printable_results.retain(|c| {
+        let distinct: HashSet<&[String]> = c.files.iter().map(|f| dirs[f.0 as usize]).collect();
+        distinct.len() >= opts.min_distinct_dirs
+    });
+}
+
+/// One line of --group-output, the "type" field tells it apart from the summary report.
+#[derive(Serialize)]
+struct GroupLine<'a> {
+    #[serde(rename = "type")]
+    line_type: &'static str,
+    #[serde(flatten)]
+    collision: &'a Collision,
+}
+
+/// Write each finalized duplicate, which isn't ignored, as a single line of JSON to stdout or
+/// stderr before the report is output.  Lines are in the same order as the report.
+fn emit_groups(
+    printable_results: &[Collision],
+    opts: &Options,
+    ignore_hashes: &HashMap<u64, bool>,
+) {
+    let mut out: Box<dyn Write> = if opts.group_output == "stdout" {
+        Box::new(io::stdout())
+    } else {
+        Box::new(io::stderr())
+    };
+
+    for p in printable_results
+        .iter()
+        .filter(|p| !ignore_hashes.contains_key(&p.key))
+    {
+        let line = GroupLine {
+            line_type: "group",
+            collision: p,
+        };
+        let rc = serde_json::to_writer(&mut out, &line)
+            .map_err(io::Error::from)
+            .and_then(|_| out.write_all(b"\n"));
+        if let Err(e) = rc {
+            eprintln!("WARNING: Unable to write group output, reason {}", e);
+            return;
+        }
+    }
+}
+
+/// Is the ignore file a URL to fetch rather than a file.
+fn is_url(file_name: &str) -> bool {
+    file_name.starts_with("http://")
+}
+
+/// Read the ignore file, or fetch it when it's a URL.
+fn read_ignore_file(file_name: &str) -> Result<Vec<u8>, String> {
+    #[cfg(feature = "http")]
+    if is_url(file_name) {
+        return http::get(file_name);
+    }
+
+    std::fs::read(file_name).map_err(|e| e.to_string())
+}
+
+/// Open the user supplied file which contains the hash signatures for text that we don't
+/// want to report on.  It's read once, a URL is only fetched the one time.  With strict an invalid
+/// entry is an error rather than a warning.
+fn get_ignore_hashes(file_name: &str, strict: bool) -> HashMap<u64, bool> {
+    let mut ignores: HashMap<u64, bool> = HashMap::new();
+
+    match read_ignore_file(file_name) {
+        Ok(content) => {
+            for (i, t) in String::from_utf8_lossy(&content).lines().enumerate() {
+                let l = t.trim();
+
+                if !l.is_empty() && !l.starts_with('#') {
+                    if let Ok(hv) = l.parse::<u64>() {
+                        ignores.insert(hv, true);
+                    } else if strict {
+                        fatal(
+                            2,
+                            format!(
+                                "Ignore file {} line {} contains invalid hash value \"{}\"",
+                                file_name,
+                                i + 1,
+                                l
+                            ),
+                        );
+                    } else {
+                        eprintln!("WARNING: Ignore file contains invalid hash value \"{}\"", l);
+                        record_warning(
+                            WarningKind::InvalidIgnoreEntry,
+                            format!("Ignore file contains invalid hash value \"{}\"", l),
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) if is_url(file_name) => {
+            // Not scanning without the baseline is a failed check, as a duplicate it holds would
+            // otherwise be reported.
+            fatal(
+                EXIT_CHECK_FAILED,
+                format!("Unable to fetch ignore file {}, reason: {}", file_name, e),
+            );
+        }
+        Err(e) => {
+            fatal(
+                2,
+                format!(
+                    "Unable to open supplied ignore file {}, reason: {}",
+                    file_name, e
+                ),
+            );
+        }
+    }
+
+    ignores
+}
+
+/// Read the --boilerplate file, returning the signature of each line in it.  Blank lines and
+/// comments are skipped as in the ignore file, a line which starts with "\\#" is the literal
+/// line starting with "#".
+fn get_boilerplate_hashes(file_name: &str, opts: &Options) -> HashSet<u64> {
+    let content = std::fs::read(file_name).unwrap_or_else(|e| {
+        fatal(
+            2,
+            format!(
+                "Unable to open supplied boilerplate file {}, reason: {}",
+                file_name, e
+            ),
+        );
+    });
+
+    String::from_utf8_lossy(&content)
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| line_signature(if l.starts_with("\\#") { &l[1..] } else { l }, opts))
+        .collect()
+}
+
+/// Parse a duration like "90s", "30m", "12h", "7d" or "2w".
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let d = duration.trim();
+    let unit = d.chars().last()?;
+    let multiplier: u64 = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        'w' => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+    let value = d[..d.len() - 1].parse::<u64>().ok()?;
+    Some(Duration::from_secs(value.checked_mul(multiplier)?))
+}
+
+/// File names are kept as strings, one which isn't valid UTF-8 is skipped with a warning, or with
+/// --strict-utf8-paths is an error.
+fn utf8_name(path: &Path, opts: &Options) -> Option<String> {
+    if let Some(name) = path.to_str() {
+        return Some(name.to_string());
+    }
+
+    let msg = format!("File name {} isn't valid UTF-8", path.to_string_lossy());
+    if opts.strict_utf8_paths {
+        fatal(1, msg);
+    }
+    let msg = format!("{}, skipped", msg);
+    eprintln!("WARNING: {}", msg);
+    record_warning(WarningKind::NonUtf8Name, msg);
+    None
+}
+
+/// Check the file system modification time of a file against the --modified-since cutoff.  We
+/// only look at the metadata, if it's unavailable we keep the file.
+fn modified_after(path: &Path, cutoff: Option<SystemTime>) -> bool {
+    match cutoff {
+        None => true,
+        Some(cutoff) => match path.metadata().and_then(|m| m.modified()) {
+            Ok(mtime) => mtime >= cutoff,
+            Err(_) => true,
+        },
+    }
+}
+
+/// Storage for the line signatures of every file.  Identical files, which are common with
+/// vendored copies of code, produce identical signature vectors, so we only keep one copy of each
+/// unique vector and every file id refers to it by index.
+#[derive(Debug)]
+struct SignatureArena {
+    vectors: Vec<Vec<u64>>,
+    file_index: Vec<u32>,
+    lookup: HashMap<u64, Vec<u32>>,
+    // Per file, with --alpha-rename
+    identifiers: HashMap<u32, Identifiers>,
+    // Per file, with --whole-functions
+    functions: HashMap<u32, Vec<Function>>,
+    // Per file which has any, with --treat-includes-as-boundaries or --blank-line-boundary
+    boundaries: HashMap<u32, Vec<u32>>,
+}
+
+impl SignatureArena {
+    /// Index 0 is an empty vector, which is what files we haven't processed refer to.
+    fn new(num_files: usize) -> SignatureArena {
+        SignatureArena {
+            vectors: vec![vec![]],
+            file_index: vec![0; num_files],
+            lookup: HashMap::new(),
+            identifiers: HashMap::new(),
+            functions: HashMap::new(),
+            boundaries: HashMap::new(),
+        }
+    }
+
+    /// The digest insert_digested wants, hash it before taking the lock on the arena so the
+    /// threads reading files don't wait on each other to hash.
+    fn digest(signatures: &[u64]) -> u64 {
+        calculate_hash(&signatures)
+    }
+
+    /// Store the signatures for a file, sharing an existing vector if we already have one with
+    /// the same content.
+    #[cfg(test)]
+    fn insert(&mut self, fid: u32, signatures: Vec<u64>) {
+        let digest = SignatureArena::digest(&signatures);
+        self.insert_digested(fid, signatures, digest);
+    }
+
+    /// insert with the digest of the signatures already worked out.
+    fn insert_digested(&mut self, fid: u32, signatures: Vec<u64>, digest: u64) {
+        if signatures.is_empty() {
+            self.file_index[fid as usize] = 0;
+            return;
+        }
+
+        let vectors = &mut self.vectors;
+        let candidates = self.lookup.entry(digest).or_default();
+
+        let index = match candidates
+            .iter()
+            .find(|&&i| vectors[i as usize] == signatures)
+        {
+            Some(&i) => i,
+            None => {
+                let i = vectors.len() as u32;
+                vectors.push(signatures);
+                candidates.push(i);
+                i
+            }
+        };
+
+        self.file_index[fid as usize] = index;
+    }
+
+    /// The line signatures for a file.
+    fn get(&self, fid: u32) -> &[u64] {
+        &self.vectors[self.file_index[fid as usize] as usize]
+    }
+
+    /// The files with signatures, the vectors they share and the bytes of signatures which
+    /// sharing saved, for --verbose.
+    fn sharing(&self) -> (usize, usize, u64) {
+        let bytes = |v: &Vec<u64>| (v.len() * std::mem::size_of::<u64>()) as u64;
+        let files = self.file_index.iter().filter(|i| **i != 0).count();
+        let all: u64 = self
+            .file_index
+            .iter()
+            .map(|i| bytes(&self.vectors[*i as usize]))
+            .sum();
+        let stored: u64 = self.vectors.iter().map(bytes).sum();
+        (files, self.vectors.len() - 1, all - stored)
+    }
+}
+
+/// Percent-encode a path segment for a URL, everything but the unreserved characters of RFC 3986
+/// is encoded, byte by byte of its UTF-8.
+fn percent_encode(segment: &str) -> String {
+    let mut rc = String::with_capacity(segment.len());
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            rc.push(b as char);
+        } else {
+            rc += &format!("%{:02X}", b);
+        }
+    }
+    rc
+}
+
+/// A file name as a relative URL for --url-paths: relative to root, with ".." for a file outside
+/// of it, segments separated by '/' whatever the platform and each one percent-encoded.  Without
+/// encode it's the relative path for --relative-paths.
+fn url_path(name: &str, root: &Path, encode: bool) -> String {
+    use std::path::Component;
+
+    let path = Path::new(name);
+    let mut segments: Vec<String> = vec![];
+    let rest = if path.is_absolute() {
+        let common = root
+            .components()
+            .zip(path.components())
+            .take_while(|(a, b)| a == b)
+            .count();
+        segments.extend((common..root.components().count()).map(|_| "..".to_string()));
+        path.components().skip(common).collect::<Vec<Component>>()
+    } else {
+        path.components().collect()
+    };
+    for c in rest {
+        match c {
+            Component::Normal(s) if encode => segments.push(percent_encode(&s.to_string_lossy())),
+            Component::Normal(s) => segments.push(s.to_string_lossy().to_string()),
+            Component::ParentDir => segments.push("..".to_string()),
+            _ => {}
+        }
+    }
+    segments.join("/")
+}
+
+/// Data structure which we use to store the count of how many files we have processed,
+/// a vector of file name strings and a hash map which maps file name to integer.  We do this so
+/// that we only have one copy of the file names in memory and use an integer to identify the
+/// files though out the source code.  This reduces memory consumption significantly and also
+/// results in file name compares becoming integer comparisons.
+#[derive(Debug)]
+struct FileId {
+    num_files: u32,
+    index_to_name: Vec<Arc<str>>,
+    name_to_index: HashMap<Arc<str>, u32>,
+    line_maps: HashMap<u32, Vec<u32>>,
+    // Compare file names ignoring case, for case insensitive file systems.
+    case_fold: bool,
+    // With --url-paths or --relative-paths, the directory the names in JSON are relative to.
+    url_root: Option<PathBuf>,
+    // Percent-encode them, --url-paths.
+    url_encode: bool,
+}
+
+impl FileId {
+    fn new() -> FileId {
+        FileId {
+            num_files: 0,
+            index_to_name: vec![],
+            name_to_index: HashMap::new(),
+            line_maps: HashMap::new(),
+            case_fold: false,
+            url_root: None,
+            url_encode: false,
+        }
+    }
+
+    /// Given a file name, if it doesn't already exist we will store the information about which
+    /// index it is stored in and it's value.  With case_fold a name which only differs in case
+    /// from one we already have is the same file, the name first seen is the one reported.
+    fn register_file(&mut self, file_name: &str) -> Option<u32> {
+        let key: Arc<str> = if self.case_fold {
+            Arc::from(file_name.to_lowercase())
+        } else {
+            Arc::from(file_name)
+        };
+        if self.name_to_index.contains_key(&key) {
+            return None;
+        }
+        let num = self.num_files;
+
+        self.index_to_name.push(Arc::from(file_name));
+        self.name_to_index.insert(key, self.num_files);
+        if let Some(v) = self.num_files.checked_add(1) {
+            self.num_files = v;
+        } else {
+            fatal(2, format!("Number of files processed exceeds {}", u32::MAX));
+        }
+        Some(num)
+    }
+
+    /// Given an id (integer) return the actual file name.
+    fn id_to_name(&self, index: u32) -> Arc<str> {
+        self.index_to_name[index as usize].clone()
+    }
+
+    /// The file name as it's written in JSON, a relative URL with --url-paths or a relative path
+    /// with --relative-paths.
+    fn json_name(&self, index: u32) -> String {
+        let name = self.id_to_name(index);
+        match &self.url_root {
+            Some(root) => url_path(&name, root, self.url_encode),
+            None => name.to_string(),
+        }
+    }
+
+    /// Record which line in the file each signature came from, for files where some lines
+    /// were left out of the signatures.
+    fn set_line_map(&mut self, index: u32, line_map: Vec<u32>) {
+        self.line_maps.insert(index, line_map);
+    }
+
+    /// Given the start and length of a duplicate in signatures, return the start line and end
+    /// line (exclusive) in the file, 0 based.  The range includes any lines which were left out
+    /// of the signatures inside it.
+    fn line_range(&self, index: u32, start: u32, num_lines: u32) -> (u32, u32) {
+        match self.line_maps.get(&index) {
+            Some(map) => (
+                map[start as usize],
+                map[(start + num_lines - 1) as usize] + 1,
+            ),
+            None => (start, start + num_lines),
+        }
+    }
+
+    /// Number of files we have information for.
+    fn number_files(&self) -> u32 {
+        self.num_files
+    }
+}
+
+/// The byte which terminates a line (record), '\n' by default.  It can be given as an escape
+/// sequence eg. "\0", "\r", a single character or a number eg. "0", "0x1e".
+#[derive(Debug, Clone, Copy)]
+pub struct RecordSeparator(u8);
+
+impl FromStr for RecordSeparator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let byte = match s {
+            "\\n" => Some(b'\n'),
+            "\\r" => Some(b'\r'),
+            "\\t" => Some(b'\t'),
+            "\\0" => Some(0),
+            _ => {
+                if let Some(hex) = s.strip_prefix("0x") {
+                    u8::from_str_radix(hex, 16).ok()
+                } else if let Ok(n) = s.parse::<u8>() {
+                    Some(n)
+                } else if s.len() == 1 {
+                    Some(s.as_bytes()[0])
+                } else {
+                    None
+                }
+            }
+        };
+
+        byte.map(RecordSeparator)
+            .ok_or_else(|| format!("invalid record separator '{}'", s))
+    }
+}
+
+impl fmt::Display for RecordSeparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            b'\n' => write!(f, "\\n"),
+            b'\r' => write!(f, "\\r"),
+            b'\t' => write!(f, "\\t"),
+            0 => write!(f, "\\0"),
+            b if b.is_ascii_graphic() => write!(f, "{}", b as char),
+            b => write!(f, "{:#04x}", b),
+        }
+    }
+}
+
+/// What the reported hash signature of a duplicate covers.  Content, the default, is only the
+/// duplicated text, position also covers where each copy is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FingerprintMode {
+    Content,
+    Position,
+}
+
+impl FromStr for FingerprintMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "content" => Ok(FingerprintMode::Content),
+            "position" => Ok(FingerprintMode::Position),
+            _ => Err(format!(
+                "invalid fingerprint mode '{}', expected content or position",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FingerprintMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FingerprintMode::Content => write!(f, "content"),
+            FingerprintMode::Position => write!(f, "position"),
+        }
+    }
+}
+
+/// What else besides leading and trailing whitespace doesn't matter when comparing lines,
+/// --normalize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Normalize {
+    None,
+    Collapse,
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Normalize::None),
+            "collapse" => Ok(Normalize::Collapse),
+            _ => Err(format!(
+                "invalid normalization '{}', expected none or collapse",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Normalize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Normalize::None => write!(f, "none"),
+            Normalize::Collapse => write!(f, "collapse"),
+        }
+    }
+}
+
+/// The Unicode normalization form lines are put in before hashing, --normalize-unicode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnicodeForm {
+    None,
+    Nfc,
+    Nfd,
+}
+
+impl FromStr for UnicodeForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(UnicodeForm::None),
+            "nfc" | "nfd" if cfg!(not(feature = "unicode")) => Err(format!(
+                "normalization form '{}' needs duplihere built with the unicode feature",
+                s
+            )),
+            "nfc" => Ok(UnicodeForm::Nfc),
+            "nfd" => Ok(UnicodeForm::Nfd),
+            _ => Err(format!(
+                "invalid normalization form '{}', expected nfc, nfd or none",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UnicodeForm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnicodeForm::None => write!(f, "none"),
+            UnicodeForm::Nfc => write!(f, "nfc"),
+            UnicodeForm::Nfd => write!(f, "nfd"),
+        }
+    }
+}
+
+/// What the duplicated line counts in the summary are, --line-count-mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCountMode {
+    Removable,
+    Involved,
+}
+
+impl FromStr for LineCountMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "removable" => Ok(LineCountMode::Removable),
+            "involved" => Ok(LineCountMode::Involved),
+            _ => Err(format!(
+                "invalid line count mode '{}', expected removable or involved",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LineCountMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineCountMode::Removable => write!(f, "removable"),
+            LineCountMode::Involved => write!(f, "involved"),
+        }
+    }
+}
+
+/// The order of the locations of a duplicate, --sort-locations-by.  By line puts them in order of
+/// their start line, by file keeps the locations in a file together, files in the order of their
+/// ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocationOrder {
+    Line,
+    File,
+}
+
+impl FromStr for LocationOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(LocationOrder::Line),
+            "file" => Ok(LocationOrder::File),
+            _ => Err(format!(
+                "invalid location order '{}', expected line or file",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LocationOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LocationOrder::Line => write!(f, "line"),
+            LocationOrder::File => write!(f, "file"),
+        }
+    }
+}
+
+/// The order files are given their ids and processed in, --scan-order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOrder {
+    Name,
+    Size,
+    Mtime,
+}
+
+impl FromStr for ScanOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(ScanOrder::Name),
+            "size" => Ok(ScanOrder::Size),
+            "mtime" => Ok(ScanOrder::Mtime),
+            _ => Err(format!(
+                "invalid scan order '{}', expected name, size or mtime",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ScanOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanOrder::Name => write!(f, "name"),
+            ScanOrder::Size => write!(f, "size"),
+            ScanOrder::Mtime => write!(f, "mtime"),
+        }
+    }
+}
+
+/// Sort the files to process and register them again in that order, so the file ids don't
+/// depend on the order of the file patterns or the order a glob returns files in.  Size puts the
+/// largest first and mtime the most recently modified first, ties are broken by name.
+fn order_files(files: &mut [(u32, String)], order: ScanOrder, file_lookup: &mut FileId) {
+    let mtime = |f: &str| {
+        std::fs::metadata(f)
+            .and_then(|m| m.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    match order {
+        ScanOrder::Name => files.sort_by(|a, b| a.1.cmp(&b.1)),
+        ScanOrder::Size => {
+            files.sort_by_cached_key(|f| (std::cmp::Reverse(text_size(&f.1)), f.1.clone()))
+        }
+        ScanOrder::Mtime => {
+            files.sort_by_cached_key(|f| (std::cmp::Reverse(mtime(&f.1)), f.1.clone()))
+        }
+    }
+
+    let case_fold = file_lookup.case_fold;
+    let url_root = file_lookup.url_root.take();
+    let url_encode = file_lookup.url_encode;
+    *file_lookup = FileId::new();
+    file_lookup.case_fold = case_fold;
+    file_lookup.url_root = url_root;
+    file_lookup.url_encode = url_encode;
+    for f in files.iter_mut() {
+        f.0 = file_lookup
+            .register_file(&f.1)
+            .expect("file names are already unique");
+    }
+}
+
+/// The --threads value, a number or auto.  0 matches the number of cpu cores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    Auto,
+    Count(usize),
+}
+
+impl FromStr for Threads {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            Ok(Threads::Auto)
+        } else {
+            s.parse::<usize>()
+                .map(Threads::Count)
+                .map_err(|_| format!("invalid thread count '{}', expected a number or auto", s))
+        }
+    }
+}
+
+impl fmt::Display for Threads {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Threads::Auto => write!(f, "auto"),
+            Threads::Count(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Command line options.
+#[derive(Debug, Clone)]
+pub struct Options {
+    lines: u32,
+    print: bool,
+    json: bool,
+    file_globs: Vec<String>,
+    ignore: String,
+    threads: Threads,
+    force: bool,
+    first_n_lines: u64,
+    html: String,
+    dedup_identical_locations: bool,
+    modified_since: String,
+    syslog: bool,
+    ignore_generated: bool,
+    generated_markers: Vec<String>,
+    benchmark_threads: bool,
+    record_separator: RecordSeparator,
+    output: String,
+    tee: bool,
+    note_whitespace_diffs: bool,
+    quickfix: bool,
+    max_files: u64,
+    explain_ignored: bool,
+    suffix_array: bool,
+    group_output: String,
+    ignore_tests: bool,
+    test_patterns: Vec<String>,
+    progress: bool,
+    skip_directive: String,
+    report_limit_bytes: u64,
+    salt: String,
+    rayon_stats: bool,
+    case_fold_paths: bool,
+    trend: Vec<String>,
+    fingerprint_mode: FingerprintMode,
+    scan_stdin_as: String,
+    collapse_consecutive: bool,
+    validate_ignore: bool,
+    strict: bool,
+    io_threads: usize,
+    cpu_threads: usize,
+    min_distinct_dirs: usize,
+    dir_depth: usize,
+    scan_order: ScanOrder,
+    signatures_only: String,
+    deny_list: String,
+    mmap: bool,
+    count_by_extension: bool,
+    boilerplate: String,
+    // Filled in from the --boilerplate file, not a command line option.
+    boilerplate_hashes: HashSet<u64>,
+    verbose: bool,
+    left: Vec<String>,
+    right: Vec<String>,
+    pager: bool,
+    report_only_files: bool,
+    max_locations: usize,
+    emit_hashes: bool,
+    normalize: Normalize,
+    ignore_case: bool,
+    report_comments: Vec<String>,
+    // Parsed from report_comments
+    report_context: BTreeMap<String, String>,
+    min_block_lines_for_print: u32,
+    alpha_rename: bool,
+    output_json_array_only: bool,
+    strict_utf8_paths: bool,
+    quiet_on_clean: bool,
+    no_duplihereignore: bool,
+    max_overlap_ratio: f64,
+    largest_files: usize,
+    normalize_unicode: UnicodeForm,
+    tui: bool,
+    line_count_mode: LineCountMode,
+    fail_on_file: Vec<String>,
+    dot: String,
+    read_buffer_size: usize,
+    preset_ci: bool,
+    whole_functions: Language,
+    sort_locations_by: LocationOrder,
+    dump_collision_hash_stats: bool,
+    extract_to: String,
+    ignore_first_line: u64,
+    compare_normalized: bool,
+    no_dir_config: bool,
+    max_pairs: u64,
+    emit_line_coverage: bool,
+    treat_includes_as_boundaries: bool,
+    flag_trailing_ws: bool,
+    checkpoint: String,
+    resume: String,
+    report_histogram: bool,
+    per_language_thresholds: bool,
+    language_lines: Vec<String>,
+    // Parsed from language_lines
+    language_table: HashMap<&'static str, u32>,
+    // The options duplihere.toml can set which were given on the command line.
+    given: HashSet<&'static str>,
+    url_paths: bool,
+    detect_rotations: bool,
+    summary_json: String,
+    blank_line_boundary: bool,
+    fix_plan: bool,
+    strict_ignore: bool,
+    output_split_by_severity: String,
+    base_dir: String,
+    emit_ignore_template: String,
+    report_relative_savings: bool,
+    location_hashes: bool,
+    skip_symlinked_dirs: bool,
+    progress_json: bool,
+    flag_reindent: bool,
+    files_manifest: String,
+    hash_seed: u64,
+    strip_license_header: bool,
+    license_header_lines: u64,
+    relative_paths: bool,
+    json_errors: bool,
+}
+
+/// Default values for the command line options.
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            lines: 6,
+            print: false,
+            json: false,
+            file_globs: vec![],
+            ignore: "".to_string(),
+            threads: Threads::Count(4),
+            force: false,
+            first_n_lines: 0,
+            html: "".to_string(),
+            dedup_identical_locations: false,
+            modified_since: "".to_string(),
+            syslog: false,
+            ignore_generated: false,
+            generated_markers: vec![],
+            benchmark_threads: false,
+            record_separator: RecordSeparator(b'\n'),
+            output: "".to_string(),
+            tee: false,
+            note_whitespace_diffs: false,
+            quickfix: false,
+            max_files: 0,
+            explain_ignored: false,
+            suffix_array: false,
+            group_output: "".to_string(),
+            ignore_tests: false,
+            test_patterns: vec![],
+            progress: false,
+            skip_directive: "duplihere: skip".to_string(),
+            report_limit_bytes: 0,
+            salt: "".to_string(),
+            rayon_stats: false,
+            case_fold_paths: cfg!(any(target_os = "macos", windows)),
+            trend: vec![],
+            fingerprint_mode: FingerprintMode::Content,
+            scan_stdin_as: "<stdin>".to_string(),
+            collapse_consecutive: false,
+            validate_ignore: false,
+            strict: false,
+            io_threads: 0,
+            cpu_threads: 0,
+            min_distinct_dirs: 0,
+            dir_depth: 0,
+            scan_order: ScanOrder::Name,
+            signatures_only: "".to_string(),
+            deny_list: "".to_string(),
+            mmap: false,
+            count_by_extension: false,
+            boilerplate: "".to_string(),
+            boilerplate_hashes: HashSet::new(),
+            verbose: false,
+            left: vec![],
+            right: vec![],
+            pager: false,
+            report_only_files: false,
+            max_locations: 0,
+            emit_hashes: false,
+            normalize: Normalize::None,
+            ignore_case: false,
+            report_comments: vec![],
+            report_context: BTreeMap::new(),
+            min_block_lines_for_print: 0,
+            alpha_rename: false,
+            output_json_array_only: false,
+            strict_utf8_paths: false,
+            quiet_on_clean: false,
+            no_duplihereignore: false,
+            max_overlap_ratio: 0.0,
+            largest_files: 0,
+            normalize_unicode: UnicodeForm::None,
+            tui: false,
+            line_count_mode: LineCountMode::Removable,
+            fail_on_file: vec![],
+            dot: "".to_string(),
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            preset_ci: false,
+            whole_functions: Language::None,
+            sort_locations_by: LocationOrder::Line,
+            dump_collision_hash_stats: false,
+            extract_to: "".to_string(),
+            ignore_first_line: 0,
+            compare_normalized: false,
+            no_dir_config: false,
+            max_pairs: 0,
+            emit_line_coverage: false,
+            treat_includes_as_boundaries: false,
+            flag_trailing_ws: false,
+            checkpoint: "".to_string(),
+            resume: "".to_string(),
+            report_histogram: false,
+            per_language_thresholds: false,
+            language_lines: vec![],
+            language_table: HashMap::new(),
+            given: HashSet::new(),
+            url_paths: false,
+            detect_rotations: false,
+            summary_json: "".to_string(),
+            blank_line_boundary: false,
+            fix_plan: false,
+            strict_ignore: false,
+            output_split_by_severity: "".to_string(),
+            base_dir: "".to_string(),
+            emit_ignore_template: "".to_string(),
+            report_relative_savings: false,
+            location_hashes: false,
+            skip_symlinked_dirs: false,
+            progress_json: false,
+            flag_reindent: false,
+            files_manifest: "".to_string(),
+            hash_seed: 0,
+            strip_license_header: false,
+            license_header_lines: 0,
+            relative_paths: false,
+            json_errors: false,
+        }
+    }
+}
+
+/// The options --preset-ci stands for, applied once the command line is parsed.  --scan-order
+/// name is the default so one given is kept, the flags are turned back off by their negative form.
+fn preset_ci(opts: &mut Options) {
+    opts.json = true;
+    opts.relative_paths = true;
+    opts.json_errors = true;
+}
+
+/// Values of --lines below this produce huge reports which are mostly noise.
+const MIN_SANE_LINES: u32 = 3;
+
+static LONG_DESC: &str = "Find duplicate lines of text in one or more text files.
+
+The duplicated text can be at different levels of indention,
+but otherwise needs to be identical.
+
+More information: https://github.com/tasleson/duplihere";
+
+fn main() -> Result<(), rags::Error> {
+    let mut opts = Options::default();
+    let mut no_json = false;
+    let mut absolute_paths = false;
+    let mut text_errors = false;
+    let mut lines = Given::new(opts.lines);
+    let mut normalize = Given::new(opts.normalize);
+    let mut normalize_unicode = Given::new(opts.normalize_unicode);
+    let mut parser = argparse!();
+    parser
+        .app_desc("find duplicate text")
+        .app_long_desc(LONG_DESC)
+        .group("argument", "description")?
+        .flag('p', "print", "print duplicate text", &mut opts.print, false)?
+        .flag('j', "json", "output JSON", &mut opts.json, false)?
+        .arg(
+            'l',
+            "lines",
+            "minimum number of duplicate lines",
+            &mut lines,
+            Some("<number>"),
+            false,
+        )?
+        .list(
+            'f',
+            "file",
+            "pattern or file eg. \"**/*.[h|c]\" recursive, \"*.py\", \
+            \"file.ext\", - reads stdin, can repeat, required unless using --trend or \
+            --left and --right",
+            &mut opts.file_globs,
+            Some("<pattern or specific file>"),
+            false,
+        )?
+        .arg(
+            'i',
+            "ignore",
+            "file containing hash values to ignore, one per line",
+            &mut opts.ignore,
+            Some("<file name>"),
+            false,
+        )?
+        .arg(
+            't',
+            "threads",
+            "number of threads to utilize. Set to 0 to match #cpu cores, auto picks the number \
+            for finding collisions from the amount of work",
+            &mut opts.threads,
+            Some("<thread number>"),
+            false,
+        )?
+        .long_flag(
+            "force",
+            "don't warn about a small number of --lines",
+            &mut opts.force,
+            false,
+        )?
+        .long_arg(
+            "first-n-lines",
+            "only scan the first n lines of each file, duplicates after that are missed. \
+            0 scans everything",
+            &mut opts.first_n_lines,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "html",
+            "also write an interactive HTML report, with filtering and sorting, to a file",
+            &mut opts.html,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "dedup-identical-locations",
+            "count each duplicated line once in the total, merging overlapping copies in a file",
+            &mut opts.dedup_identical_locations,
+            false,
+        )?
+        .long_arg(
+            "modified-since",
+            "only scan files modified within the duration eg. 7d (units s, m, h, d, w), \
+            duplicates of older files are missed",
+            &mut opts.modified_since,
+            Some("<duration>"),
+            false,
+        )?
+        .long_flag(
+            "syslog",
+            "send the summary and major/critical duplicates to syslog instead of stdout",
+            &mut opts.syslog,
+            false,
+        )?
+        .long_flag(
+            "ignore-generated",
+            "skip files with a generated marker eg. \"DO NOT EDIT\" in their first lines",
+            &mut opts.ignore_generated,
+            false,
+        )?
+        .long_list(
+            "generated-marker",
+            "text which identifies a generated file, replaces the default markers, can repeat",
+            &mut opts.generated_markers,
+            Some("<text>"),
+            false,
+        )?
+        .long_flag(
+            "benchmark-threads",
+            "time finding duplicates with 1, 2, 4, 8 and #cpu threads instead of reporting",
+            &mut opts.benchmark_threads,
+            false,
+        )?
+        .long_arg(
+            "record-separator",
+            "byte which terminates a line eg. \"\\0\", \"\\r\", \"0x1e\"",
+            &mut opts.record_separator,
+            Some("<byte>"),
+            false,
+        )?
+        .long_arg(
+            "output",
+            "write the JSON report to a file instead of stdout, requires --json",
+            &mut opts.output,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "tee",
+            "with --output, write the JSON report to stdout too",
+            &mut opts.tee,
+            false,
+        )?
+        .long_flag(
+            "note-whitespace-diffs",
+            "mark duplicates whose copies differ in whitespace",
+            &mut opts.note_whitespace_diffs,
+            false,
+        )?
+        .long_flag(
+            "quickfix",
+            "output file:line:col: message lines for an editor quickfix list",
+            &mut opts.quickfix,
+            false,
+        )?
+        .long_arg(
+            "max-files",
+            "stop adding files after this many, prefer narrowing the file patterns. \
+            0 is unlimited",
+            &mut opts.max_files,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "explain-ignored",
+            "list the duplicates suppressed by the ignore file on stderr",
+            &mut opts.explain_ignored,
+            false,
+        )?
+        .long_flag(
+            "suffix-array",
+            "when given a single file find its maximal repeats with a suffix array, much faster \
+            for large files",
+            &mut opts.suffix_array,
+            false,
+        )?
+        .long_arg(
+            "group-output",
+            "also write each duplicate as a line of JSON to stdout or stderr",
+            &mut opts.group_output,
+            Some("<stdout|stderr>"),
+            false,
+        )?
+        .long_flag(
+            "ignore-tests",
+            "don't report duplicates found only in test files, they are still scanned",
+            &mut opts.ignore_tests,
+            false,
+        )?
+        .long_list(
+            "test-pattern",
+            "additional pattern for --ignore-tests eg. \"**/fixtures/**\", can repeat",
+            &mut opts.test_patterns,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_flag(
+            "progress",
+            "show progress, throughput and an ETA on stderr while scanning files",
+            &mut opts.progress,
+            false,
+        )?
+        .long_arg(
+            "skip-directive",
+            "lines containing this text are left out when looking for duplicates, \
+            empty to disable",
+            &mut opts.skip_directive,
+            Some("<text>"),
+            false,
+        )?
+        .long_arg(
+            "report-limit-bytes",
+            "cap the text or JSON report at about this size, leaving out the smallest \
+            duplicates. 0 is unlimited",
+            &mut opts.report_limit_bytes,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "salt",
+            "mix a value into the reported hash signatures, signatures from different salts \
+            can't be compared",
+            &mut opts.salt,
+            Some("<value>"),
+            false,
+        )?
+        .long_flag(
+            "rayon-stats",
+            "show how the work of each parallel phase was spread over the threads on stderr",
+            &mut opts.rayon_stats,
+            false,
+        )?
+        .long_arg(
+            "case-fold-paths",
+            "treat file paths which differ only in case as the same file, on by default for \
+            macOS and Windows",
+            &mut opts.case_fold_paths,
+            Some("<true|false>"),
+            false,
+        )?
+        .long_list(
+            "trend",
+            "print duplicated line totals over time as CSV from saved JSON reports instead of \
+            scanning, can repeat",
+            &mut opts.trend,
+            Some("<report>"),
+            false,
+        )?
+        .long_arg(
+            "fingerprint-mode",
+            "what the hash signature covers, the duplicated text or the text and where each \
+            copy is",
+            &mut opts.fingerprint_mode,
+            Some("<content|position>"),
+            false,
+        )?
+        .long_arg(
+            "scan-stdin-as",
+            "name to report for text read from stdin with -f -",
+            &mut opts.scan_stdin_as,
+            Some("<name>"),
+            false,
+        )?
+        .long_flag(
+            "collapse-consecutive",
+            "report copies repeated at a regular stride in a file once, with a repeat count",
+            &mut opts.collapse_consecutive,
+            false,
+        )?
+        .long_flag(
+            "validate-ignore",
+            "instead of reporting, list which --ignore file entries still match a duplicate and \
+            which are stale",
+            &mut opts.validate_ignore,
+            false,
+        )?
+        .long_flag(
+            "strict",
+            "with --validate-ignore, exit with 3 when there are stale entries",
+            &mut opts.strict,
+            false,
+        )?
+        .long_arg(
+            "io-threads",
+            "number of threads reading and hashing files. 0 uses --threads",
+            &mut opts.io_threads,
+            Some("<thread number>"),
+            false,
+        )?
+        .long_arg(
+            "cpu-threads",
+            "number of threads finding collisions, see --benchmark-threads. 0 uses --threads",
+            &mut opts.cpu_threads,
+            Some("<thread number>"),
+            false,
+        )?
+        .long_arg(
+            "min-distinct-dirs",
+            "only report duplicates with copies in at least this many directories",
+            &mut opts.min_distinct_dirs,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "dir-depth",
+            "with --min-distinct-dirs, compare directories this many levels below the \
+            directory common to all files. 0 compares the full directory",
+            &mut opts.dir_depth,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "scan-order",
+            "order files are numbered and processed in, by name, largest first or most \
+            recently modified first",
+            &mut opts.scan_order,
+            Some("<name|size|mtime>"),
+            false,
+        )?
+        .long_arg(
+            "signatures-only",
+            "also write the sorted hash signatures of the duplicates to a file, one per line",
+            &mut opts.signatures_only,
+            Some("<file name>"),
+            false,
+        )?
+        .long_arg(
+            "deny-list",
+            "only report copies of the snippets in this file, exit with 3 if any are found",
+            &mut opts.deny_list,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "mmap",
+            "memory map large files to hash them, files mustn't change while being scanned",
+            &mut opts.mmap,
+            false,
+        )?
+        .long_flag(
+            "count-by-extension",
+            "add the number of duplicated lines for each file extension to the summary",
+            &mut opts.count_by_extension,
+            false,
+        )?
+        .long_arg(
+            "boilerplate",
+            "file of lines, eg. from a license header, which are left out when looking for \
+            duplicates",
+            &mut opts.boilerplate,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "verbose",
+            "explain choices made along the way on stderr",
+            &mut opts.verbose,
+            false,
+        )?
+        .long_list(
+            "left",
+            "pattern for the first of two trees to compare, only copies between the trees are \
+            reported, can repeat",
+            &mut opts.left,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_list(
+            "right",
+            "pattern for the second of two trees to compare, can repeat",
+            &mut opts.right,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_flag(
+            "pager",
+            "show the text report with $PAGER, or less, when stdout is a terminal",
+            &mut opts.pager,
+            false,
+        )?
+        .long_flag(
+            "report-only-files",
+            "instead of the report, list the files with duplicated lines and how many, most first",
+            &mut opts.report_only_files,
+            false,
+        )?
+        .long_arg(
+            "max-locations",
+            "bound memory for text repeated very many times by keeping at most this many \
+            locations for a duplicate, the rest aren't reported. 0 is unlimited",
+            &mut opts.max_locations,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "emit-hashes",
+            "instead of finding duplicates write the line signatures of each file to stdout",
+            &mut opts.emit_hashes,
+            false,
+        )?
+        .long_arg(
+            "normalize",
+            "collapse treats each run of whitespace within a line as a single space",
+            &mut normalize,
+            Some("<none|collapse>"),
+            false,
+        )?
+        .long_flag(
+            "ignore-case",
+            "lines which only differ in case are the same",
+            &mut opts.ignore_case,
+            false,
+        )?
+        .long_list(
+            "report-comment",
+            "add context such as the build number to the report, can repeat",
+            &mut opts.report_comments,
+            Some("<key=value>"),
+            false,
+        )?
+        .long_arg(
+            "min-block-lines-for-print",
+            "with -p only print the text of duplicates of at least this many lines",
+            &mut opts.min_block_lines_for_print,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "alpha-rename",
+            "experimental, also find copies where identifiers were consistently renamed, slow",
+            &mut opts.alpha_rename,
+            false,
+        )?
+        .long_flag(
+            "output-json-array-only",
+            "the JSON report is just the duplicates array, without the summary",
+            &mut opts.output_json_array_only,
+            false,
+        )?
+        .long_flag(
+            "strict-utf8-paths",
+            "a file name which isn't valid UTF-8 is an error instead of being skipped",
+            &mut opts.strict_utf8_paths,
+            false,
+        )?
+        .long_flag(
+            "quiet-on-clean",
+            "when nothing is found print a single line instead of the summary",
+            &mut opts.quiet_on_clean,
+            false,
+        )?
+        .long_flag(
+            "no-duplihereignore",
+            "don't leave out the files matched by .duplihereignore files",
+            &mut opts.no_duplihereignore,
+            false,
+        )?
+        .long_arg(
+            "max-overlap-ratio",
+            "fraction of their lines two copies in the same file may share and still be \
+            reported, 0 to 1",
+            &mut opts.max_overlap_ratio,
+            Some("<ratio>"),
+            false,
+        )?
+        .long_arg(
+            "largest-files",
+            "after scanning list this many of the files with the most lines to stderr",
+            &mut opts.largest_files,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "normalize-unicode",
+            "put lines in this Unicode normalization form before comparing them, nfc and nfd need \
+            the unicode feature",
+            &mut normalize_unicode,
+            Some("<nfc|nfd|none>"),
+            false,
+        )?
+        .long_flag(
+            "tui",
+            "browse the results in the terminal and mark duplicates to add to the ignore file",
+            &mut opts.tui,
+            false,
+        )?
+        .long_arg(
+            "line-count-mode",
+            "count the lines which could be removed, or every line which is part of a copy",
+            &mut opts.line_count_mode,
+            Some("<removable|involved>"),
+            false,
+        )?
+        .long_list(
+            "fail-on-file",
+            "exit with 3 when a duplicate has a copy in a file matching the pattern, can repeat",
+            &mut opts.fail_on_file,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_arg(
+            "dot",
+            "also write the graph of which files share duplicates as Graphviz DOT to a file",
+            &mut opts.dot,
+            Some("<file name>"),
+            false,
+        )?
+        .long_arg(
+            "read-buffer-size",
+            "size in bytes of the buffer files are read with",
+            &mut opts.read_buffer_size,
+            Some("<bytes>"),
+            false,
+        )?
+        .long_flag(
+            "preset-ci",
+            "the options for a CI job: --json --scan-order name --relative-paths --json-errors, given options take precedence",
+            &mut opts.preset_ci,
+            false,
+        )?
+        .long_arg(
+            "whole-functions",
+            "report functions duplicated in their entirety instead of runs of lines, experimental",
+            &mut opts.whole_functions,
+            Some("<c|cpp|csharp|go|java|javascript|rust|none>"),
+            false,
+        )?
+        .long_arg(
+            "sort-locations-by",
+            "order the locations of a duplicate by start line, or by file keeping a file's together",
+            &mut opts.sort_locations_by,
+            Some("<line|file>"),
+            false,
+        )?
+        .long_flag(
+            "dump-collision-hash-stats",
+            "print how many windows of --lines collide to stderr, without finding duplicates",
+            &mut opts.dump_collision_hash_stats,
+            false,
+        )?
+        .long_arg(
+            "extract-to",
+            "also write the text of each duplicate to a file of its own in a directory",
+            &mut opts.extract_to,
+            Some("<directory>"),
+            false,
+        )?
+        .long_arg(
+            "ignore-first-line",
+            "leave the first n lines of every file out of the comparison",
+            &mut opts.ignore_first_line,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "compare-normalized",
+            "print to stderr how many more duplicates the normalization options find, no report",
+            &mut opts.compare_normalized,
+            false,
+        )?
+        .long_flag(
+            "no-dir-config",
+            "don't apply the settings in duplihere.toml files",
+            &mut opts.no_dir_config,
+            false,
+        )?
+        .long_arg(
+            "max-pairs",
+            "stop comparing locations after this many pairs, 0 is no limit",
+            &mut opts.max_pairs,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "emit-line-coverage",
+            "output the ranges of lines in each file which are part of a duplicate as JSON",
+            &mut opts.emit_line_coverage,
+            false,
+        )?
+        .long_flag(
+            "treat-includes-as-boundaries",
+            "don't let a duplicate in a C family file span an #include or other preprocessor line",
+            &mut opts.treat_includes_as_boundaries,
+            false,
+        )?
+        .long_flag(
+            "flag-trailing-ws",
+            "mark duplicates whose copies only differ in trailing whitespace",
+            &mut opts.flag_trailing_ws,
+            false,
+        )?
+        .long_arg(
+            "checkpoint",
+            "record the files scanned as the scan goes so it can be resumed",
+            &mut opts.checkpoint,
+            Some("<file name>"),
+            false,
+        )?
+        .long_arg(
+            "resume",
+            "resume an interrupted scan from its checkpoint",
+            &mut opts.resume,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "report-histogram",
+            "add the number of duplicates and their lines by size of duplicate to the summary",
+            &mut opts.report_histogram,
+            false,
+        )?
+        .long_flag(
+            "per-language-thresholds",
+            "use the --lines of each file's language from a built-in table, --lines for the rest",
+            &mut opts.per_language_thresholds,
+            false,
+        )?
+        .long_list(
+            "language-lines",
+            "with --per-language-thresholds, the lines for a language in place of the table's, can repeat",
+            &mut opts.language_lines,
+            Some("<language=number>"),
+            false,
+        )?
+        .long_flag(
+            "url-paths",
+            "write the paths in JSON as percent-encoded URLs relative to the current directory",
+            &mut opts.url_paths,
+            false,
+        )?
+        .long_flag(
+            "detect-rotations",
+            "also report blocks of --lines lines copied rotated or reversed, experimental",
+            &mut opts.detect_rotations,
+            false,
+        )?
+        .long_arg(
+            "summary-json",
+            "also write the summary counts as JSON to this file",
+            &mut opts.summary_json,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "blank-line-boundary",
+            "don't let a duplicate span a blank line",
+            &mut opts.blank_line_boundary,
+            false,
+        )?
+        .long_flag(
+            "fix-plan",
+            "instead of the report, rank the files whose copies would remove the most duplicated lines",
+            &mut opts.fix_plan,
+            false,
+        )?
+        .long_flag(
+            "strict-ignore",
+            "make an invalid entry in the ignore file an error instead of a warning",
+            &mut opts.strict_ignore,
+            false,
+        )?
+        .long_arg(
+            "output-split-by-severity",
+            "also write a report of the critical, major, minor and info duplicates each to a file in this directory",
+            &mut opts.output_split_by_severity,
+            Some("<directory>"),
+            false,
+        )?
+        .long_arg(
+            "base-dir",
+            "resolve relative -f, --left and --right patterns in this directory instead of the current one",
+            &mut opts.base_dir,
+            Some("<directory>"),
+            false,
+        )?
+        .long_arg(
+            "emit-ignore-template",
+            "also write an ignore file listing every duplicate commented out, to uncomment the ones to ignore",
+            &mut opts.emit_ignore_template,
+            Some("<file name>"),
+            false,
+        )?
+        .long_flag(
+            "report-relative-savings",
+            "show the percentage of all the duplicated lines each duplicate accounts for",
+            &mut opts.report_relative_savings,
+            false,
+        )?
+        .long_flag(
+            "location-hashes",
+            "add the hash of the text of each copy to the JSON to tell which copies are identical",
+            &mut opts.location_hashes,
+            false,
+        )?
+        .long_flag(
+            "skip-symlinked-dirs",
+            "don't enter symbolically linked directories when expanding -f, --left and --right patterns",
+            &mut opts.skip_symlinked_dirs,
+            false,
+        )?
+        .long_flag(
+            "progress-json",
+            "write progress events as JSON lines on stderr while scanning, for frontends",
+            &mut opts.progress_json,
+            false,
+        )?
+        .long_flag(
+            "flag-reindent",
+            "mark duplicates whose copies only differ in indentation",
+            &mut opts.flag_reindent,
+            false,
+        )?
+        .long_arg(
+            "files-manifest",
+            "also write every file scanned with its lines and whether it has duplicates, CSV or JSON with -j",
+            &mut opts.files_manifest,
+            Some("<file name>"),
+            false,
+        )?
+        .long_arg(
+            "hash-seed",
+            "seed the hashes with this number, hash signatures from different seeds can't be compared",
+            &mut opts.hash_seed,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "strip-license-header",
+            "leave the comment block at the top of every file out of the comparison",
+            &mut opts.strip_license_header,
+            false,
+        )?
+        .long_arg(
+            "license-header-lines",
+            "with --strip-license-header, the header is at most this many lines. 0 is unlimited",
+            &mut opts.license_header_lines,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "relative-paths",
+            "write the paths in JSON relative to the current directory, separated by /",
+            &mut opts.relative_paths,
+            false,
+        )?
+        .long_flag(
+            "json-errors",
+            "write an error which stops the run as a line of JSON on stderr",
+            &mut opts.json_errors,
+            false,
+        )?
+        .long_flag(
+            "no-json",
+            "turn off --json, eg. from --preset-ci",
+            &mut no_json,
+            false,
+        )?
+        .long_flag(
+            "absolute-paths",
+            "turn off --relative-paths, eg. from --preset-ci",
+            &mut absolute_paths,
+            false,
+        )?
+        .long_flag(
+            "text-errors",
+            "turn off --json-errors, eg. from --preset-ci",
+            &mut text_errors,
+            false,
+        )?
+        .done()?;
+
+    // Whether --preset-ci was given is the parser's to tell, a value of another option which
+    // happens to be --preset-ci isn't it.
+    if opts.preset_ci {
+        preset_ci(&mut opts);
+    }
+    opts.json &= !no_json;
+    opts.relative_paths &= !absolute_paths;
+    opts.json_errors &= !text_errors;
+
+    opts.lines = lines.value;
+    opts.normalize = normalize.value;
+    opts.normalize_unicode = normalize_unicode.value;
+    for (name, given) in [
+        ("lines", lines.given),
+        ("normalize", normalize.given),
+        ("normalize-unicode", normalize_unicode.given),
+        // A flag is only ever turned on.
+        ("ignore-case", opts.ignore_case),
+    ] {
+        if given {
+            opts.given.insert(name);
+        }
+    }
+
+    JSON_ERRORS.store(opts.json_errors, Ordering::Relaxed);
+    HASH_SEED.store(opts.hash_seed, Ordering::Relaxed);
+
+    for c in &opts.report_comments {
+        match c.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                opts.report_context
+                    .insert(key.trim().to_string(), value.to_string());
+            }
+            _ => {
+                fatal(
+                    2,
+                    format!(
+                        "--report-comment \"{}\" must be key=value with a non-empty key",
+                        c
+                    ),
+                );
+            }
+        }
+    }
+
+    if opts.license_header_lines != 0 && !opts.strip_license_header {
+        fatal(2, "--license-header-lines needs --strip-license-header");
+    }
+
+    if !opts.language_lines.is_empty() && !opts.per_language_thresholds {
+        fatal(2, "--language-lines needs --per-language-thresholds");
+    }
+    // An explicit -l is the lines of every language but those given with --language-lines.
+    match language_lines::table(&opts.language_lines, !opts.given.contains("lines")) {
+        Ok(table) => opts.language_table = table,
+        Err(e) => {
+            fatal(2, e);
+        }
+    }
+
+    if parser.wants_help() {
+        parser.print_help();
+    } else if !opts.trend.is_empty() {
+        if let Err(e) = trend::print_trend(&opts.trend, &opts.report_context) {
+            fatal(1, e);
+        }
+    } else {
+        if opts.left.is_empty() != opts.right.is_empty() {
+            fatal(2, "--left and --right must be used together");
+        }
+
+        if opts.file_globs.is_empty() && opts.left.is_empty() {
+            fatal(2, "required argument was not given: -f, --file");
+        }
+
+        if opts.lines == 0 {
+            fatal(
+                2,
+                "--lines must be at least 1, a zero line duplicate is meaningless",
+            );
+        }
+
+        if !opts.output.is_empty() && !opts.json {
+            fatal(2, "--output requires --json");
+        }
+
+        if opts.quickfix && opts.json {
+            fatal(2, "--quickfix and --json can't be used together");
+        }
+
+        if !opts.group_output.is_empty()
+            && opts.group_output != "stdout"
+            && opts.group_output != "stderr"
+        {
+            fatal(2, "--group-output must be stdout or stderr");
+        }
+
+        if opts.ignore.starts_with("https://") {
+            fatal(2, "-i can't fetch https:// URLs, there's no TLS support");
+        }
+
+        if cfg!(not(feature = "http")) && is_url(&opts.ignore) {
+            fatal(
+                2,
+                "-i can't fetch URLs, duplihere was built without the http feature",
+            );
+        }
+
+        if opts.tui && is_url(&opts.ignore) {
+            fatal(2, "--tui can't add to an ignore file given as a URL");
+        }
+
+        if opts.validate_ignore && opts.ignore.is_empty() {
+            fatal(2, "--validate-ignore requires --ignore");
+        }
+
+        if opts.strict && !opts.validate_ignore {
+            fatal(2, "--strict requires --validate-ignore");
+        }
 
-                if !l.is_empty() && !l.starts_with('#') {
-                    if let Ok(hv) = l.parse::<u64>() {
-                        ignores.insert(hv, true);
-                    } else {
-                        eprintln!("WARNING: Ignore file contains invalid hash value \"{}\"", l);
-                    }
-                }
+        if opts.tui {
+            if !cfg!(feature = "tui") {
+                fatal(
+                    2,
+                    "--tui isn't available, duplihere was built without the tui feature",
+                );
+            }
+            if opts.json || opts.quickfix || opts.syslog {
+                fatal(2, "--tui can't be used with --json, --quickfix or --syslog");
+            }
+            if !io::stdout().is_terminal() {
+                fatal(2, "--tui needs a terminal");
             }
         }
-        Err(e) => {
-            eprintln!(
-                "Unable to open supplied ignore file {}, reason: {}",
-                file_name, e
+
+        if opts.line_count_mode == LineCountMode::Involved && opts.dedup_identical_locations {
+            fatal(
+                2,
+                "--dedup-identical-locations only applies to --line-count-mode removable",
             );
-            process::exit(2);
         }
-    }
 
-    ignores
-}
+        if !(0.0..=1.0).contains(&opts.max_overlap_ratio) {
+            fatal(2, "--max-overlap-ratio must be between 0 and 1");
+        }
 
-/// Data structure which we use to store the count of how many files we have processed,
-/// a vector of file name strings and a hash map which maps file name to integer.  We do this so
-/// that we only have one copy of the file names in memory and use an integer to identify the
-/// files though out the source code.  This reduces memory consumption significantly and also
-/// results in file name compares becoming integer comparisons.
-#[derive(Debug)]
-struct FileId {
-    num_files: u32,
-    index_to_name: Vec<Arc<str>>,
-    name_to_index: HashMap<Arc<str>, u32>,
-}
+        if !READ_BUFFER_SIZES.contains(&opts.read_buffer_size) {
+            fatal(
+                2,
+                format!(
+                    "--read-buffer-size must be between {} and {} bytes",
+                    READ_BUFFER_SIZES.start(),
+                    READ_BUFFER_SIZES.end()
+                ),
+            );
+        }
 
-impl FileId {
-    fn new() -> FileId {
-        FileId {
-            num_files: 0,
-            index_to_name: vec![],
-            name_to_index: HashMap::new(),
+        if opts.output_json_array_only && !opts.json {
+            fatal(2, "--output-json-array-only requires --json");
         }
-    }
 
-    /// Given a file name, if it doesn't already exist we will store the information about which
-    /// index it is stored in and it's value.
-    fn register_file(&mut self, file_name: &str) -> Option<u32> {
-        if self.name_to_index.contains_key(file_name) {
-            return None;
+        if opts.compare_normalized
+            && opts.normalize == Normalize::None
+            && opts.normalize_unicode == UnicodeForm::None
+            && !opts.ignore_case
+            && !opts.alpha_rename
+        {
+            fatal(
+                2,
+                "--compare-normalized needs --normalize collapse, --normalize-unicode, \
+                --ignore-case or --alpha-rename",
+            );
         }
-        let num = self.num_files;
-        let name = Arc::new(file_name);
 
-        self.index_to_name.push(Arc::from(*name));
-        self.name_to_index.insert(Arc::from(*name), self.num_files);
-        if let Some(v) = self.num_files.checked_add(1) {
-            self.num_files = v;
-        } else {
-            eprintln!("Number of files processed exceeds {}", u32::MAX);
-            process::exit(2);
+        if opts.progress && opts.progress_json {
+            fatal(2, "--progress can't be used with --progress-json, pick one");
         }
-        Some(num)
-    }
 
-    /// Given an id (integer) return the actual file name.
-    fn id_to_name(&self, index: u32) -> Arc<str> {
-        self.index_to_name[index as usize].clone()
-    }
+        if opts.compare_normalized {
+            // Only a report writes these, --compare-normalized prints its counts instead.
+            for (given, name) in [
+                (!opts.files_manifest.is_empty(), "--files-manifest"),
+                (!opts.summary_json.is_empty(), "--summary-json"),
+                (!opts.html.is_empty(), "--html"),
+                (!opts.dot.is_empty(), "--dot"),
+                (!opts.extract_to.is_empty(), "--extract-to"),
+            ] {
+                if given {
+                    fatal(
+                        2,
+                        format!("--compare-normalized can't be used with {}", name),
+                    );
+                }
+            }
+        }
 
-    /// Number of files we have information for.
-    fn number_files(&self) -> u32 {
-        self.num_files
-    }
-}
+        if opts.alpha_rename && opts.suffix_array {
+            fatal(2, "--alpha-rename can't be used with --suffix-array");
+        }
 
-/// Command line options.
-#[derive(Debug)]
-pub struct Options {
-    lines: u32,
-    print: bool,
-    json: bool,
-    file_globs: Vec<String>,
-    ignore: String,
-    threads: usize,
-}
+        if (!opts.checkpoint.is_empty() || !opts.resume.is_empty())
+            && opts
+                .file_globs
+                .iter()
+                .chain(&opts.left)
+                .chain(&opts.right)
+                .any(|g| g == "-")
+        {
+            fatal(2, "--checkpoint and --resume can't be used with -f -");
+        }
 
-/// Default values for the command line options.
-impl Default for Options {
-    fn default() -> Options {
-        Options {
-            lines: 6,
-            print: false,
-            json: false,
-            file_globs: vec![],
-            ignore: "".to_string(),
-            threads: 4,
+        if opts.treat_includes_as_boundaries && opts.suffix_array {
+            fatal(
+                2,
+                "--treat-includes-as-boundaries can't be used with --suffix-array",
+            );
         }
-    }
-}
 
-static LONG_DESC: &str = "Find duplicate lines of text in one or more text files.
+        if !opts.base_dir.is_empty() && !Path::new(&opts.base_dir).is_dir() {
+            fatal(2, format!("--base-dir {} isn't a directory", opts.base_dir));
+        }
 
-The duplicated text can be at different levels of indention,
-but otherwise needs to be identical.
+        if opts.blank_line_boundary && opts.suffix_array {
+            fatal(2, "--blank-line-boundary can't be used with --suffix-array");
+        }
 
-More information: https://github.com/tasleson/duplihere";
+        if opts.whole_functions != Language::None && opts.suffix_array {
+            fatal(2, "--whole-functions can't be used with --suffix-array");
+        }
 
-fn main() -> Result<(), rags::Error> {
-    let mut opts = Options::default();
-    let mut parser = argparse!();
-    parser
-        .app_desc("find duplicate text")
-        .app_long_desc(LONG_DESC)
-        .group("argument", "description")?
-        .flag('p', "print", "print duplicate text", &mut opts.print, false)?
-        .flag('j', "json", "output JSON", &mut opts.json, false)?
-        .arg(
-            'l',
-            "lines",
-            "minimum number of duplicate lines",
-            &mut opts.lines,
-            Some("<number>"),
-            false,
-        )?
-        .list(
-            'f',
-            "file",
-            "pattern or file eg. \"**/*.[h|c]\" recursive, \"*.py\", \
-            \"file.ext\", can repeat",
-            &mut opts.file_globs,
-            Some("<pattern or specific file>"),
-            true,
-        )?
-        .arg(
-            'i',
-            "ignore",
-            "file containing hash values to ignore, one per line",
-            &mut opts.ignore,
-            Some("<file name>"),
-            false,
-        )?
-        .arg(
-            't',
-            "threads",
-            "number of threads to utilize. Set to 0 to match #cpu cores",
-            &mut opts.threads,
-            Some("<thread number>"),
-            false,
-        )?
-        .done()?;
+        if opts.min_block_lines_for_print != 0 && !opts.print {
+            fatal(2, "--min-block-lines-for-print requires --print");
+        }
+
+        if opts.tee && opts.output.is_empty() {
+            fatal(2, "--tee requires --output");
+        }
+
+        if opts.lines < MIN_SANE_LINES && !opts.force {
+            eprintln!(
+                "WARNING: --lines {} is less than {}, expect a very large report which is \
+                mostly noise (use --force to silence this warning)",
+                opts.lines, MIN_SANE_LINES
+            );
+        }
 
-    if parser.wants_help() {
-        parser.print_help();
-    } else {
         let results_hash: DashMap<u64, Collision>;
+        // The lines scanned of each file for --files-manifest.
+        let mut file_lines: Vec<(u32, usize)> = vec![];
         let mut ignore_hash: HashMap<u64, bool> = HashMap::new();
+        let mut file_tags: FileTags;
+        let file_options: FileOptions;
 
         // Dashmap scales well through ~3-4 threads, then stalls for our use case.
-        if opts.threads != 0 {
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(opts.threads)
-                .build_global()
-                .unwrap();
+        if let Threads::Count(n) = opts.threads {
+            if n != 0 {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .build_global()
+                    .unwrap();
+            }
         }
 
+        // Reading files is mostly IO, finding collisions is CPU bound and stalls on dashmap with
+        // more threads, so each can have its own pool.  With --threads auto the collision pool
+        // is sized once we know how much work there is.
+        let io_pool = phase_pool(opts.io_threads);
+
         {
             let mut files_to_process: Vec<(u32, String)> = vec![];
 
             if !opts.ignore.is_empty() {
-                ignore_hash = get_ignore_hashes(&opts.ignore);
+                ignore_hash = get_ignore_hashes(&opts.ignore, opts.strict_ignore);
+            }
+
+            if !opts.boilerplate.is_empty() {
+                opts.boilerplate_hashes = get_boilerplate_hashes(&opts.boilerplate, &opts);
             }
 
+            let modified_cutoff = if opts.modified_since.is_empty() {
+                None
+            } else {
+                match parse_duration(&opts.modified_since) {
+                    Some(d) => SystemTime::now().checked_sub(d),
+                    None => {
+                        fatal(
+                            2,
+                            format!(
+                                "Invalid --modified-since duration '{}', expected a number \
+                            followed by one of s, m, h, d, w eg. 7d",
+                                opts.modified_since
+                            ),
+                        );
+                    }
+                }
+            };
+
             {
                 // Hold the lock on FILE_LOOKUP for the duration as we are single threaded here.
                 let mut file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+                file_lookup_locked.case_fold = opts.case_fold_paths;
+                if opts.url_paths || opts.relative_paths {
+                    file_lookup_locked.url_root = scan_root(&opts).ok();
+                    file_lookup_locked.url_encode = opts.url_paths;
+                }
+
+                let mut path_ignore = if opts.no_duplihereignore {
+                    None
+                } else {
+                    scan_root(&opts).ok().map(PathIgnore::new)
+                };
+                let base = (!opts.base_dir.is_empty())
+                    .then(|| scan_root(&opts).ok())
+                    .flatten()
+                    .map(|d| Pattern::escape(&d.to_string_lossy()));
+
+                let mut left_names: HashSet<String> = HashSet::new();
+                let mut right_names: HashSet<String> = HashSet::new();
+                let globs = opts
+                    .file_globs
+                    .iter()
+                    .map(|g| (g, None))
+                    .chain(opts.left.iter().map(|g| (g, Some(Side::Left))))
+                    .chain(opts.right.iter().map(|g| (g, Some(Side::Right))));
+
+                'globs: for (g, side) in globs {
+                    if g == "-" {
+                        let mut content = vec![];
+                        if let Err(e) = io::stdin().read_to_end(&mut content) {
+                            fatal(1, format!("Unable to read stdin, reason {}", e));
+                        }
+                        if let Some(fid) = file_lookup_locked.register_file(&opts.scan_stdin_as) {
+                            VIRTUAL_FILES
+                                .lock()
+                                .unwrap()
+                                .insert(opts.scan_stdin_as.clone(), Arc::from(content));
+                            files_to_process.push((fid, opts.scan_stdin_as.clone()));
+                        }
+                        continue;
+                    }
 
-                for g in &opts.file_globs {
-                    match glob(g) {
+                    let g = match &base {
+                        Some(base) if !Path::new(g).is_absolute() => format!("{}/{}", base, g),
+                        _ => g.clone(),
+                    };
+                    let entries = if opts.skip_symlinked_dirs {
+                        walk::files(&g)
+                    } else {
+                        glob(&g).map(|paths| {
+                            paths
+                                .map(|p| p.map_err(|e| format!("{:?}", e)))
+                                .collect::<Vec<_>>()
+                        })
+                    };
+                    match entries {
                         Ok(entries) => {
                             for filename in entries {
                                 match filename {
                                     Ok(specific_file) => {
-                                        if specific_file.is_file() {
+                                        if specific_file.is_file()
+                                            && modified_after(&specific_file, modified_cutoff)
+                                        {
                                             let file_str_name =
-                                                String::from(specific_file.to_str().unwrap());
+                                                match utf8_name(&specific_file, &opts) {
+                                                    Some(name) => name,
+                                                    None => continue,
+                                                };
 
                                             match canonicalize(file_str_name.clone()) {
                                                 Ok(fn_ok) => {
-                                                    let c_name_str =
-                                                        String::from(fn_ok.to_str().unwrap());
+                                                    let c_name_str = match utf8_name(&fn_ok, &opts)
+                                                    {
+                                                        Some(name) => name,
+                                                        None => continue,
+                                                    };
+
+                                                    let ignored = path_ignore
+                                                        .as_mut()
+                                                        .map(|p| p.is_ignored(&fn_ok))
+                                                        .unwrap_or(Ok(false));
+                                                    match ignored {
+                                                        Ok(true) => continue,
+                                                        Ok(false) => {}
+                                                        Err(e) => {
+                                                            fatal(2, e);
+                                                        }
+                                                    }
+
+                                                    if opts.max_files != 0
+                                                        && file_lookup_locked.number_files() as u64
+                                                            >= opts.max_files
+                                                    {
+                                                        let msg = format!(
+                                                            "Reached --max-files {}, remaining \
+                                                            files are not processed, results \
+                                                            depend on glob ordering",
+                                                            opts.max_files
+                                                        );
+                                                        eprintln!("WARNING: {}", msg);
+                                                        record_warning(WarningKind::FileLimit, msg);
+                                                        break 'globs;
+                                                    }
+
+                                                    match side {
+                                                        Some(Side::Left) => {
+                                                            left_names.insert(c_name_str.clone());
+                                                        }
+                                                        Some(Side::Right) => {
+                                                            right_names.insert(c_name_str.clone());
+                                                        }
+                                                        None => {}
+                                                    }
 
                                                     if let Some(fid) = file_lookup_locked
                                                         .register_file(&c_name_str)
@@ -697,49 +6031,372 @@ fn main() -> Result<(), rags::Error> {
                                                     }
                                                 }
                                                 Err(e) => {
-                                                    eprintln!(
-                                                    "WARNING: Unable to process file {}, reason {}",
-                                                    file_str_name, e
-                                                );
+                                                    let msg = format!(
+                                                        "Unable to process file {}, reason {}",
+                                                        file_str_name, e
+                                                    );
+                                                    eprintln!("WARNING: {}", msg);
+                                                    record_warning(
+                                                        WarningKind::UnreadableFile,
+                                                        msg,
+                                                    );
                                                 }
                                             }
                                         }
                                     }
                                     Err(e) => {
-                                        eprintln!("Unable to process {:?}", e);
-                                        process::exit(1);
+                                        fatal(1, format!("Unable to process {}", e));
                                     }
                                 }
                             }
                         }
                         Err(e) => {
-                            eprintln!("Bad glob pattern supplied '{}', error: {}", g, e);
-                            process::exit(1);
+                            fatal(
+                                1,
+                                format!("Bad glob pattern supplied '{}', error: {}", g, e),
+                            );
+                        }
+                    }
+                }
+
+                let mut deny_names: HashSet<String> = HashSet::new();
+                if !opts.deny_list.is_empty() {
+                    for (name, snippet) in read_deny_list(&opts.deny_list, opts.lines) {
+                        if let Some(fid) = file_lookup_locked.register_file(&name) {
+                            VIRTUAL_FILES
+                                .lock()
+                                .unwrap()
+                                .insert(name.clone(), Arc::from(snippet));
+                            files_to_process.push((fid, name.clone()));
+                            deny_names.insert(name);
                         }
                     }
                 }
+
+                order_files(
+                    &mut files_to_process,
+                    opts.scan_order,
+                    &mut file_lookup_locked,
+                );
+
+                let ids = |names: &HashSet<String>| -> HashSet<u32> {
+                    files_to_process
+                        .iter()
+                        .filter(|f| names.contains(&f.1))
+                        .map(|f| f.0)
+                        .collect()
+                };
+                // A file in both trees is on the left.
+                right_names.retain(|n| !left_names.contains(n));
+                file_tags = FileTags {
+                    deny: ids(&deny_names),
+                    left: ids(&left_names),
+                    right: ids(&right_names),
+                    lines: HashMap::new(),
+                };
+            }
+
+            file_options = if opts.no_dir_config && !opts.per_language_thresholds {
+                FileOptions::default()
+            } else {
+                apply_dir_configs(&files_to_process, &mut opts, &mut file_tags)
+            };
+
+            if opts.compare_normalized {
+                compare_normalized(&files_to_process, &opts, &ignore_hash, &file_tags, &io_pool);
+                return Ok(());
             }
 
             let collision_hashes: DashMap<u64, Vec<(u32, u32)>> = DashMap::new();
-            let file_hashes: Mutex<Vec<Vec<u64>>> =
-                Mutex::new(vec![vec![0; 0]; files_to_process.len()]);
-
-            files_to_process.par_iter().for_each(|e| {
-                process_file(
-                    e.0,
-                    &e.1,
-                    opts.lines as usize,
-                    &file_hashes,
-                    &collision_hashes,
-                )
+            let file_hashes: Mutex<SignatureArena> =
+                Mutex::new(SignatureArena::new(files_to_process.len()));
+
+            let (checkpoint, resumed) = start_checkpoint(&files_to_process, &opts, &file_options);
+            let mut files_remaining = files_to_process.clone();
+            if !resumed.is_empty() {
+                for record in resumed {
+                    files_remaining[record.fid as usize].0 = u32::MAX;
+                    resume_file(
+                        record,
+                        &opts,
+                        &file_options,
+                        &file_hashes,
+                        &collision_hashes,
+                        checkpoint.as_ref(),
+                    );
+                }
+                files_remaining.retain(|f| f.0 != u32::MAX);
+            }
+
+            let file_size = text_size;
+            let progress = if opts.progress_json || (opts.progress && io::stderr().is_terminal()) {
+                let bytes_total = files_remaining.iter().map(|e| file_size(&e.1)).sum();
+                Some(Progress::new(
+                    files_remaining.len() as u64,
+                    bytes_total,
+                    opts.progress_json,
+                ))
+            } else {
+                None
+            };
+
+            thread::scope(|scope| {
+                let renderer = progress.as_ref().map(|p| scope.spawn(move || p.render()));
+
+                in_pool(&io_pool, || {
+                    let stats = opts.rayon_stats.then(|| PhaseStats::new("process files"));
+                    files_remaining.par_iter().for_each(|e| {
+                        let process = || {
+                            process_file(
+                                e.0,
+                                &e.1,
+                                file_options.get(e.0, &opts),
+                                &file_hashes,
+                                &collision_hashes,
+                                checkpoint.as_ref(),
+                            )
+                        };
+                        match &stats {
+                            Some(s) => s.record(process),
+                            None => process(),
+                        }
+                        if let Some(p) = &progress {
+                            p.file_done(file_size(&e.1));
+                        }
+                    });
+                    if let Some(s) = &stats {
+                        s.report();
+                    }
+                });
+
+                if let (Some(p), Some(r)) = (&progress, renderer) {
+                    p.finish();
+                    r.thread().unpark();
+                }
             });
 
-            results_hash =
-                find_collisions(collision_hashes, &mut file_hashes.lock().unwrap(), &opts);
+            if let Some(checkpoint) = &checkpoint {
+                if let Err(e) = checkpoint.finish() {
+                    fatal(1, format!("Unable to write the checkpoint, reason {}", e));
+                }
+            }
+
+            if opts.emit_hashes {
+                let file_hashes = file_hashes.lock().unwrap();
+                if let Err(e) = emit_hashes(&files_to_process, &file_hashes, &opts, &file_tags) {
+                    if e.kind() != io::ErrorKind::BrokenPipe {
+                        fatal(1, format!("Unable to write line signatures, reason {}", e));
+                    }
+                }
+                return Ok(());
+            }
+
+            if opts.verbose {
+                let (files, vectors, saved) = file_hashes.lock().unwrap().sharing();
+                eprintln!(
+                    "{} files share {} line signature vectors, {:.1} MB not stored twice",
+                    files,
+                    vectors,
+                    saved as f64 / (1024.0 * 1024.0)
+                );
+            }
+
+            if opts.largest_files != 0 {
+                print_largest_files(
+                    &files_to_process,
+                    &file_hashes.lock().unwrap(),
+                    &file_tags,
+                    opts.largest_files,
+                );
+            }
+
+            if opts.dump_collision_hash_stats {
+                print_collision_hash_stats(&collision_hashes, opts.lines);
+                return Ok(());
+            }
+
+            if opts.benchmark_threads {
+                benchmark_threads(&collision_hashes, &file_hashes.lock().unwrap(), &opts);
+                return Ok(());
+            }
+
+            if let Some(p) = &progress {
+                p.phase("collide");
+            }
+            results_hash = collide(
+                collision_hashes,
+                &file_hashes.lock().unwrap(),
+                &files_to_process,
+                &opts,
+            );
+            if let Some(p) = &progress {
+                p.phase("report");
+            }
+
+            if !opts.files_manifest.is_empty() {
+                let file_hashes = file_hashes.lock().unwrap();
+                file_lines = files_to_process
+                    .iter()
+                    .filter(|f| !file_tags.deny.contains(&f.0))
+                    .map(|f| (f.0, file_hashes.get(f.0).len()))
+                    .collect();
+            }
         }
 
-        process_report(results_hash, &opts, &ignore_hash);
+        process_report(results_hash, &opts, &ignore_hash, &file_tags, &file_lines);
     }
 
     Ok(())
 }
+
+/// A directory for a unit test, removed with what's in it when it's dropped, so it's cleaned up
+/// when an assertion fails too.
+#[cfg(test)]
+struct TestDir {
+    path: PathBuf,
+}
+
+#[cfg(test)]
+impl TestDir {
+    /// An empty directory, name only has to be unique among the tests of a module.
+    fn new(name: &str) -> TestDir {
+        static NEXT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let path = std::env::temp_dir()
+            .join(format!("duplihere-unit-{}", process::id()))
+            .join(format!("{}-{}", NEXT.fetch_add(1, Ordering::Relaxed), name));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        // Names are compared to canonical names, so the directory has to be one.
+        TestDir {
+            path: canonicalize(&path).unwrap(),
+        }
+    }
+
+    /// Write a file, creating the directories it's in.
+    fn write(&self, name: impl AsRef<Path>, content: &str) -> PathBuf {
+        let path = self.path.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords_are_sorted() {
+        assert!(KEYWORDS.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn identifiers_but_not_keywords_or_numbers() {
+        let (blinded, identifiers) = split_identifiers("if (total > 10) return total_2;");
+        assert_eq!(blinded, "if (\0 > 10) return \0;");
+        assert_eq!(
+            identifiers,
+            vec![calculate_hash(&"total"), calculate_hash(&"total_2")]
+        );
+
+        let (blinded, _) = split_identifiers("while (x) y = None");
+        assert_eq!(blinded, "while (\0) \0 = None");
+    }
+
+    #[test]
+    fn identical_files_share_their_signatures() {
+        let mut arena = SignatureArena::new(4);
+        arena.insert(0, vec![1, 2, 3]);
+        arena.insert(1, vec![4, 5]);
+        arena.insert(2, vec![1, 2, 3]);
+        arena.insert(3, vec![]);
+        assert_eq!(arena.get(0), [1, 2, 3]);
+        assert_eq!(arena.get(1), [4, 5]);
+        assert_eq!(arena.get(2), [1, 2, 3]);
+        assert!(arena.get(3).is_empty());
+        assert_eq!(arena.file_index, [1, 2, 1, 0]);
+        assert_eq!(arena.sharing(), (3, 2, 24));
+    }
+
+    #[test]
+    fn overlapping_copies() {
+        // Copies of 6 lines starting 6 lines apart are adjacent.
+        assert!(overlap((0, 10), (0, 16), 6, 0.0));
+        assert!(!overlap((0, 10), (0, 17), 6, 0.0));
+        assert!(!overlap((0, 10), (1, 10), 6, 0.0));
+        // Sharing half their lines.
+        assert!(overlap((0, 10), (0, 13), 6, 0.5));
+        assert!(!overlap((0, 14), (0, 10), 6, 0.5));
+        // Only starting on the same line.
+        assert!(overlap((0, 10), (0, 10), 6, 1.0));
+        assert!(!overlap((0, 10), (0, 11), 6, 1.0));
+    }
+
+    #[test]
+    fn url_paths() {
+        assert_eq!(percent_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(percent_encode("a b#1?x=%.c"), "a%20b%231%3Fx%3D%25.c");
+        assert_eq!(percent_encode("\u{fc}.c"), "%C3%BC.c");
+
+        let root = Path::new("/home/user/proj");
+        assert_eq!(
+            url_path("/home/user/proj/sub dir/a b#1.c", root, true),
+            "sub%20dir/a%20b%231.c"
+        );
+        assert_eq!(
+            url_path("/home/user/other/\u{fc}.c", root, true),
+            "../other/%C3%BC.c"
+        );
+        assert_eq!(
+            url_path("/home/user/proj/sub dir/a b#1.c", root, false),
+            "sub dir/a b#1.c"
+        );
+    }
+
+    /// A writer which fails every write.
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dup_text() {
+        let dir = TestDir::new("dup_text");
+        let path = dir.write("a.txt", "one\r\ntwo\nthree;four\nfive\n");
+        let name = path.to_str().unwrap();
+
+        let mut out: Vec<u8> = vec![];
+        write_dup_text(&mut out, name, 1, 2, b'\n', 4096).unwrap();
+        assert_eq!(out, b"two\nthree;four\n");
+
+        // Lines end with the record separator, the last one with whatever ends the file.
+        let mut out: Vec<u8> = vec![];
+        write_dup_text(&mut out, name, 1, 5, b';', 4096).unwrap();
+        assert_eq!(out, b"four\nfive\n");
+
+        assert!(write_dup_text(&mut Broken, name, 0, 1, b'\n', 4096).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Read only memory mapping of a file for --mmap, hashing lines straight out of the page cache
+//! saves copying every line into a buffer.  If the file is truncated while it's mapped, reading
+//! past the new end raises SIGBUS and we die, so it's only for trees which aren't being changed
+//! during the scan.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read only and owned by us.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map the whole of the file, which must not be empty.
+    pub fn map(file: &File) -> io::Result<Mmap> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't map an empty file",
+            ));
+        }
+
+        // SAFETY: we ask for a new read only, private mapping of a file descriptor which is valid
+        // for the duration of the call, the result is checked before it's used.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: ptr is a live mapping of len readable bytes until we are dropped.
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len are exactly what mmap gave us, and no slices outlive self.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Files left out of the scan by .duplihereignore files, which use the gitignore syntax.  The
+//! ignore file in the scan root, the current directory, applies to everything below it and one in
+//! a sub directory to everything below that, taking precedence over the ones above it.
+//!
+//! As with gitignore the last pattern which matches decides, "!" re-includes what an earlier
+//! pattern excluded, a trailing "/" only matches directories and a pattern containing any other
+//! "/" is relative to the directory of the ignore file, otherwise it matches a name at any
+//! depth.  A file in an excluded directory can't be re-included.
+
+use glob::{MatchOptions, Pattern};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const IGNORE_FILE_NAME: &str = ".duplihereignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+struct Rule {
+    pattern: Pattern,
+    negate: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Result<Rule, String>> {
+        let mut l = line.trim_end();
+        if l.is_empty() || l.starts_with('#') {
+            return None;
+        }
+
+        // A leading backslash escapes a literal "#" or "!".
+        let negate = l.starts_with('!');
+        if negate || l.starts_with("\\#") || l.starts_with("\\!") {
+            l = &l[1..];
+        }
+
+        let dir_only = l.ends_with('/');
+        let l = l.trim_end_matches('/');
+        let anchored = l.contains('/');
+        let l = l.trim_start_matches('/');
+        if l.is_empty() {
+            return None;
+        }
+
+        Some(
+            Pattern::new(l)
+                .map(|pattern| Rule {
+                    pattern,
+                    negate,
+                    dir_only,
+                    anchored,
+                })
+                .map_err(|e| format!("invalid pattern '{}', {}", line, e)),
+        )
+    }
+
+    /// Does the rule match the path, relative to the directory of its ignore file.
+    fn matches(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.pattern.matches_path_with(relative, MATCH_OPTIONS)
+        } else {
+            relative
+                .file_name()
+                .map(|n| {
+                    self.pattern
+                        .matches_with(&n.to_string_lossy(), MATCH_OPTIONS)
+                })
+                .unwrap_or(false)
+        }
+    }
+}
+
+pub struct PathIgnore {
+    root: PathBuf,
+    // The rules of the ignore file in each directory we've looked in, empty if it has none.
+    dirs: HashMap<PathBuf, Vec<Rule>>,
+}
+
+impl PathIgnore {
+    /// Ignore files are looked for in root and the directories below it, root must be
+    /// canonical.
+    pub fn new(root: PathBuf) -> PathIgnore {
+        PathIgnore {
+            root,
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn load(&mut self, dir: &Path) -> Result<(), String> {
+        if self.dirs.contains_key(dir) {
+            return Ok(());
+        }
+
+        let file = dir.join(IGNORE_FILE_NAME);
+        let rules = match std::fs::read_to_string(&file) {
+            Ok(content) => content
+                .lines()
+                .filter_map(Rule::parse)
+                .collect::<Result<Vec<Rule>, String>>()
+                .map_err(|e| format!("{}: {}", file.display(), e))?,
+            Err(_) => vec![],
+        };
+        self.dirs.insert(dir.to_path_buf(), rules);
+        Ok(())
+    }
+
+    /// Is the path excluded by the rules of the ignore files in the directories from root down
+    /// to the one it is in, the deepest last.
+    fn excluded(&self, path: &Path, bases: &[&Path], is_dir: bool) -> bool {
+        let mut rc = false;
+        for base in bases {
+            let relative = match path.strip_prefix(base) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            for rule in &self.dirs[*base] {
+                if rule.matches(relative, is_dir) {
+                    rc = !rule.negate;
+                }
+            }
+        }
+        rc
+    }
+
+    /// Is the file, a canonical path, left out of the scan.  Files outside of root never are.
+    pub fn is_ignored(&mut self, file: &Path) -> Result<bool, String> {
+        let dirs: Vec<&Path> = match file.strip_prefix(&self.root) {
+            Ok(_) => {
+                let mut dirs: Vec<&Path> = file
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|d| d.starts_with(&self.root))
+                    .collect();
+                dirs.reverse();
+                dirs
+            }
+            Err(_) => return Ok(false),
+        };
+        for d in &dirs {
+            self.load(d)?;
+        }
+
+        // A directory is only matched by the ignore files above it.
+        for (i, d) in dirs.iter().enumerate().skip(1) {
+            if self.excluded(d, &dirs[..i], true) {
+                return Ok(true);
+            }
+        }
+        Ok(self.excluded(file, &dirs, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::TestDir;
+
+    fn rule(line: &str) -> Rule {
+        Rule::parse(line).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert!(Rule::parse("").is_none());
+        assert!(Rule::parse("# comment").is_none());
+        assert!(Rule::parse("/").is_none());
+        assert!(Rule::parse("[").unwrap().is_err());
+
+        let r = rule("!keep.c");
+        assert!(r.negate && !r.anchored && !r.dir_only);
+        let r = rule("\\!bang.c");
+        assert!(!r.negate);
+        assert!(r.matches(Path::new("!bang.c"), false));
+        assert!(rule("\\#hash.c").matches(Path::new("#hash.c"), false));
+
+        let r = rule("build/");
+        assert!(r.dir_only && !r.anchored);
+        assert!(r.matches(Path::new("a/build"), true));
+        assert!(!r.matches(Path::new("a/build"), false));
+    }
+
+    #[test]
+    fn anchoring() {
+        // Without a "/" a pattern matches a name at any depth.
+        let r = rule("*.pb.go");
+        assert!(r.matches(Path::new("x.pb.go"), false));
+        assert!(r.matches(Path::new("a/b/x.pb.go"), false));
+
+        // With one it's relative to the ignore file's directory.
+        for line in ["/gen.c", "src/gen.c"] {
+            let r = rule(line);
+            assert!(r.anchored, "{}", line);
+        }
+        assert!(rule("/gen.c").matches(Path::new("gen.c"), false));
+        assert!(!rule("/gen.c").matches(Path::new("src/gen.c"), false));
+        assert!(rule("src/gen.c").matches(Path::new("src/gen.c"), false));
+        assert!(!rule("src/gen.c").matches(Path::new("a/src/gen.c"), false));
+        assert!(!rule("src/*.c").matches(Path::new("src/a/gen.c"), false));
+    }
+
+    #[test]
+    fn negation_and_nesting() {
+        let root = TestDir::new("negation_and_nesting");
+        std::fs::create_dir_all(root.join("sub/vendor")).unwrap();
+        root.write(IGNORE_FILE_NAME, "*.gen.c\n!keep.gen.c\nvendor/\n");
+        root.write(
+            Path::new("sub").join(IGNORE_FILE_NAME),
+            "!*.gen.c\nkeep.gen.c\n",
+        );
+
+        let mut ignore = PathIgnore::new(root.to_path_buf());
+        let mut ignored = |name: &str| ignore.is_ignored(&root.join(name)).unwrap();
+        // The last matching pattern decides.
+        assert!(ignored("a.gen.c"));
+        assert!(!ignored("keep.gen.c"));
+        assert!(!ignored("a.c"));
+        // The nearer ignore file takes precedence.
+        assert!(!ignored("sub/a.gen.c"));
+        assert!(ignored("sub/keep.gen.c"));
+        // A file in an excluded directory can't be re-included.
+        assert!(ignored("sub/vendor/a.c"));
+        assert!(ignored("sub/vendor/a.gen.c"));
+        // Files outside of the root never are.
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/a.gen.c")).unwrap());
+    }
+}
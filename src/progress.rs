@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Progress reporting while we read and hash files.  The worker threads only bump atomic
+//! counters, a separate thread renders a status line to stderr a few times a second.
+//!
+//! With --progress-json it writes events instead, one JSON object per line, for a frontend to
+//! show its own progress:
+//! ```json
+//! {"phase":"scan","files_done":120,"files_total":480,"percent":25.0}
+//! ```
+//! phase is "scan" while files are read and hashed, an event is written when files_done has
+//! changed since the last one, the last is once every file is done.  Then there's one event
+//! for each phase after it, "collide" when the duplicates are searched for and "report" when the
+//! report is written, their counts are those of the finished scan.
+
+use serde::Serialize;
+
+use std::io::{self, prelude::*};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const REFRESH: Duration = Duration::from_millis(250);
+
+pub struct Progress {
+    files_total: u64,
+    bytes_total: u64,
+    files_done: AtomicU64,
+    bytes_done: AtomicU64,
+    finished: AtomicBool,
+    start: Instant,
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    phase: &'a str,
+    files_done: u64,
+    files_total: u64,
+    percent: f64,
+}
+
+impl Progress {
+    /// A status line, or with json events.
+    pub fn new(files_total: u64, bytes_total: u64, json: bool) -> Progress {
+        Progress {
+            files_total,
+            bytes_total,
+            files_done: AtomicU64::new(0),
+            bytes_done: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+            start: Instant::now(),
+            json,
+        }
+    }
+
+    /// Record that a file of the given size has been processed.
+    pub fn file_done(&self, bytes: u64) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Render the status line until finish() is called, run this on its own thread.
+    pub fn render(&self) {
+        if self.json {
+            return self.render_events();
+        }
+
+        let mut stderr = io::stderr();
+        loop {
+            let finished = self.finished.load(Ordering::Relaxed);
+            let _ = write!(stderr, "\r{}\x1b[K", self.status());
+            if finished {
+                let _ = writeln!(stderr);
+                return;
+            }
+            thread::park_timeout(REFRESH);
+        }
+    }
+
+    fn render_events(&self) {
+        let mut last = None;
+        loop {
+            let finished = self.finished.load(Ordering::Relaxed);
+            let files = self.files_done.load(Ordering::Relaxed);
+            if last != Some(files) {
+                self.phase("scan");
+                last = Some(files);
+            }
+            if finished {
+                return;
+            }
+            thread::park_timeout(REFRESH);
+        }
+    }
+
+    /// With json, write an event for the phase with the files done so far.
+    pub fn phase(&self, phase: &str) {
+        if !self.json {
+            return;
+        }
+        let files_done = self.files_done.load(Ordering::Relaxed);
+        let percent = if self.files_total == 0 {
+            100.0
+        } else {
+            (files_done as f64 * 1000.0 / self.files_total as f64).round() / 10.0
+        };
+        let event = Event {
+            phase,
+            files_done,
+            files_total: self.files_total,
+            percent,
+        };
+        // A line at a time so events aren't interleaved with warnings.
+        if let Ok(mut line) = serde_json::to_string(&event) {
+            line.push('\n');
+            let _ = io::stderr().write_all(line.as_bytes());
+        }
+    }
+
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    fn status(&self) -> String {
+        let files = self.files_done.load(Ordering::Relaxed);
+        let bytes = self.bytes_done.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64().max(0.001);
+        let bytes_per_sec = bytes as f64 / elapsed;
+
+        let eta = if bytes == 0 {
+            "--:--".to_string()
+        } else {
+            let remaining = self.bytes_total.saturating_sub(bytes) as f64 / bytes_per_sec;
+            let secs = remaining.round() as u64;
+            format!("{:02}:{:02}", secs / 60, secs % 60)
+        };
+
+        format!(
+            "Scanned {}/{} files, {:.1}/{:.1} MB, {:.0} files/s, {:.1} MB/s, ETA {}",
+            files,
+            self.files_total,
+            mb(bytes),
+            mb(self.bytes_total),
+            files as f64 / elapsed,
+            mb(bytes_per_sec as u64),
+            eta
+        )
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Blocks of --lines lines which are another block rotated or reversed, --detect-rotations, eg. a
+//! config section whose entries were rotated when it was copied: b c d a, or d c b a, of a b c d.
+//! Other orders of the same lines, eg. b a d c, aren't.  Each window of --lines signatures is
+//! hashed with its signatures sorted, which is cheap and leaves few windows to look at more
+//! closely, those which share it but not the hash of their lines in order.  Of those, windows
+//! whose smallest rotation, of the lines or the lines reversed, is the same are reordered copies.
+//! Unlike exact duplicates they aren't grown past --lines, and a block is only found when a window
+//! holds all of it, the windows of a longer rotated block hold different lines on each side.
+
+use std::collections::{HashMap, HashSet};
+
+use dashmap::DashMap;
+
+use crate::{calculate_hash, Collision, SignatureArena};
+
+/// File id, start and hash of the lines in order of a window.
+type Window = (u32, u32, u64);
+
+/// Add the reordered blocks of min_lines in the files to results_hash.
+pub fn find_collisions(
+    file_hashes: &SignatureArena,
+    files: &[(u32, String)],
+    min_lines: u32,
+    results_hash: &DashMap<u64, Collision>,
+) {
+    let n = min_lines as usize;
+    if n == 0 {
+        return;
+    }
+
+    // The windows by the hash of their lines sorted.
+    let mut windows: HashMap<u64, Vec<Window>> = HashMap::new();
+    for (fid, _) in files {
+        let hashes = file_hashes.get(*fid);
+        for (start, window) in hashes.windows(n).enumerate() {
+            let mut sorted = window.to_vec();
+            sorted.sort_unstable();
+            windows.entry(calculate_hash(&sorted)).or_default().push((
+                *fid,
+                start as u32,
+                calculate_hash(&window),
+            ));
+        }
+    }
+
+    // The windows with the same lines in another order, by their smallest rotation.
+    let mut groups: Vec<(u64, Vec<Window>)> = windows
+        .into_values()
+        .filter(|w| w.iter().any(|l| l.2 != w[0].2))
+        .flat_map(|w| {
+            let mut rotations: HashMap<u64, Vec<Window>> = HashMap::new();
+            for l in w {
+                let start = l.1 as usize;
+                let lines = &file_hashes.get(l.0)[start..start + n];
+                rotations.entry(rotation_hash(lines)).or_default().push(l);
+            }
+            rotations.into_iter()
+        })
+        .filter(|(_, w)| w.iter().any(|l| l.2 != w[0].2))
+        .collect();
+    for (_, w) in groups.iter_mut() {
+        w.sort_unstable();
+    }
+    groups.sort_unstable_by_key(|(_, w)| (w[0].0, w[0].1));
+
+    // Where a reordered block has been reported, the windows of a block overlap one another and
+    // only the first is reported.
+    let mut reported: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (rotation, w) in groups {
+        let mut taken: Vec<Window> = vec![];
+        for l in w {
+            let overlaps = |s: &u32| s.abs_diff(l.1) < min_lines;
+            let clash = reported
+                .get(&l.0)
+                .map(|r| r.iter().any(overlaps))
+                .unwrap_or(false)
+                || taken.iter().any(|t| t.0 == l.0 && overlaps(&t.1));
+            if !clash {
+                taken.push(l);
+            }
+        }
+        if taken.len() < 2 || taken.iter().all(|l| l.2 == taken[0].2) {
+            continue;
+        }
+
+        for l in &taken {
+            reported.entry(l.0).or_default().push(l.1);
+        }
+        // Copies in the same order are an exact duplicate, reported as one, only the first of
+        // each order is a reordered copy.
+        let mut orders = HashSet::new();
+        taken.retain(|l| orders.insert(l.2));
+
+        let key = calculate_hash(&("reordered", rotation));
+        let mut c = Collision::new(key, min_lines, taken.iter().map(|l| (l.0, l.1)).collect());
+        c.reordered = true;
+        results_hash.insert(key, c);
+    }
+}
+
+/// The hash of the smallest of the rotations of the lines and of the lines reversed, it's the same
+/// for lines which are a rotation or reversal of one another.
+fn rotation_hash(lines: &[u64]) -> u64 {
+    let reversed: Vec<u64> = lines.iter().rev().copied().collect();
+    let smallest = [lines, &reversed[..]]
+        .iter()
+        .flat_map(|l| (0..l.len()).map(move |r| [&l[r..], &l[..r]].concat()))
+        .min()
+        .unwrap_or_default();
+    calculate_hash(&smallest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The reordered duplicates found in files of the signatures, their locations sorted.
+    fn find(files: &[&[u64]], min_lines: u32) -> Vec<Vec<(u32, u32)>> {
+        let mut arena = SignatureArena::new(files.len());
+        let mut names = vec![];
+        for (fid, signatures) in files.iter().enumerate() {
+            arena.insert(fid as u32, signatures.to_vec());
+            names.push((fid as u32, format!("{}.conf", fid)));
+        }
+        let results = DashMap::new();
+        find_collisions(&arena, &names, min_lines, &results);
+        let mut rc: Vec<Vec<(u32, u32)>> = results
+            .into_iter()
+            .map(|(_, c)| {
+                assert!(c.reordered);
+                assert_eq!(c.num_lines, min_lines);
+                c.files
+            })
+            .collect();
+        rc.sort();
+        rc
+    }
+
+    #[test]
+    fn rotations() {
+        assert_eq!(
+            find(&[&[9, 1, 2, 3, 4], &[3, 4, 1, 2, 8]], 4),
+            vec![vec![(0, 1), (1, 0)]]
+        );
+    }
+
+    #[test]
+    fn reversed() {
+        assert_eq!(
+            find(&[&[1, 2, 3, 4], &[4, 3, 2, 1]], 4),
+            vec![vec![(0, 0), (1, 0)]]
+        );
+        // Reversed and rotated.
+        assert_eq!(
+            find(&[&[1, 2, 3, 4], &[2, 1, 4, 3]], 4),
+            vec![vec![(0, 0), (1, 0)]]
+        );
+    }
+
+    #[test]
+    fn other_orders_are_not_rotations() {
+        assert!(find(&[&[1, 2, 3, 4, 5], &[2, 1, 3, 5, 4]], 5).is_empty());
+        assert!(find(&[&[1, 2, 3, 4], &[1, 3, 2, 4]], 4).is_empty());
+    }
+
+    #[test]
+    fn exact_copies_are_listed_once() {
+        assert_eq!(
+            find(&[&[1, 2, 3, 4], &[1, 2, 3, 4], &[2, 3, 4, 1]], 4),
+            vec![vec![(0, 0), (2, 0)]]
+        );
+        // Only exact copies, that's an exact duplicate.
+        assert!(find(&[&[1, 2, 3, 4], &[1, 2, 3, 4]], 4).is_empty());
+    }
+
+    #[test]
+    fn rotation_hash_of_each_rotation() {
+        let h = rotation_hash(&[1, 2, 3]);
+        for l in [[2, 3, 1], [3, 1, 2], [3, 2, 1], [1, 3, 2], [2, 1, 3]] {
+            assert_eq!(rotation_hash(&l), h);
+        }
+        assert_ne!(rotation_hash(&[1, 2, 3, 4]), rotation_hash(&[1, 3, 2, 4]));
+    }
+}
@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Finding duplicates when we are only given one file.  The general path registers every
+//! rolling window in the collision hash and then compares every pair of locations in each bucket,
+//! which is O(n²) per bucket and gets very expensive for a large file with repeating content.
+//!
+//! Here we build a suffix array over the sequence of line signatures instead, prefix doubling
+//! with counting sorts, O(n log n), followed by the LCP array (Kasai, O(n)).  A bottom up walk of
+//! the LCP array visits every lcp-interval, the intervals which are at least --lines long and
+//! can't be extended to the left are the maximal repeats, the same text the general path reports
+//! once its results have been scrubbed of shorter suffixes.
+//!
+//! This is used with --suffix-array when exactly one file is given.  On ordinary source files the
+//! results match the general path, they differ in two corner cases:
+//! * When the copies of a maximal repeat overlap, eg. a block repeated back to back, the general
+//!   path can still report a shorter non-overlapping piece of it, we drop the overlapping copies.
+//! * The general path skips a window which has the same hash as the one before it, so runs of
+//!   identical lines (closing braces, blank lines) can hide a duplicate from it, we find those.
+
+use std::hash::{Hash, Hasher};
+
+use dashmap::DashMap;
+
+use crate::{new_hasher, overlap, Collision};
+
+/// Build the suffix array for a sequence of line signatures.
+fn suffix_array(signatures: &[u64]) -> Vec<usize> {
+    let n = signatures.len();
+    if n == 0 {
+        return vec![];
+    }
+
+    // Initial ranks are the dense rank of each line signature.
+    let mut sorted: Vec<u64> = signatures.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let mut rank: Vec<usize> = signatures
+        .iter()
+        .map(|s| sorted.binary_search(s).unwrap())
+        .collect();
+
+    let mut sa: Vec<usize> = (0..n).collect();
+    sa.sort_unstable_by_key(|&i| rank[i]);
+
+    let mut tmp = vec![0; n];
+    let mut k = 1;
+    loop {
+        // Sort by (rank[i], rank[i + k]), suffixes which run out sort first.  Ordering by the
+        // second key is done by taking the previous order shifted by k, then a stable counting
+        // sort on the first key.
+        let mut by_second: Vec<usize> = (n - k.min(n)..n).collect();
+        by_second.extend(sa.iter().filter(|&&i| i >= k).map(|&i| i - k));
+
+        let max_rank = rank.iter().copied().max().unwrap_or(0);
+        let mut count = vec![0usize; max_rank + 2];
+        for &r in &rank {
+            count[r + 1] += 1;
+        }
+        for i in 1..count.len() {
+            count[i] += count[i - 1];
+        }
+        for &i in &by_second {
+            sa[count[rank[i]]] = i;
+            count[rank[i]] += 1;
+        }
+
+        let key = |i: usize| (rank[i], if i + k < n { rank[i + k] + 1 } else { 0 });
+        tmp[sa[0]] = 0;
+        for w in 1..n {
+            tmp[sa[w]] = tmp[sa[w - 1]] + usize::from(key(sa[w - 1]) != key(sa[w]));
+        }
+        std::mem::swap(&mut rank, &mut tmp);
+
+        if rank[sa[n - 1]] == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// lcp[i] is the length of the longest common prefix of the suffixes sa[i - 1] and sa[i].
+fn lcp_array(signatures: &[u64], sa: &[usize]) -> Vec<usize> {
+    let n = signatures.len();
+    let mut rank = vec![0; n];
+    for (i, &s) in sa.iter().enumerate() {
+        rank[s] = i;
+    }
+
+    let mut lcp = vec![0; n];
+    let mut h = 0;
+    for i in 0..n {
+        if rank[i] > 0 {
+            let j = sa[rank[i] - 1];
+            while i + h < n && j + h < n && signatures[i + h] == signatures[j + h] {
+                h += 1;
+            }
+            lcp[rank[i]] = h;
+            h = h.saturating_sub(1);
+        } else {
+            h = 0;
+        }
+    }
+    lcp
+}
+
+/// What precedes the suffixes of an interval, if they are not all preceded by the same line the
+/// repeat can't be extended to the left.
+#[derive(Clone, Copy, PartialEq)]
+enum Left {
+    Empty,
+    Line(u64),
+    Diverse,
+}
+
+impl Left {
+    fn merge(self, other: Left) -> Left {
+        match (self, other) {
+            (Left::Empty, o) | (o, Left::Empty) => o,
+            (Left::Line(a), Left::Line(b)) if a == b => Left::Line(a),
+            _ => Left::Diverse,
+        }
+    }
+}
+
+struct Interval {
+    lcp: usize,
+    lb: usize,
+    left: Left,
+}
+
+/// Report the maximal repeats in a single file as collisions in results_hash.  Locations of a
+/// repeat which overlap a previous location more than --max-overlap-ratio allows are dropped,
+/// the same as the general path does.
+pub fn find_collisions(
+    fid: u32,
+    signatures: &[u64],
+    min_lines: u32,
+    max_overlap_ratio: f64,
+    results_hash: &DashMap<u64, Collision>,
+) {
+    let n = signatures.len();
+    let min_lines = min_lines as usize;
+    if n <= min_lines || min_lines == 0 {
+        return;
+    }
+
+    let sa = suffix_array(signatures);
+    let lcp = lcp_array(signatures, &sa);
+    let leaf = |p: usize| {
+        if p == 0 {
+            Left::Diverse
+        } else {
+            Left::Line(signatures[p - 1])
+        }
+    };
+
+    let mut stack = vec![Interval {
+        lcp: 0,
+        lb: 0,
+        left: Left::Empty,
+    }];
+
+    for i in 1..=n {
+        let cur = if i < n { lcp[i] } else { 0 };
+        let mut lb = i - 1;
+        let mut child = leaf(sa[i - 1]);
+
+        while cur < stack.last().unwrap().lcp {
+            let mut node = stack.pop().unwrap();
+            node.left = node.left.merge(child);
+            if node.lcp >= min_lines && node.left == Left::Diverse {
+                report(
+                    fid,
+                    signatures,
+                    &sa[node.lb..i],
+                    node.lcp,
+                    max_overlap_ratio,
+                    results_hash,
+                );
+            }
+            lb = node.lb;
+            child = node.left;
+        }
+
+        let top = stack.last_mut().unwrap();
+        if cur > top.lcp {
+            stack.push(Interval {
+                lcp: cur,
+                lb,
+                left: child,
+            });
+        } else {
+            top.left = top.left.merge(child);
+        }
+    }
+}
+
+fn report(
+    fid: u32,
+    signatures: &[u64],
+    positions: &[usize],
+    num_lines: usize,
+    max_overlap_ratio: f64,
+    results_hash: &DashMap<u64, Collision>,
+) {
+    let mut starts: Vec<usize> = positions.to_vec();
+    starts.sort_unstable();
+
+    let mut files: Vec<(u32, u32)> = vec![];
+    for s in starts {
+        match files.last() {
+            Some(&prev) if overlap(prev, (fid, s as u32), num_lines as u32, max_overlap_ratio) => {}
+            _ => files.push((fid, s as u32)),
+        }
+    }
+
+    if files.len() < 2 {
+        return;
+    }
+
+    let start = files[0].1 as usize;
+    let mut s = new_hasher();
+    for l in &signatures[start..start + num_lines] {
+        l.hash(&mut s);
+    }
+    let key = s.finish();
+
+    results_hash.insert(key, Collision::new(key, num_lines as u32, files));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffix_and_lcp_arrays() {
+        // b a n a n a
+        let signatures = [2, 1, 14, 1, 14, 1];
+        let sa = suffix_array(&signatures);
+        let mut naive: Vec<usize> = (0..signatures.len()).collect();
+        naive.sort_by_key(|&i| &signatures[i..]);
+        assert_eq!(sa, naive);
+        assert_eq!(sa, vec![5, 3, 1, 0, 4, 2]);
+        assert_eq!(lcp_array(&signatures, &sa), vec![0, 1, 3, 0, 0, 2]);
+    }
+}
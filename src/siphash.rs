@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! SipHash-1-3 with keys of our choosing, the line signatures, window hashes and hash signatures
+//! are made with it, --hash-seed.  std's DefaultHasher is SipHash-1-3 with both keys 0 today, but
+//! std only promises it's stable within a build, a later std could change it and with it every
+//! hash signature in every ignore file.  This is the same algorithm written out, so with the
+//! default seed of 0 the signatures are those DefaultHasher has always given and they can't
+//! change underneath us.  Integers, strings and the rest are fed to it by their Hash
+//! implementations exactly as they are to DefaultHasher.
+
+use std::hash::Hasher;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SipHasher13 {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // Bytes written which don't make up a whole word yet, little endian, and how many.
+    tail: u64,
+    ntail: usize,
+    length: usize,
+}
+
+macro_rules! sip_round {
+    ($s:expr) => {{
+        $s.v0 = $s.v0.wrapping_add($s.v1);
+        $s.v1 = $s.v1.rotate_left(13);
+        $s.v1 ^= $s.v0;
+        $s.v0 = $s.v0.rotate_left(32);
+        $s.v2 = $s.v2.wrapping_add($s.v3);
+        $s.v3 = $s.v3.rotate_left(16);
+        $s.v3 ^= $s.v2;
+        $s.v0 = $s.v0.wrapping_add($s.v3);
+        $s.v3 = $s.v3.rotate_left(21);
+        $s.v3 ^= $s.v0;
+        $s.v2 = $s.v2.wrapping_add($s.v1);
+        $s.v1 = $s.v1.rotate_left(17);
+        $s.v1 ^= $s.v2;
+        $s.v2 = $s.v2.rotate_left(32);
+    }};
+}
+
+/// Up to 8 bytes as a little endian word.
+fn word(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .enumerate()
+        .fold(0, |w, (i, b)| w | (*b as u64) << (8 * i))
+}
+
+impl SipHasher13 {
+    pub fn new_with_keys(k0: u64, k1: u64) -> SipHasher13 {
+        SipHasher13 {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            ntail: 0,
+            length: 0,
+        }
+    }
+
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        sip_round!(self);
+        self.v0 ^= m;
+    }
+}
+
+impl Hasher for SipHasher13 {
+    fn write(&mut self, msg: &[u8]) {
+        self.length += msg.len();
+
+        let mut msg = msg;
+        if self.ntail != 0 {
+            let needed = (8 - self.ntail).min(msg.len());
+            self.tail |= word(&msg[..needed]) << (8 * self.ntail);
+            self.ntail += needed;
+            msg = &msg[needed..];
+            if self.ntail < 8 {
+                return;
+            }
+            self.compress(self.tail);
+            self.tail = 0;
+            self.ntail = 0;
+        }
+
+        let mut words = msg.chunks_exact(8);
+        for w in &mut words {
+            self.compress(word(w));
+        }
+        let rest = words.remainder();
+        self.tail = word(rest);
+        self.ntail = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut s = *self;
+        let b = ((s.length as u64 & 0xff) << 56) | s.tail;
+        s.compress(b);
+        s.v2 ^= 0xff;
+        sip_round!(s);
+        sip_round!(s);
+        sip_round!(s);
+        s.v0 ^ s.v1 ^ s.v2 ^ s.v3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::hash::Hash;
+
+    fn hash<T: Hash + ?Sized>(seed: u64, t: &T) -> u64 {
+        let mut h = SipHasher13::new_with_keys(seed, seed);
+        t.hash(&mut h);
+        h.finish()
+    }
+
+    fn hash_bytes(seed: u64, bytes: &[u8]) -> u64 {
+        let mut h = SipHasher13::new_with_keys(seed, seed);
+        h.write(bytes);
+        h.finish()
+    }
+
+    #[test]
+    fn known_answers() {
+        // With both keys 0 these are what DefaultHasher gave when this was written, so the hash
+        // signatures of ignore files made before --hash-seed still match.
+        let bytes: Vec<u8> = (0..64).collect();
+        assert_eq!(hash_bytes(0, &[]), 0xd1fb_a762_150c_532c);
+        assert_eq!(hash_bytes(0, &bytes[..8]), 0xead4_11e6_7ebe_2eea);
+        assert_eq!(hash_bytes(0, &bytes[..15]), 0xf30e_b725_bb91_c9ea);
+        assert_eq!(hash_bytes(0, &bytes), 0x75e0_5fd5_bbc8_70c6);
+        assert_eq!(hash(0, "if (total > 10)"), 0x3de4_aae8_fa68_ac11);
+        assert_eq!(hash(0, &(42u64, 7u32)), 0x2973_4723_cca4_e5be);
+
+        // And with --hash-seed 1, pinned so a change to the seeded hashes is noticed.
+        assert_eq!(hash_bytes(1, &bytes[..15]), 0xafc4_76f0_6cef_5d80);
+    }
+
+    #[test]
+    fn seeds() {
+        let text = "if (total > 10)";
+        assert_eq!(hash(7, text), hash(7, text));
+        assert_ne!(hash(7, text), hash(8, text));
+        assert_ne!(hash(0, text), hash(1, text));
+    }
+
+    #[test]
+    fn writes_in_pieces() {
+        let bytes: Vec<u8> = (0..64).collect();
+        for split in [1, 3, 7, 8, 9, 20, 63] {
+            let mut h = SipHasher13::new_with_keys(5, 5);
+            h.write(&bytes[..split]);
+            h.write(&bytes[split..]);
+            assert_eq!(h.finish(), hash_bytes(5, &bytes), "{}", split);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Instrumentation for --rayon-stats, how the work of each parallel phase was spread over the
+//! rayon worker threads.  Each worker counts the items it processed and the time it spent on
+//! them, whatever is left of the phase's wall clock time it spent idle, stealing or waiting.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+#[derive(Default)]
+struct Worker {
+    items: AtomicU64,
+    busy_ns: AtomicU64,
+}
+
+pub struct PhaseStats {
+    name: &'static str,
+    start: Instant,
+    // One per worker in the current pool, plus a last one for work done outside of it.
+    workers: Vec<Worker>,
+}
+
+impl PhaseStats {
+    /// Start a phase, call this from within the pool which will do the work.
+    pub fn new(name: &'static str) -> PhaseStats {
+        PhaseStats {
+            name,
+            start: Instant::now(),
+            workers: (0..=rayon::current_num_threads())
+                .map(|_| Worker::default())
+                .collect(),
+        }
+    }
+
+    /// Run f, one item of work, accounting it to the worker thread we are on.
+    pub fn record<R>(&self, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let rc = f();
+        let index = rayon::current_thread_index()
+            .unwrap_or(self.workers.len() - 1)
+            .min(self.workers.len() - 1);
+        let w = &self.workers[index];
+        w.items.fetch_add(1, Ordering::Relaxed);
+        w.busy_ns
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        rc
+    }
+
+    /// Print the per worker table for the phase to stderr.
+    pub fn report(&self) {
+        let wall_ns = self.start.elapsed().as_nanos() as u64;
+        let ms = |ns: u64| ns as f64 / 1_000_000.0;
+        let pool_size = self.workers.len() - 1;
+
+        eprintln!(
+            "rayon stats: {}, {:.3} ms wall, {} workers",
+            self.name,
+            ms(wall_ns),
+            pool_size
+        );
+        eprintln!(
+            "{:>8} {:>12} {:>12} {:>12}",
+            "worker", "items", "busy ms", "idle ms"
+        );
+
+        let mut busy_total = 0;
+        let mut busy_max = 0;
+        for (i, w) in self.workers.iter().enumerate() {
+            let items = w.items.load(Ordering::Relaxed);
+            let busy = w.busy_ns.load(Ordering::Relaxed);
+            if i == pool_size && items == 0 {
+                continue;
+            }
+            let worker = if i == pool_size {
+                "other".to_string()
+            } else {
+                i.to_string()
+            };
+            eprintln!(
+                "{:>8} {:>12} {:>12.3} {:>12.3}",
+                worker,
+                items,
+                ms(busy),
+                ms(wall_ns.saturating_sub(busy))
+            );
+            busy_total += busy;
+            busy_max = busy_max.max(busy);
+        }
+
+        if busy_total != 0 && pool_size != 0 {
+            // 1.0 is a perfect split, pool_size means one worker did everything.
+            let mean = busy_total as f64 / pool_size as f64;
+            eprintln!(
+                "imbalance (max busy / mean busy): {:.2}",
+                busy_max as f64 / mean
+            );
+        }
+    }
+}
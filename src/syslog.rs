@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Minimal syslog client, messages are sent in RFC 3164 format to the local /dev/log socket
+//! which is also where journald listens on systemd based systems.  Everything is logged with
+//! the user facility.
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+
+const LOG_USER: u8 = 1 << 3;
+
+/// Syslog priorities we make use of.
+#[derive(Debug, Clone, Copy)]
+pub enum Priority {
+    Err = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+pub struct Syslog {
+    socket: UnixDatagram,
+}
+
+impl Syslog {
+    pub fn connect() -> io::Result<Syslog> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect("/dev/log")?;
+        Ok(Syslog { socket })
+    }
+
+    pub fn log(&self, priority: Priority, msg: &str) -> io::Result<()> {
+        let line = format!(
+            "<{}>duplihere[{}]: {}",
+            LOG_USER | priority as u8,
+            process::id(),
+            msg
+        );
+        self.socket.send(line.as_bytes()).map(|_| ())
+    }
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Duplication over time from JSON reports saved by earlier runs, --trend.  Nothing is scanned,
+//! we only read the reports and print the duplicated line totals as CSV along with the change
+//! from the report before.
+//!
+//! Any --report-comment context is written first as "# key=value" lines.
+//!
+//! Every report must have the same schema version, reports written before the version was
+//! recorded count as version 0.  When every report has a generated_at timestamp they are ordered
+//! by it, otherwise they are taken in the order given.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+
+use serde_json::Value;
+
+use crate::context_header;
+
+struct Entry {
+    name: String,
+    version: u64,
+    generated_at: Option<u64>,
+    num_lines: u64,
+}
+
+fn load(name: &str) -> Result<Entry, String> {
+    let file = File::open(name).map_err(|e| format!("Unable to open {}, reason {}", name, e))?;
+    let report: Value = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Unable to parse report {}, reason {}", name, e))?;
+
+    let num_lines = report["num_lines"]
+        .as_u64()
+        .ok_or_else(|| format!("Report {} has no num_lines, is it a JSON report?", name))?;
+
+    Ok(Entry {
+        name: name.to_string(),
+        version: report["version"].as_u64().unwrap_or(0),
+        generated_at: report["generated_at"].as_u64(),
+        num_lines,
+    })
+}
+
+/// Quote a CSV field if it needs it.
+pub fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Print the trend for the reports to stdout as CSV.
+pub fn print_trend(reports: &[String], context: &BTreeMap<String, String>) -> Result<(), String> {
+    let mut entries = reports
+        .iter()
+        .map(|r| load(r))
+        .collect::<Result<Vec<Entry>, String>>()?;
+
+    if let Some(first) = entries.first() {
+        if let Some(other) = entries.iter().find(|e| e.version != first.version) {
+            return Err(format!(
+                "Report {} has schema version {} but {} has version {}, \
+                reports must share a schema version",
+                other.name, other.version, first.name, first.version
+            ));
+        }
+    }
+
+    if entries.iter().all(|e| e.generated_at.is_some()) {
+        entries.sort_by_key(|e| e.generated_at);
+    }
+
+    print!("{}", context_header(context));
+    println!("report,generated_at,num_lines,delta");
+    let mut prev: Option<u64> = None;
+    for e in &entries {
+        let generated_at = e.generated_at.map(|t| t.to_string()).unwrap_or_default();
+        let delta = prev
+            .map(|p| format!("{:+}", e.num_lines as i64 - p as i64))
+            .unwrap_or_default();
+        println!(
+            "{},{},{},{}",
+            csv_field(&e.name),
+            generated_at,
+            e.num_lines,
+            delta
+        );
+        prev = Some(e.num_lines);
+    }
+    Ok(())
+}
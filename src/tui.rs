@@ -0,0 +1,283 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Interactive browser for the results, --tui, built with the tui feature.  Duplicates are listed
+//! largest first, one can be opened to see where its copies are and the duplicated text, and
+//! duplicates can be marked to be added to the ignore file when we exit.
+
+use crossterm::cursor;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{execute, queue};
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::panic;
+
+/// What we show for a duplicate.
+pub struct Entry {
+    pub key: u64,
+    pub num_lines: u32,
+    pub locations: Vec<String>,
+    // The text of the first copy, read when the duplicate is opened.
+    pub text: Box<dyn Fn() -> Vec<String>>,
+}
+
+enum View {
+    List,
+    Detail { text: Vec<String>, scroll: usize },
+}
+
+struct State {
+    selected: usize,
+    top: usize,
+    marked: BTreeSet<usize>,
+    view: View,
+}
+
+/// Puts the terminal back as it was when dropped, so it's restored however we leave the
+/// browser, including by an error or a panic.
+struct Screen;
+
+impl Screen {
+    fn enter(out: &mut impl Write) -> io::Result<Screen> {
+        terminal::enable_raw_mode()?;
+        let screen = Screen;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+fn restore() {
+    let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = terminal::disable_raw_mode();
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Run the browser until the user quits, returning the keys of the duplicates they marked.
+/// Nothing is returned when they interrupt it with Ctrl-C.
+pub fn run(entries: &[Entry]) -> io::Result<Vec<u64>> {
+    // A panic message written to the alternate screen would be lost with it, restore the
+    // terminal before it's printed.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+
+    let mut out = io::stdout();
+    let rc = Screen::enter(&mut out).and_then(|_screen| event_loop(entries, &mut out));
+    let _ = panic::take_hook();
+    rc.map(|marked| marked.iter().map(|&i| entries[i].key).collect())
+}
+
+fn event_loop(entries: &[Entry], out: &mut impl Write) -> io::Result<BTreeSet<usize>> {
+    let mut state = State {
+        selected: 0,
+        top: 0,
+        marked: BTreeSet::new(),
+        view: View::List,
+    };
+
+    loop {
+        let (cols, rows) = terminal::size()?;
+        // Less a header and a footer line.
+        let body = (rows as usize).saturating_sub(2).max(1);
+        render(entries, &mut state, out, cols as usize, body)?;
+
+        let key = match event::read()? {
+            // Raw mode doesn't turn Ctrl-C into SIGINT.
+            Event::Key(k)
+                if k.modifiers.contains(KeyModifiers::CONTROL) && k.code == KeyCode::Char('c') =>
+            {
+                return Ok(BTreeSet::new());
+            }
+            Event::Key(k) if k.kind == KeyEventKind::Press => k.code,
+            _ => continue,
+        };
+
+        match &mut state.view {
+            View::List => match key {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(state.marked),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    state.selected = state.selected.saturating_sub(1);
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    state.selected = (state.selected + 1).min(entries.len().saturating_sub(1));
+                }
+                KeyCode::PageUp => state.selected = state.selected.saturating_sub(body),
+                KeyCode::PageDown => {
+                    state.selected = (state.selected + body).min(entries.len().saturating_sub(1));
+                }
+                KeyCode::Char(' ') | KeyCode::Char('m') => toggle(&mut state, entries.len()),
+                KeyCode::Enter | KeyCode::Right if !entries.is_empty() => {
+                    state.view = View::Detail {
+                        text: (entries[state.selected].text)(),
+                        scroll: 0,
+                    };
+                }
+                _ => {}
+            },
+            View::Detail { scroll, .. } => match key {
+                KeyCode::Char('q') | KeyCode::Esc | KeyCode::Left | KeyCode::Backspace => {
+                    state.view = View::List;
+                }
+                KeyCode::Up | KeyCode::Char('k') => *scroll = scroll.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => *scroll += 1,
+                KeyCode::PageUp => *scroll = scroll.saturating_sub(body),
+                KeyCode::PageDown => *scroll += body,
+                KeyCode::Char(' ') | KeyCode::Char('m') => toggle(&mut state, entries.len()),
+                _ => {}
+            },
+        }
+    }
+}
+
+fn toggle(state: &mut State, entries: usize) {
+    if state.selected >= entries {
+        return;
+    }
+    if !state.marked.remove(&state.selected) {
+        state.marked.insert(state.selected);
+    }
+}
+
+/// Cut a line to the width of the terminal.
+fn fit(s: &str, cols: usize) -> String {
+    s.chars().take(cols).collect()
+}
+
+fn render(
+    entries: &[Entry],
+    state: &mut State,
+    out: &mut impl Write,
+    cols: usize,
+    body: usize,
+) -> io::Result<()> {
+    queue!(out, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+
+    let marked = state.marked.len();
+    let lines: Vec<String> = match &mut state.view {
+        View::List => {
+            if state.selected < state.top {
+                state.top = state.selected;
+            } else if state.selected >= state.top + body {
+                state.top = state.selected + 1 - body;
+            }
+            queue!(
+                out,
+                Print(fit(
+                    &format!(
+                        "duplihere: {} duplicates, {} marked for the ignore file",
+                        entries.len(),
+                        marked
+                    ),
+                    cols
+                ))
+            )?;
+            entries
+                .iter()
+                .enumerate()
+                .skip(state.top)
+                .take(body)
+                .map(|(i, e)| {
+                    format!(
+                        "{} {:>6} lines {:>4} copies  {}",
+                        if state.marked.contains(&i) { '*' } else { ' ' },
+                        e.num_lines,
+                        e.locations.len(),
+                        e.locations.first().map(|l| l.as_str()).unwrap_or("")
+                    )
+                })
+                .collect()
+        }
+        View::Detail { text, scroll } => {
+            let e = &entries[state.selected];
+            let mut all: Vec<String> = e.locations.clone();
+            all.push("".to_string());
+            all.extend(text.iter().cloned());
+            *scroll = (*scroll).min(all.len().saturating_sub(body));
+            queue!(
+                out,
+                Print(fit(
+                    &format!(
+                        "{}Hash signature {}, {} lines in {} copies",
+                        if state.marked.contains(&state.selected) {
+                            "* "
+                        } else {
+                            ""
+                        },
+                        e.key,
+                        e.num_lines,
+                        e.locations.len()
+                    ),
+                    cols
+                ))
+            )?;
+            all.into_iter().skip(*scroll).take(body).collect()
+        }
+    };
+
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, row as u16 + 1))?;
+        let selected = matches!(state.view, View::List) && state.top + row == state.selected;
+        if selected {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        queue!(out, Print(fit(line, cols)))?;
+        if selected {
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+    }
+
+    let help = match state.view {
+        View::List => "up/down move  enter open  space mark  q quit",
+        View::Detail { .. } => "up/down scroll  space mark  esc back",
+    };
+    queue!(
+        out,
+        cursor::MoveTo(0, body as u16 + 1),
+        SetAttribute(Attribute::Reverse),
+        Print(fit(help, cols)),
+        SetAttribute(Attribute::Reset)
+    )?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        State {
+            selected: 0,
+            top: 0,
+            marked: BTreeSet::new(),
+            view: View::List,
+        }
+    }
+
+    #[test]
+    fn toggle_without_duplicates() {
+        let mut s = state();
+        toggle(&mut s, 0);
+        assert!(s.marked.is_empty());
+    }
+
+    #[test]
+    fn toggle_marks_and_unmarks() {
+        let mut s = state();
+        s.selected = 1;
+        toggle(&mut s, 2);
+        assert_eq!(s.marked.iter().copied().collect::<Vec<usize>>(), vec![1]);
+        toggle(&mut s, 2);
+        assert!(s.marked.is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Expanding a -f pattern without entering symlinked directories, --skip-symlinked-dirs.  glob
+//! follows a symbolic link to a directory like any other directory, so with a tree linked into
+//! the scan root a `**` pattern walks both copies, the files are only scanned once as their
+//! canonical names are the same but the expansion is done twice, and a link to a directory above
+//! it walks the tree over and over.  glob has no way to leave them out, so this walks the
+//! directories itself and matches the pattern against each file.
+//!
+//! The walk starts in the directory of the part of the pattern without wildcards, which is
+//! followed even if it's a link, and stops at the depth the pattern can match unless it has a
+//! `**`.  Symbolic links to files are matched as files.  As with glob entries in a directory are
+//! in name order and `*` matches names starting with a ".".
+
+use glob::{MatchOptions, Pattern, PatternError};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// The files matching pattern, an error for each directory which couldn't be read.
+pub fn files(pattern: &str) -> Result<Vec<Result<PathBuf, String>>, PatternError> {
+    let compiled = Pattern::new(pattern)?;
+    let components: Vec<&str> = pattern.split('/').collect();
+    let literal = components
+        .iter()
+        .take_while(|c| !c.contains(['*', '?', '[']))
+        .count();
+
+    if literal == components.len() {
+        let path = PathBuf::from(pattern);
+        return Ok(if path.exists() {
+            vec![Ok(path)]
+        } else {
+            vec![]
+        });
+    }
+
+    let root = match components[..literal].join("/") {
+        r if r.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        r => r,
+    };
+    let max_depth = if components[literal..].contains(&"**") {
+        None
+    } else {
+        Some(components.len() - literal)
+    };
+
+    let mut rc = vec![];
+    walk(Path::new(&root), 1, max_depth, &compiled, &mut rc);
+    Ok(rc)
+}
+
+fn walk(
+    dir: &Path,
+    depth: usize,
+    max_depth: Option<usize>,
+    pattern: &Pattern,
+    rc: &mut Vec<Result<PathBuf, String>>,
+) {
+    // An empty dir is the current directory, the names found in it are left relative as glob
+    // leaves them.
+    let entries = match fs::read_dir(if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }) {
+        Ok(entries) => entries,
+        Err(e) => {
+            rc.push(Err(format!("{}: {}", dir.display(), e)));
+            return;
+        }
+    };
+    let mut names: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| dir.join(e.file_name()))
+        .collect();
+    names.sort_unstable();
+
+    for path in names {
+        let md = match fs::symlink_metadata(&path) {
+            Ok(md) => md,
+            Err(_) => continue,
+        };
+        if md.is_dir() {
+            if max_depth.map(|m| depth < m).unwrap_or(true) {
+                walk(&path, depth + 1, max_depth, pattern, rc);
+            }
+        } else if path.is_file() && pattern.matches_path_with(&path, MATCH_OPTIONS) {
+            rc.push(Ok(path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::TestDir;
+
+    /// A directory for a test with the files in it, each holding its name.
+    fn setup(name: &str, files: &[&str]) -> TestDir {
+        let dir = TestDir::new(name);
+        for f in files {
+            dir.write(f, f);
+        }
+        dir
+    }
+
+    /// The files matching pattern in dir, relative to it.
+    fn matching(dir: &Path, pattern: &str) -> Vec<String> {
+        files(&format!("{}/{}", dir.display(), pattern))
+            .unwrap()
+            .into_iter()
+            .map(|f| {
+                f.unwrap()
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn depth_and_order() {
+        let dir = setup(
+            "depth_and_order",
+            &[
+                "b.c",
+                "a.c",
+                ".hidden.c",
+                "a.h",
+                "sub/c.c",
+                "sub/deeper/d.c",
+            ],
+        );
+        assert_eq!(matching(&dir, "*.c"), [".hidden.c", "a.c", "b.c"]);
+        assert_eq!(matching(&dir, "*/*.c"), ["sub/c.c"]);
+        assert_eq!(
+            matching(&dir, "**/*.c"),
+            [".hidden.c", "a.c", "b.c", "sub/c.c", "sub/deeper/d.c"]
+        );
+        assert_eq!(matching(&dir, "sub/**/*.c"), ["sub/c.c", "sub/deeper/d.c"]);
+    }
+
+    #[test]
+    fn literal_patterns() {
+        let dir = setup("literal_patterns", &["a.c"]);
+        assert_eq!(matching(&dir, "a.c"), ["a.c"]);
+        assert!(matching(&dir, "b.c").is_empty());
+        assert!(files("[").is_err());
+
+        let missing = files(&format!("{}/missing/*.c", dir.display())).unwrap();
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_dirs() {
+        use std::os::unix::fs::symlink;
+
+        let dir = setup("symlinked_dirs", &["src/a.c", "vendor/b.c"]);
+        symlink(dir.join("vendor"), dir.join("src/lib")).unwrap();
+        symlink(dir.join("vendor/b.c"), dir.join("src/b.c")).unwrap();
+        // A link to a directory above, which would be walked over and over.
+        symlink(&*dir, dir.join("src/loop")).unwrap();
+
+        assert_eq!(matching(&dir, "src/**/*.c"), ["src/a.c", "src/b.c"]);
+        // The directories before the first wildcard are followed.
+        assert_eq!(matching(&dir, "src/lib/*.c"), ["src/lib/b.c"]);
+    }
+}
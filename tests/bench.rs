@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Benchmarks for the performance options, they are ignored by default as they take a while and
+//! only mean something in a release build:
+//! ```bash
+//! $ cargo test --release --test bench -- --ignored --nocapture --test-threads 1
+//! ```
+//! Each prints the best of a few runs for every setting it compares.
+
+mod common;
+
+use common::*;
+
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 3;
+
+/// The best time of a few runs of duplihere with args.
+fn best(t: &Tree, args: &[&str]) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let out = t.run(args);
+            let elapsed = start.elapsed();
+            assert!(out.status.success(), "{:?} {}", args, stderr(&out));
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+/// A file of n lines, with distinct content for each seed.
+fn source(seed: usize, n: usize) -> String {
+    (0..n)
+        .map(|i| format!("    value_{} = compute({}, {});\n", i % 97, seed, i))
+        .collect()
+}
+
+#[test]
+#[ignore]
+fn shared_signatures() {
+    let t = Tree::new("bench_shared_signatures");
+    let vendored = source(0, 5000);
+    for i in 0..200 {
+        t.write(&format!("vendored/{}.c", i), &vendored);
+        t.write(&format!("distinct/{}.c", i), &source(i, 5000));
+    }
+
+    for dir in ["vendored", "distinct"] {
+        let glob = format!("{}/*.c", dir);
+        let args = ["-f", &glob, "-l", "50", "--dump-collision-hash-stats"];
+        let out = t.run(&[&args[..], &["--verbose"]].concat());
+        let sharing = stderr(&out)
+            .lines()
+            .find(|l| l.contains("line signature vectors"))
+            .unwrap_or_default()
+            .to_string();
+        println!("{:<10} {}", dir, sharing);
+        for threads in ["1", "4", "8"] {
+            println!(
+                "{:<10} -t {} {:?}",
+                dir,
+                threads,
+                best(&t, &[&args[..], &["-t", threads]].concat())
+            );
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn io_and_cpu_threads() {
+    let t = Tree::new("bench_io_and_cpu_threads");
+    for i in 0..400 {
+        // Half the files share a block so there are collisions to walk.
+        let shared = if i % 2 == 0 {
+            source(0, 200)
+        } else {
+            String::new()
+        };
+        t.write(&format!("{}.c", i), &(source(i, 2000) + &shared));
+    }
+
+    for (io, cpu) in [("1", "1"), ("8", "1"), ("8", "4"), ("8", "8"), ("16", "4")] {
+        println!(
+            "--io-threads {:<2} --cpu-threads {} {:?}",
+            io,
+            cpu,
+            best(
+                &t,
+                &["-f", "*.c", "-j", "--io-threads", io, "--cpu-threads", cpu]
+            )
+        );
+    }
+}
+
+#[test]
+#[ignore]
+fn mmap() {
+    let t = Tree::new("bench_mmap");
+    for i in 0..20 {
+        t.write(&format!("{}.c", i), &source(i, 200_000));
+    }
+
+    for args in [&[][..], &["--mmap"]] {
+        let args = [&["-f", "*.c", "--dump-collision-hash-stats"][..], args].concat();
+        println!("{:<55} {:?}", args.join(" "), best(&t, &args));
+    }
+}
+
+#[test]
+#[ignore]
+fn read_buffer_size() {
+    let t = Tree::new("bench_read_buffer_size");
+    t.write("large.c", &source(0, 2_000_000));
+
+    for size in ["512", "8192", "65536", "1048576"] {
+        println!(
+            "--read-buffer-size {:<8} {:?}",
+            size,
+            best(
+                &t,
+                &[
+                    "-f",
+                    "large.c",
+                    "--dump-collision-hash-stats",
+                    "--read-buffer-size",
+                    size
+                ]
+            )
+        );
+    }
+}
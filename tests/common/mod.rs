@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Helpers for the tests which run duplihere on a tree of files made for the test.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// A directory of files for one test, removed when it's dropped.
+pub struct Tree {
+    pub root: PathBuf,
+}
+
+impl Tree {
+    /// An empty tree, name has to be unique among the tests.
+    pub fn new(name: &str) -> Tree {
+        let root = std::env::temp_dir()
+            .join(format!("duplihere-test-{}", std::process::id()))
+            .join(name);
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        // The scan reports canonical names, so should the tests.
+        Tree {
+            root: fs::canonicalize(&root).unwrap(),
+        }
+    }
+
+    /// Write a file, creating the directories it's in.
+    pub fn write(&self, name: &str, content: &str) -> PathBuf {
+        let path = self.root.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    /// The full name of a file as the report has it.
+    pub fn name(&self, name: &str) -> String {
+        self.path(name).to_string_lossy().to_string()
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.path(name)).unwrap()
+    }
+
+    /// Run duplihere in the tree.
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.run_with_stdin(args, "")
+    }
+
+    pub fn run_with_stdin(&self, args: &[&str], stdin: &str) -> Output {
+        use std::io::Write;
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_duplihere"))
+            .args(args)
+            .current_dir(&self.root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(stdin.as_bytes())
+            .unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Run with -j and return the report.
+    pub fn json(&self, args: &[&str]) -> serde_json::Value {
+        let mut args = args.to_vec();
+        args.push("-j");
+        let out = self.run(&args);
+        assert!(out.status.success(), "{}", stderr(&out));
+        serde_json::from_slice(&out.stdout).unwrap()
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+pub fn stdout(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stdout).to_string()
+}
+
+pub fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).to_string()
+}
+
+/// n lines which are all different, each starting with prefix.
+pub fn block(prefix: &str, n: usize) -> String {
+    (0..n)
+        .map(|i| format!("{} {} = {};\n", prefix, i, i * 7))
+        .collect()
+}
+
+/// The duplicates of a JSON report.
+pub fn duplicates(report: &serde_json::Value) -> &Vec<serde_json::Value> {
+    report["duplicates"].as_array().unwrap()
+}
+
+/// The files of a duplicate, name and line.
+pub fn locations(duplicate: &serde_json::Value) -> Vec<(String, u64)> {
+    duplicate["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| (f[0].as_str().unwrap().to_string(), f[1].as_u64().unwrap()))
+        .collect()
+}
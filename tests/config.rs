@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Settings for a subtree from duplihere.toml files.
+
+mod common;
+
+use common::*;
+
+/// A block of n lines in a.c and b.c of dir, each file with a line of its own after it.
+fn copies(t: &Tree, dir: &str, n: usize) {
+    for f in ["a", "b"] {
+        t.write(
+            &format!("{}/{}.c", dir, f),
+            &format!("{}int {};\n", block(dir, n), f),
+        );
+    }
+}
+
+/// The directory of the first copy of each duplicate.
+fn dirs(t: &Tree, report: &serde_json::Value) -> Vec<String> {
+    let root = format!("{}/", t.root.display());
+    let mut rc: Vec<String> = duplicates(report)
+        .iter()
+        .map(|d| {
+            let name = locations(d)[0].0.clone();
+            let rel = name.strip_prefix(&root).unwrap().to_string();
+            rel[..rel.rfind('/').unwrap()].to_string()
+        })
+        .collect();
+    rc.sort();
+    rc
+}
+
+fn nested(t: &Tree) {
+    t.write("duplihere.toml", "lines = 8\n");
+    t.write("sub/duplihere.toml", "lines = 4\n");
+    t.write("sub/deeper/duplihere.toml", "ignore-case = false\n");
+    copies(t, "top", 7);
+    copies(t, "sub", 5);
+    copies(t, "sub/deeper", 4);
+    copies(t, "sub/deeper/deepest", 3);
+}
+
+#[test]
+fn nearest_config_wins() {
+    let t = Tree::new("nearest_config_wins");
+    nested(&t);
+
+    // top needs 8 lines, sub and below 4, deeper only sets ignore-case so 4 is inherited.
+    let report = t.json(&["-f", "**/*.c"]);
+    assert_eq!(dirs(&t, &report), vec!["sub", "sub/deeper"]);
+}
+
+#[test]
+fn command_line_lines_win() {
+    let t = Tree::new("command_line_lines_win");
+    nested(&t);
+
+    let forms: [&[&str]; 2] = [&["-l", "5"], &["--lines=5"]];
+    for lines in forms {
+        let args = [&["-f", "**/*.c"], lines].concat();
+        let report = t.json(&args);
+        assert_eq!(dirs(&t, &report), vec!["sub", "top"], "{:?}", args);
+    }
+}
+
+#[test]
+fn option_values_are_not_options() {
+    let t = Tree::new("option_values_are_not_options");
+    nested(&t);
+
+    // The value of another option which looks like -l or --ignore-case doesn't override the
+    // config.
+    let report = t.json(&["-f", "**/*.c", "--skip-directive=-l"]);
+    assert_eq!(dirs(&t, &report), vec!["sub", "sub/deeper"]);
+    let report = t.json(&["-f", "**/*.c", "--generated-marker=--ignore-case"]);
+    assert_eq!(dirs(&t, &report), vec!["sub", "sub/deeper"]);
+}
+
+#[test]
+fn different_normalization_stops_matching() {
+    let t = Tree::new("different_normalization_stops_matching");
+    let text = block("Upper", 8);
+    t.write("one/a.c", &text);
+    t.write("two/b.c", &text);
+    t.write("three/c.c", &text);
+    t.write("two/duplihere.toml", "ignore-case = true\n");
+
+    let report = t.json(&["-f", "**/*.c"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    let names: Vec<String> = locations(&all[0]).into_iter().map(|l| l.0).collect();
+    assert_eq!(names, vec![t.name("one/a.c"), t.name("three/c.c")]);
+}
+
+/// A block of n lines copied between two files of a language.
+fn language_copies(t: &Tree, ext: &str, n: usize) {
+    for f in ["a", "b"] {
+        t.write(
+            &format!("{}/{}.{}", ext, f, ext),
+            &format!("{}{} {};\n", block(ext, n), f, ext),
+        );
+    }
+}
+
+#[test]
+fn per_language_thresholds() {
+    let t = Tree::new("per_language_thresholds");
+    // Python needs 4 lines, Java 10.
+    language_copies(&t, "py", 5);
+    language_copies(&t, "java", 9);
+
+    let report = t.json(&["-f", "**/*.*", "--per-language-thresholds"]);
+    assert_eq!(dirs(&t, &report), vec!["py"]);
+
+    let report = t.json(&[
+        "-f",
+        "**/*.*",
+        "--per-language-thresholds",
+        "--language-lines",
+        "java=9",
+        "--language-lines",
+        "python=6",
+    ]);
+    assert_eq!(dirs(&t, &report), vec!["java"]);
+}
+
+#[test]
+fn command_line_lines_win_over_the_language_table() {
+    let t = Tree::new("command_line_lines_win_over_the_language_table");
+    language_copies(&t, "py", 5);
+    language_copies(&t, "java", 9);
+
+    let report = t.json(&["-f", "**/*.*", "--per-language-thresholds", "-l", "5"]);
+    assert_eq!(dirs(&t, &report), vec!["java", "py"]);
+
+    // --language-lines still wins over -l.
+    let report = t.json(&[
+        "-f",
+        "**/*.*",
+        "--per-language-thresholds",
+        "-l",
+        "5",
+        "--language-lines",
+        "java=10",
+    ]);
+    assert_eq!(dirs(&t, &report), vec!["py"]);
+}
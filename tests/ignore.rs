@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The ignore file, read from a file or fetched from a URL.
+
+mod common;
+
+use common::*;
+
+/// Two files with the same 8 lines.
+fn copies(t: &Tree) {
+    t.write("a.c", &format!("{}int a;\n", block("x", 8)));
+    t.write("b.c", &format!("{}int b;\n", block("x", 8)));
+}
+
+#[test]
+fn https_is_refused() {
+    let t = Tree::new("https_is_refused");
+    copies(&t);
+    let out = t.run(&["-f", "*.c", "-i", "https://127.0.0.1:1/ignore.txt"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("https://"), "{}", stderr(&out));
+}
+
+#[test]
+fn strict_ignore() {
+    let t = Tree::new("strict_ignore");
+    copies(&t);
+    let key = duplicates(&t.json(&["-f", "*.c"]))[0]["key"].clone();
+    t.write(
+        "ignore.txt",
+        &format!("# accepted\n{}\n\n1234567890123456789O\n", key),
+    );
+
+    // The typo is warned about, the valid entry still ignores the duplicate.
+    let out = t.run(&["-f", "*.c", "-i", "ignore.txt", "-j"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stderr(&out)
+        .contains("WARNING: Ignore file contains invalid hash value \"1234567890123456789O\""));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(report["num_ignored"], 1);
+
+    let out = t.run(&["-f", "*.c", "-i", "ignore.txt", "--strict-ignore"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        stderr(&out),
+        "ERROR: Ignore file ignore.txt line 4 contains invalid hash value \"1234567890123456789O\"\n"
+    );
+    assert!(out.stdout.is_empty());
+
+    // A clean file is fine either way.
+    t.write("ignore.txt", &format!("{}\n", key));
+    let out = t.run(&["-f", "*.c", "-i", "ignore.txt", "--strict-ignore"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+}
+
+#[test]
+fn emit_ignore_template() {
+    let t = Tree::new("emit_ignore_template");
+    copies(&t);
+    t.write("c.c", &block("y", 6));
+    t.write("d.c", &block("y", 6));
+    let report = t.json(&["-f", "*.c", "--emit-ignore-template", "template.txt"]);
+    let key = |first: &str| -> u64 {
+        duplicates(&report)
+            .iter()
+            .find(|d| locations(d)[0].0 == t.name(first))
+            .unwrap()["key"]
+            .as_u64()
+            .unwrap()
+    };
+    let (x, y) = (key("a.c"), key("c.c"));
+
+    let template = t.read("template.txt");
+    assert!(template.starts_with("# duplihere ignore file, "));
+    assert!(template.contains(&format!(
+        "#\n# 8 lines in 2 places: {}:1, {}:1\n# {}\n",
+        t.name("a.c"),
+        t.name("b.c"),
+        x
+    )));
+    assert!(template.contains(&format!("# {}\n", y)));
+
+    // As written nothing is ignored, and there's nothing invalid in it.
+    let report = t.json(&["-f", "*.c", "-i", "template.txt", "--strict-ignore"]);
+    assert_eq!(report["num_ignored"], 0);
+
+    // Uncommenting one ignores just that one.
+    t.write(
+        "ignore.txt",
+        &template.replace(&format!("# {}\n", x), &format!("{}\n", x)),
+    );
+    let report = t.json(&[
+        "-f",
+        "*.c",
+        "-i",
+        "ignore.txt",
+        "--strict-ignore",
+        "--emit-ignore-template",
+        "template.txt",
+    ]);
+    assert_eq!(report["num_ignored"], 1);
+    assert_eq!(report["num_lines"], 6);
+
+    // The next template leaves out what's ignored already.
+    let template = t.read("template.txt");
+    assert!(!template.contains(&x.to_string()));
+    assert!(template.contains(&format!("# {}\n", y)));
+}
+
+#[cfg(feature = "http")]
+mod http {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Serve one request with the response, sending back the request line.
+    fn serve(response: String) -> (u16, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            loop {
+                let mut header = String::new();
+                if reader.read_line(&mut header).unwrap() == 0 || header == "\r\n" {
+                    break;
+                }
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            tx.send(request).unwrap();
+        });
+        (port, rx)
+    }
+
+    #[test]
+    fn fetch_ignore_file() {
+        let t = Tree::new("fetch_ignore_file");
+        copies(&t);
+        let report = t.json(&["-f", "*.c"]);
+        let key = duplicates(&report)[0]["key"].to_string();
+
+        let (port, rx) = serve(format!(
+            "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n# baseline\n{}\n",
+            key
+        ));
+        let url = format!("http://127.0.0.1:{}/duplihere/ignore.txt", port);
+        let report = t.json(&["-f", "*.c", "-i", &url]);
+        // The JSON report keeps ignored duplicates, with an id of 0.
+        assert_eq!(report["num_ignored"], 1);
+        assert_eq!(report["num_lines"], 0);
+        assert_eq!(duplicates(&report)[0]["id"], 0);
+        assert_eq!(rx.recv().unwrap(), "GET /duplihere/ignore.txt HTTP/1.0\r\n");
+    }
+
+    #[test]
+    fn fetch_failure_fails_the_run() {
+        let t = Tree::new("fetch_failure_fails_the_run");
+        copies(&t);
+
+        let (port, _rx) = serve("HTTP/1.0 404 Not Found\r\n\r\n".to_string());
+        let url = format!("http://127.0.0.1:{}/ignore.txt", port);
+        let out = t.run(&["-f", "*.c", "-i", &url]);
+        assert_eq!(out.status.code(), Some(3));
+        assert!(stderr(&out).contains("404 Not Found"), "{}", stderr(&out));
+        assert!(stdout(&out).is_empty());
+
+        // Nothing listening on the port any more.
+        let out = t.run(&["-f", "*.c", "-i", &url]);
+        assert_eq!(out.status.code(), Some(3));
+    }
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn url_needs_the_http_feature() {
+    let t = Tree::new("url_needs_the_http_feature");
+    copies(&t);
+    let out = t.run(&["-f", "*.c", "-i", "http://127.0.0.1:1/ignore.txt"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("http feature"), "{}", stderr(&out));
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Options which stand for others and how they combine.
+
+mod common;
+
+use common::*;
+
+fn copies(t: &Tree) {
+    t.write("sub/a.c", &format!("{}int a;\n", block("x", 8)));
+    t.write("sub/b.c", &format!("{}int b;\n", block("x", 8)));
+}
+
+#[test]
+fn preset_ci_is_the_documented_bundle() {
+    let t = Tree::new("preset_ci_is_the_documented_bundle");
+    copies(&t);
+    let pattern = t.name("sub/*.c");
+
+    // --json, --relative-paths
+    let out = t.run(&["--preset-ci", "-f", &pattern]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        locations(&duplicates(&report)[0]),
+        vec![("sub/a.c".to_string(), 0), ("sub/b.c".to_string(), 0)]
+    );
+
+    // --json-errors
+    let out = t.run(&["--preset-ci", "-f", &pattern, "-l", "0"]);
+    assert_eq!(out.status.code(), Some(2));
+    let error: serde_json::Value = serde_json::from_str(stderr(&out).trim()).unwrap();
+    assert_eq!(error["exit_code"], 2);
+    assert!(error["error"].as_str().unwrap().contains("--lines"));
+
+    // --scan-order name, the same as without the preset.
+    let plain = t.json(&["-f", &pattern, "--relative-paths"]);
+    assert_eq!(duplicates(&report), duplicates(&plain));
+}
+
+#[test]
+fn given_options_override_preset_ci() {
+    let t = Tree::new("given_options_override_preset_ci");
+    copies(&t);
+    let pattern = t.name("sub/*.c");
+
+    let out = t.run(&["-f", &pattern, "--no-json", "--preset-ci"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("duplicate lines in 1 chunks in 2 files"));
+
+    let out = t.run(&["--preset-ci", "-f", &pattern, "--absolute-paths"]);
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(
+        locations(&duplicates(&report)[0]),
+        vec![(t.name("sub/a.c"), 0), (t.name("sub/b.c"), 0)]
+    );
+
+    let out = t.run(&["--preset-ci", "-f", &pattern, "-l", "0", "--text-errors"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(
+        stderr(&out).starts_with("ERROR: --lines"),
+        "{}",
+        stderr(&out)
+    );
+
+    let out = t.run(&["--preset-ci", "-f", &pattern, "--scan-order", "size"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap();
+}
+
+#[test]
+fn preset_ci_as_an_option_value() {
+    let t = Tree::new("preset_ci_as_an_option_value");
+    copies(&t);
+    let pattern = t.name("sub/*.c");
+
+    // The value of another option, not the preset.
+    let out = t.run(&["-f", &pattern, "--salt", "--preset-ci"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains("duplicate lines in 1 chunks in 2 files"));
+    let out = t.run(&["-f", &pattern, "--report-comment=--preset-ci"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        stderr(&out),
+        "ERROR: --report-comment \"--preset-ci\" must be key=value with a non-empty key\n"
+    );
+}
+
+#[test]
+fn relative_paths_are_not_encoded() {
+    let t = Tree::new("relative_paths_are_not_encoded");
+    t.write("a dir/a#1.c", &block("x", 8));
+    t.write("a dir/b.c", &block("x", 8));
+
+    let report = t.json(&["-f", "a dir/*.c", "--relative-paths"]);
+    assert_eq!(
+        locations(&duplicates(&report)[0]),
+        vec![("a dir/a#1.c".to_string(), 0), ("a dir/b.c".to_string(), 0)]
+    );
+    let report = t.json(&["-f", "a dir/*.c", "--url-paths"]);
+    assert_eq!(
+        locations(&duplicates(&report)[0]),
+        vec![
+            ("a%20dir/a%231.c".to_string(), 0),
+            ("a%20dir/b.c".to_string(), 0)
+        ]
+    );
+}
+
+#[test]
+fn url_paths() {
+    let t = Tree::new("url_paths");
+    let text = block("x", 8);
+    t.write("sub dir/a b#1.c", &text);
+    t.write("sub dir/\u{fc}ber?.c", &text);
+    t.write("100%.c", &text);
+
+    let out = t.run(&[
+        "-f",
+        "**/*.c",
+        "--url-paths",
+        "-j",
+        "--group-output",
+        "stderr",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let urls = vec![
+        "100%25.c".to_string(),
+        "sub%20dir/%C3%BCber%3F.c".to_string(),
+        "sub%20dir/a%20b%231.c".to_string(),
+    ];
+    let mut found: Vec<String> = locations(&duplicates(&report)[0])
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    found.sort();
+    assert_eq!(found, urls);
+    // The group output has them too.
+    for url in &urls {
+        assert!(stderr(&out).contains(url.as_str()), "{}", url);
+    }
+
+    // The text report keeps the full paths.
+    let out = t.run(&["-f", "**/*.c", "--url-paths"]);
+    assert!(stdout(&out).contains(&t.name("sub dir/a b#1.c")));
+}
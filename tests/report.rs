@@ -0,0 +1,945 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! The report, its summary and the other outputs written from the duplicates found.
+
+mod common;
+
+use common::*;
+
+#[test]
+fn ids_are_numbered_over_reported_duplicates() {
+    let t = Tree::new("ids_are_numbered_over_reported_duplicates");
+    let first = block("first", 7);
+    let second = block("second", 7);
+    t.write("a.c", &format!("{}int a;\n{}", first, second));
+    t.write("b.c", &format!("{}int b;\n{}", second, first));
+
+    // Find the signature of one of them and ignore it.
+    let report = t.json(&["-f", "*.c", "-l", "3"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 2);
+    t.write("ignore.txt", &format!("{}\n", all[0]["key"]));
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "-l",
+        "3",
+        "-i",
+        "ignore.txt",
+        "--group-output",
+        "stdout",
+    ]);
+    assert!(out.status.success());
+    let text = stdout(&out);
+    let groups: Vec<serde_json::Value> = text
+        .lines()
+        .filter(|l| l.starts_with('{'))
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0]["type"], "group");
+    assert_eq!(groups[0]["id"], 1);
+    assert!(text.contains("Duplicate 1\n"));
+    assert!(!text.contains("Duplicate 2\n"));
+}
+
+#[cfg(unix)]
+#[test]
+fn syslog_still_writes_side_outputs() {
+    let t = Tree::new("syslog_still_writes_side_outputs");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+
+    // Whether or not there's a syslog to log to, the files are written first.
+    t.run(&[
+        "-f",
+        "*.c",
+        "--syslog",
+        "--dot",
+        "g.dot",
+        "--html",
+        "r.html",
+        "--extract-to",
+        "snippets",
+    ]);
+    assert!(t.read("g.dot").contains(&t.name("a.c")));
+    assert!(t.read("r.html").contains(&t.name("b.c")));
+    assert!(t.path("snippets").is_dir());
+}
+
+#[test]
+fn fingerprint_mode_groups_by_content_or_position() {
+    let t = Tree::new("fingerprint_mode_groups_by_content_or_position");
+    t.write("a.c", &block("x", 8));
+    t.write("b.c", &block("x", 8));
+    t.write(
+        "c.c",
+        &format!("int c;\nint cc;\nint ccc;\n{}", block("x", 8)),
+    );
+
+    let report = t.json(&["-f", "*.c", "--fingerprint-mode", "content"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.c"), 0), (t.name("b.c"), 0), (t.name("c.c"), 3)]
+    );
+
+    let report = t.json(&["-f", "*.c", "--fingerprint-mode", "position"]);
+    let all = duplicates(&report);
+    let mut pairs: Vec<Vec<(String, u64)>> = all.iter().map(locations).collect();
+    pairs.sort();
+    assert_eq!(
+        pairs,
+        vec![
+            vec![(t.name("a.c"), 0), (t.name("b.c"), 0)],
+            vec![(t.name("a.c"), 0), (t.name("c.c"), 3)],
+            vec![(t.name("b.c"), 0), (t.name("c.c"), 3)],
+        ]
+    );
+    let mut keys: Vec<u64> = all.iter().map(|d| d["key"].as_u64().unwrap()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), 3);
+    assert!(all.iter().all(|d| d["num_lines"] == 8));
+}
+
+#[test]
+fn summary_json_whatever_is_written() {
+    let t = Tree::new("summary_json_whatever_is_written");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+    t.write("ignore.json", "[]");
+
+    for mode in [
+        &[][..],
+        &["--fix-plan"],
+        &["--emit-line-coverage"],
+        &["--report-only-files"],
+        &["-i", "ignore.json", "--validate-ignore"],
+    ] {
+        let _ = std::fs::remove_file(t.path("s.json"));
+        let args = [&["-f", "*.c", "--summary-json", "s.json"][..], mode].concat();
+        let out = t.run(&args);
+        assert!(out.status.success(), "{:?} {}", mode, stderr(&out));
+        let summary: serde_json::Value = serde_json::from_str(&t.read("s.json")).unwrap();
+        assert_eq!(summary["num_duplicates"], 1, "{:?}", mode);
+        assert_eq!(summary["num_lines"], 8, "{:?}", mode);
+    }
+}
+
+#[test]
+fn extract_copies_which_differ() {
+    let t = Tree::new("extract_copies_which_differ");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text.to_uppercase());
+    t.write("c.c", &block("other", 8));
+    t.write("d.c", &block("other", 8));
+
+    let out = t.run(&["-f", "*.c", "--ignore-case", "--extract-to", "snippets"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let manifest: serde_json::Value =
+        serde_json::from_str(&t.read("snippets/manifest.json")).unwrap();
+    let manifest = manifest.as_array().unwrap();
+    assert_eq!(manifest.len(), 2);
+    assert_eq!(
+        manifest.iter().filter(|s| s["identical"] == false).count(),
+        1
+    );
+    for snippet in manifest {
+        let file = format!("snippets/{}", snippet["file"].as_str().unwrap());
+        if snippet["identical"] == false {
+            assert_eq!(t.read(&file), text);
+            assert_eq!(
+                stderr(&out),
+                format!(
+                    "WARNING: the copies of duplicate {} differ, its first copy was extracted\n",
+                    snippet["id"]
+                )
+            );
+        } else {
+            assert_eq!(t.read(&file), block("other", 8));
+        }
+    }
+}
+
+#[test]
+fn whitespace_but_not_line_ending_differences() {
+    let t = Tree::new("whitespace_but_not_line_ending_differences");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text.replace('\n', "\r\n"));
+    // The last line without a newline.
+    t.write("c.c", text.trim_end());
+
+    let differs = |t: &Tree| {
+        let report = t.json(&["-f", "*.c", "--note-whitespace-diffs"]);
+        duplicates(&report)[0]["whitespace_differs"].clone()
+    };
+    assert_eq!(differs(&t), false);
+
+    t.write("d.c", &text.replace("shared", "    shared"));
+    assert_eq!(differs(&t), true);
+    let out = t.run(&["-f", "*.c", "--note-whitespace-diffs"]);
+    assert!(stdout(&out).contains("Copies only match after whitespace normalization"));
+}
+
+#[test]
+fn trailing_whitespace_but_not_line_endings() {
+    let t = Tree::new("trailing_whitespace_but_not_line_endings");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text.replace('\n', "\r\n"));
+
+    let flagged = |t: &Tree| {
+        let report = t.json(&["-f", "*.c", "--flag-trailing-ws"]);
+        duplicates(&report)[0]["trailing_ws_differs"].clone()
+    };
+    assert_eq!(flagged(&t), false);
+
+    t.write("c.c", &text.replace(";\n", "; \t\r\n"));
+    assert_eq!(flagged(&t), true);
+
+    t.write("d.c", &text.replace("shared", "  shared"));
+    assert_eq!(flagged(&t), false);
+}
+
+#[test]
+fn reindented_copies() {
+    let t = Tree::new("reindented_copies");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+
+    let flagged = |t: &Tree| {
+        let report = t.json(&["-f", "*.c", "--flag-reindent"]);
+        duplicates(&report)[0]["reindented"].clone()
+    };
+    assert_eq!(flagged(&t), false);
+
+    // Copies at two different levels, with spaces and with tabs.
+    t.write("b.c", &text.replace("shared", "    shared"));
+    assert_eq!(flagged(&t), true);
+    t.write("c.c", &text.replace("shared", "\t\tshared"));
+    assert_eq!(flagged(&t), true);
+
+    let out = t.run(&["-f", "*.c", "--flag-reindent"]);
+    assert!(stdout(&out).contains("Copies only differ in indentation"));
+    let report = t.json(&["-f", "*.c"]);
+    assert!(duplicates(&report)[0].get("reindented").is_none());
+
+    // Any other difference isn't a re-indent.
+    t.write("d.c", &text.replace(";\n", ";  \n"));
+    assert_eq!(flagged(&t), false);
+}
+
+#[test]
+fn lines_counted_once_with_dedup_identical_locations() {
+    let t = Tree::new("lines_counted_once_with_dedup_identical_locations");
+    let x = block("x", 8);
+    t.write("a.c", &format!("{}{}", x, block("y", 4)));
+    t.write("b.c", &format!("{}{}", x, block("y", 4)));
+    t.write("c.c", &format!("{}int c;\n", x));
+
+    // x and y in a.c and b.c, and x in all three, b.c's copy of x is in both.
+    let report = t.json(&["-f", "*.c"]);
+    let mut sizes: Vec<(u64, usize)> = duplicates(&report)
+        .iter()
+        .map(|d| (d["num_lines"].as_u64().unwrap(), locations(d).len()))
+        .collect();
+    sizes.sort();
+    assert_eq!(sizes, vec![(8, 3), (12, 2)]);
+    assert_eq!(report["num_lines"], 12 + 8 * 2);
+
+    let report = t.json(&["-f", "*.c", "--dedup-identical-locations"]);
+    assert_eq!(report["num_lines"], 12 + 8);
+}
+
+#[test]
+fn salted_signatures() {
+    let t = Tree::new("salted_signatures");
+    // The same text in two repositories, each scanned on its own.
+    for repo in ["one", "two"] {
+        t.write(&format!("{}/a.c", repo), &block("shared", 8));
+        t.write(&format!("{}/b.c", repo), &block("shared", 8));
+    }
+    let key = |repo: &str, args: &[&str]| {
+        let glob = format!("{}/*.c", repo);
+        let report = t.json(&[&["-f", glob.as_str()][..], args].concat());
+        duplicates(&report)[0]["key"].as_u64().unwrap()
+    };
+
+    let salted = key("one", &["--salt", "s3cret"]);
+    assert_eq!(key("two", &["--salt", "s3cret"]), salted);
+    assert_ne!(key("two", &["--salt", "other"]), salted);
+    assert_ne!(key("two", &[]), salted);
+
+    // An ignore file has to have the signature for the salt in use.
+    t.write("ignore.txt", &format!("{}\n", salted));
+    let report = t.json(&["-f", "two/*.c", "--salt", "s3cret", "-i", "ignore.txt"]);
+    assert_eq!(report["num_ignored"], 1);
+    let report = t.json(&["-f", "two/*.c", "-i", "ignore.txt"]);
+    assert_eq!(report["num_ignored"], 0);
+}
+
+#[test]
+fn cross_file_and_within_file_lines() {
+    let t = Tree::new("cross_file_and_within_file_lines");
+    let repeated = block("repeated", 8);
+    t.write("a.c", &format!("{}int a;\n{}", repeated, repeated));
+    t.write("b.c", &block("copied", 6));
+    t.write("c.c", &block("copied", 6));
+
+    let report = t.json(&["-f", "*.c"]);
+    assert_eq!(report["num_lines"], 14);
+    assert_eq!(report["num_lines_cross_file"], 6);
+    assert_eq!(report["num_lines_within_file"], 8);
+
+    let out = t.run(&["-f", "*.c"]);
+    assert!(stdout(&out).contains("6 duplicate lines are copies across files, 8 within a file.\n"));
+}
+
+#[test]
+fn collapse_consecutive() {
+    let t = Tree::new("collapse_consecutive");
+    let copy = block("expanded", 6);
+    let repeated: String = (0..10).map(|i| format!("{}int s{};\n", copy, i)).collect();
+    t.write("a.c", &repeated);
+    t.write("b.c", &copy);
+
+    let report = t.json(&["-f", "*.c", "--collapse-consecutive"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.c"), 0), (t.name("b.c"), 0)]
+    );
+    assert_eq!(
+        all[0]["repeats"],
+        serde_json::json!([[t.name("a.c"), 0, 10, 7]])
+    );
+
+    // Every copy is still counted.
+    let expanded = t.json(&["-f", "*.c"]);
+    assert_eq!(locations(&duplicates(&expanded)[0]).len(), 11);
+    assert!(duplicates(&expanded)[0].get("repeats").is_none());
+    assert_eq!(report["num_lines"], expanded["num_lines"]);
+
+    let out = t.run(&["-f", "*.c", "--collapse-consecutive"]);
+    let text = stdout(&out);
+    assert!(text.contains(&format!(
+        "Between lines 1 and 6 in {}, repeated 10 times with stride 7\n",
+        t.name("a.c")
+    )));
+    assert!(!text.contains("Between lines 8 and 13"));
+}
+
+#[test]
+fn min_distinct_dirs() {
+    let t = Tree::new("min_distinct_dirs");
+    t.write(
+        "proj/a/x/1.c",
+        &format!(
+            "{}int a;\n{}int b;\n{}",
+            block("sibling", 6),
+            block("far", 6),
+            block("local", 6)
+        ),
+    );
+    t.write("proj/a/x/4.c", &block("local", 6));
+    t.write("proj/a/y/2.c", &block("sibling", 6));
+    t.write("proj/b/3.c", &block("far", 6));
+
+    let found = |args: &[&str]| -> Vec<String> {
+        let report = t.json(&[&["-f", "proj/**/*.c"][..], args].concat());
+        let mut others: Vec<String> = duplicates(&report)
+            .iter()
+            .flat_map(locations)
+            .map(|(name, _)| name)
+            .filter(|name| name != &t.name("proj/a/x/1.c"))
+            .collect();
+        others.sort();
+        others
+    };
+
+    let (local, sibling, far) = (
+        t.name("proj/a/x/4.c"),
+        t.name("proj/a/y/2.c"),
+        t.name("proj/b/3.c"),
+    );
+    assert_eq!(found(&[]), vec![local, sibling.clone(), far.clone()]);
+    assert_eq!(
+        found(&["--min-distinct-dirs", "2"]),
+        vec![sibling, far.clone()]
+    );
+    // Below proj, a/x and a/y are both in a.
+    assert_eq!(
+        found(&["--min-distinct-dirs", "2", "--dir-depth", "1"]),
+        vec![far]
+    );
+}
+
+#[test]
+fn signatures_only() {
+    let t = Tree::new("signatures_only");
+    let first = block("first", 7);
+    let second = block("second", 7);
+    t.write("a.c", &format!("{}int a;\n{}", first, second));
+    t.write("b.c", &format!("{}int b;\n{}", second, first));
+
+    let report = t.json(&["-f", "*.c", "--signatures-only", "committed.txt"]);
+    let mut keys: Vec<String> = duplicates(&report)
+        .iter()
+        .map(|d| d["key"].to_string())
+        .collect();
+    keys.sort();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(t.read("committed.txt"), format!("{}\n", keys.join("\n")));
+
+    // The file reads back as an ignore file, which leaves it empty.
+    let report = t.json(&[
+        "-f",
+        "*.c",
+        "-i",
+        "committed.txt",
+        "--signatures-only",
+        "ignored.txt",
+    ]);
+    assert_eq!(report["num_ignored"], 2);
+    assert_eq!(t.read("ignored.txt"), "");
+
+    // A new duplicate is the one line the committed file doesn't have.
+    t.write("c.c", &block("third", 7));
+    t.write("d.c", &block("third", 7));
+    t.json(&["-f", "*.c", "--signatures-only", "current.txt"]);
+    let (committed, current) = (t.read("committed.txt"), t.read("current.txt"));
+    let added: Vec<&str> = current
+        .lines()
+        .filter(|l| !committed.lines().any(|c| c == *l))
+        .collect();
+    assert_eq!(added.len(), 1);
+    assert_eq!(current.lines().count(), 3);
+}
+
+#[test]
+fn count_by_extension() {
+    let t = Tree::new("count_by_extension");
+    let c = block("c", 8);
+    t.write("a.c", &c);
+    t.write("b.c", &c);
+    t.write("b.h", &c);
+    let script = block("script", 6);
+    t.write("tool.py", &script);
+    t.write("Makefile", &script);
+
+    let report = t.json(&["-f", "*", "--count-by-extension"]);
+    assert_eq!(
+        report["by_extension"],
+        serde_json::json!({"c": 16, "h": 8, "py": 6, "(none)": 6})
+    );
+    assert!(t.json(&["-f", "*"]).get("by_extension").is_none());
+
+    // Most duplicated lines first, then by name.
+    let table: String = [("c", 16), ("h", 8), ("(none)", 6), ("py", 6)]
+        .iter()
+        .map(|(ext, lines)| format!("{:>12} {:>10}\n", ext, lines))
+        .collect();
+    let out = t.run(&["-f", "*", "--count-by-extension"]);
+    assert!(stdout(&out).contains(&format!(
+        "Duplicated lines by extension, every copy counted:\n{}",
+        table
+    )));
+}
+
+#[test]
+fn left_and_right_trees() {
+    let t = Tree::new("left_and_right_trees");
+    t.write(
+        "old/a.c",
+        &format!("{}int a;\n{}", block("moved", 7), block("old", 7)),
+    );
+    t.write("old/c.c", &block("old", 7));
+    t.write(
+        "new/b.c",
+        &format!("{}int b;\n{}", block("moved", 7), block("common", 7)),
+    );
+    t.write(
+        "common/s.c",
+        &format!("{}int s;\n{}", block("common", 7), block("old", 7)),
+    );
+
+    let pairs = |args: &[&str]| -> Vec<Vec<String>> {
+        let report = t.json(args);
+        let mut pairs: Vec<Vec<String>> = duplicates(&report)
+            .iter()
+            .map(|d| locations(d).into_iter().map(|(name, _)| name).collect())
+            .collect();
+        pairs.sort();
+        pairs
+    };
+
+    // Copies within the old tree aren't reported.
+    assert_eq!(
+        pairs(&["--left", "old/*.c", "--right", "new/*.c"]),
+        vec![vec![t.name("new/b.c"), t.name("old/a.c")]]
+    );
+
+    // common/s.c is on both sides, so it's left and its copies in old aren't between the trees.
+    let overlapping = [
+        "--left",
+        "old/*.c",
+        "--left",
+        "common/*.c",
+        "--right",
+        "common/*.c",
+        "--right",
+        "new/*.c",
+    ];
+    assert_eq!(
+        pairs(&overlapping),
+        vec![
+            vec![t.name("common/s.c"), t.name("new/b.c")],
+            vec![t.name("new/b.c"), t.name("old/a.c")],
+        ]
+    );
+}
+
+#[test]
+fn report_comment_context() {
+    let t = Tree::new("report_comment_context");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+
+    let comments = [
+        "--report-comment",
+        "build=1234",
+        "--report-comment",
+        "message=fix \"it\"\nfor real\\now",
+        "--report-comment",
+        "query=a=b",
+    ];
+    let report = t.json(&[&["-f", "*.c"][..], &comments].concat());
+    assert_eq!(
+        report["context"],
+        serde_json::json!({
+            "build": "1234",
+            "message": "fix \"it\"\nfor real\\now",
+            "query": "a=b",
+        })
+    );
+    assert!(t.json(&["-f", "*.c"]).get("context").is_none());
+
+    let out = t.run(&[&["-f", "*.c"][..], &comments].concat());
+    assert!(stdout(&out).starts_with(
+        "# build=1234\n\
+         # message=fix \"it\"\\nfor real\\\\now\n\
+         # query=a=b\n"
+    ));
+
+    for bad in ["no-value", "=value", " =value"] {
+        let out = t.run(&["-f", "*.c", "--report-comment", bad]);
+        assert_eq!(out.status.code(), Some(2), "{}", bad);
+        assert!(stderr(&out).contains("must be key=value"), "{}", bad);
+    }
+}
+
+#[test]
+fn min_block_lines_for_print() {
+    let t = Tree::new("min_block_lines_for_print");
+    let (small, large) = (block("small", 6), block("large", 12));
+    t.write("a.c", &format!("{}int a;\n{}", small, large));
+    t.write("b.c", &format!("{}int b;\n{}", small, large));
+
+    let printed = |args: &[&str]| -> String {
+        let out = t.run(&[&["-f", "*.c", "-p"][..], args].concat());
+        assert!(out.status.success(), "{}", stderr(&out));
+        stdout(&out)
+    };
+
+    let all = printed(&[]);
+    assert!(all.contains(&small) && all.contains(&large));
+
+    let text = printed(&["--min-block-lines-for-print", "10"]);
+    assert!(text.contains(&large));
+    assert!(!text.contains(&small));
+    // The small one is still listed.
+    assert!(text.contains(&format!("Between lines 1 and 6 in {}\n", t.name("a.c"))));
+
+    let out = t.run(&["-f", "*.c", "--min-block-lines-for-print", "10"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("requires --print"));
+}
+
+#[test]
+fn output_json_array_only() {
+    let t = Tree::new("output_json_array_only");
+    let text = block("shared", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+
+    let array = t.json(&["-f", "*.c", "--output-json-array-only"]);
+    let report = t.json(&["-f", "*.c"]);
+    assert!(array.is_array());
+    assert_eq!(&array, &report["duplicates"]);
+
+    let out = t.run(&["-f", "*.c", "--output-json-array-only"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("requires --json"));
+}
+
+#[test]
+fn line_count_mode() {
+    let t = Tree::new("line_count_mode");
+    let x = block("x", 8);
+    t.write("a.c", &format!("{}{}", x, block("y", 4)));
+    t.write("b.c", &format!("{}{}", x, block("y", 4)));
+    t.write("c.c", &format!("{}int c;\n", x));
+
+    // x and y in a.c and b.c overlaps x in all three.
+    let count = |args: &[&str]| t.json(&[&["-f", "*.c"][..], args].concat())["num_lines"].take();
+    assert_eq!(count(&[]), 12 + 8 * 2);
+    assert_eq!(count(&["--line-count-mode", "removable"]), 12 + 8 * 2);
+    // Every line of a.c and b.c and the copy of x in c.c, once each.
+    assert_eq!(count(&["--line-count-mode", "involved"]), 12 * 2 + 8);
+
+    let out = t.run(&["-f", "*.c", "--line-count-mode", "involved"]);
+    assert!(stdout(&out).contains("\nFound 32 duplicate lines "));
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "--line-count-mode",
+        "involved",
+        "--dedup-identical-locations",
+    ]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn fail_on_file() {
+    let t = Tree::new("fail_on_file");
+    let key = block("key", 8);
+    t.write("src/crypto/key.rs", &key);
+    t.write("src/util/copy.rs", &format!("{}fn copy() {{}}\n", key));
+    let helper = block("helper", 8);
+    t.write("src/util/a.rs", &helper);
+    t.write("src/util/b.rs", &helper);
+
+    let out = t.run(&["-f", "src/**/*.rs", "--fail-on-file", "src/net/**"]);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr(&out));
+
+    let out = t.run(&["-f", "src/**/*.rs", "--fail-on-file", "src/crypto/**", "-j"]);
+    assert_eq!(out.status.code(), Some(3));
+    // The report is still written, the duplicate with a copy in crypto is named.
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let critical = duplicates(&report)
+        .iter()
+        .find(|d| locations(d).iter().any(|(name, _)| name.contains("crypto")))
+        .unwrap();
+    assert_eq!(
+        stderr(&out),
+        format!(
+            "Duplicate {} has a copy in {}, which matches --fail-on-file\n",
+            critical["id"],
+            t.name("src/crypto/key.rs")
+        )
+    );
+
+    // Not once it's ignored.
+    t.write("ignore.txt", &format!("{}\n", critical["key"]));
+    let out = t.run(&[
+        "-f",
+        "src/**/*.rs",
+        "--fail-on-file",
+        "src/crypto/**",
+        "-i",
+        "ignore.txt",
+    ]);
+    assert_eq!(out.status.code(), Some(0), "{}", stderr(&out));
+}
+
+#[test]
+fn sort_locations_by() {
+    let t = Tree::new("sort_locations_by");
+    let x = block("x", 6);
+    t.write("a.c", &format!("{}{}{}", x, block("a", 14), x));
+    t.write("b.c", &format!("{}{}", block("b", 10), x));
+    t.write("c.c", &format!("{}{}", block("c", 5), x));
+
+    let order = |args: &[&str]| {
+        let report = t.json(&[&["-f", "*.c"][..], args].concat());
+        locations(&duplicates(&report)[0])
+    };
+    let (a, b, c) = (t.name("a.c"), t.name("b.c"), t.name("c.c"));
+    let by_line = vec![
+        (a.clone(), 0),
+        (c.clone(), 5),
+        (b.clone(), 10),
+        (a.clone(), 20),
+    ];
+    assert_eq!(order(&[]), by_line);
+    assert_eq!(order(&["--sort-locations-by", "line"]), by_line);
+    assert_eq!(
+        order(&["--sort-locations-by", "file"]),
+        vec![(a.clone(), 0), (a, 20), (b, 10), (c, 5)]
+    );
+}
+
+#[test]
+fn emit_line_coverage() {
+    let t = Tree::new("emit_line_coverage");
+    let x = block("x", 8);
+    let y = block("y", 4);
+    let z = block("z", 6);
+    // x and y in a.c and b.c overlaps x in a.c, b.c and c.c.
+    t.write("a.c", &format!("{}{}int a;\n{}", x, y, z));
+    t.write("b.c", &format!("{}{}", x, y));
+    t.write("c.c", &format!("{}int c;\n", x));
+    t.write("d.c", &z);
+    // Copies of v and w which touch in e.c.
+    let (v, w) = (block("v", 6), block("w", 6));
+    t.write("e.c", &format!("{}{}", v, w));
+    t.write("f.c", &format!("{}int f;\n", v));
+    t.write("g.c", &format!("int g;\n{}", w));
+
+    let out = t.run(&["-f", "*.c", "--emit-line-coverage"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let coverage: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let expected: Vec<serde_json::Value> = [
+        ("a.c", vec![(1, 12), (14, 19)]),
+        ("b.c", vec![(1, 12)]),
+        ("c.c", vec![(1, 8)]),
+        ("d.c", vec![(1, 6)]),
+        ("e.c", vec![(1, 12)]),
+        ("f.c", vec![(1, 6)]),
+        ("g.c", vec![(2, 7)]),
+    ]
+    .iter()
+    .map(|(name, ranges)| serde_json::json!({"file": t.name(name), "ranges": ranges}))
+    .collect();
+    assert_eq!(coverage, serde_json::json!({ "files": expected }));
+}
+
+#[test]
+fn report_histogram() {
+    let t = Tree::new("report_histogram");
+    let text: String = [6, 10, 11, 30, 120]
+        .iter()
+        .map(|&n| format!("{}int sep_{};\n", block(&format!("size{}", n), n), n))
+        .collect();
+    t.write("a.c", &text);
+    t.write("b.c", &text.replace("int sep_", "long sep_"));
+
+    let report = t.json(&["-f", "*.c", "--report-histogram"]);
+    assert_eq!(
+        report["histogram"],
+        serde_json::json!([
+            {"min": 6, "max": 10, "duplicates": 2, "lines": 16},
+            {"min": 11, "max": 25, "duplicates": 1, "lines": 11},
+            {"min": 26, "max": 100, "duplicates": 1, "lines": 30},
+            {"min": 101, "max": null, "duplicates": 1, "lines": 120},
+        ])
+    );
+    assert_eq!(report["num_lines"], 16 + 11 + 30 + 120);
+
+    // Buckets below -l are left out, the first starts at it.
+    let report = t.json(&["-f", "*.c", "--report-histogram", "-l", "11"]);
+    assert_eq!(report["histogram"][0]["min"], 11);
+    assert_eq!(report["histogram"].as_array().unwrap().len(), 3);
+
+    let out = t.run(&["-f", "*.c", "--report-histogram"]);
+    let table: String = [
+        ("size", "duplicates", "lines"),
+        ("6-10", "2", "16"),
+        ("11-25", "1", "11"),
+        ("26-100", "1", "30"),
+        ("101+", "1", "120"),
+    ]
+    .iter()
+    .map(|(size, duplicates, lines)| format!("{:>12} {:>10} {:>10}\n", size, duplicates, lines))
+    .collect();
+    assert!(stdout(&out).contains(&format!("Duplicates by size in lines:\n{}", table)));
+}
+
+#[test]
+fn fix_plan() {
+    let t = Tree::new("fix_plan");
+    let (a, b, c) = (block("a", 10), block("b", 8), block("c", 6));
+    // hub.c shares a block with each of the others, a.c and a2.c share theirs with each other.
+    t.write("hub.c", &format!("{}int h1;\n{}int h2;\n{}", a, b, c));
+    t.write("a.c", &a);
+    t.write("a2.c", &a);
+    t.write("b.c", &b);
+    t.write("c.c", &c);
+
+    // hub.c saves a copy of each, then one more copy of a is left.
+    let report = t.json(&["-f", "*.c", "--fix-plan"]);
+    assert_eq!(
+        report,
+        serde_json::json!({"files": [
+            {"file": t.name("hub.c"), "lines_saved": 24, "cumulative": 24},
+            {"file": t.name("a.c"), "lines_saved": 10, "cumulative": 34},
+        ]})
+    );
+    // Together they save every duplicated line.
+    assert_eq!(t.json(&["-f", "*.c"])["num_lines"], 34);
+
+    let out = t.run(&["-f", "*.c", "--fix-plan"]);
+    assert_eq!(
+        stdout(&out),
+        format!(
+            "{:>6} {:>10} {:>10} file\n{:>6} {:>10} {:>10} {}\n{:>6} {:>10} {:>10} {}\n",
+            "rank",
+            "saved",
+            "total",
+            1,
+            24,
+            24,
+            t.name("hub.c"),
+            2,
+            10,
+            34,
+            t.name("a.c")
+        )
+    );
+}
+
+#[test]
+fn output_split_by_severity() {
+    let t = Tree::new("output_split_by_severity");
+    let text: String = [3, 5, 6, 12, 24, 30]
+        .iter()
+        .map(|&n| format!("{}int sep_{};\n", block(&format!("size{}", n), n), n))
+        .collect();
+    t.write("a.c", &text);
+    t.write("b.c", &text.replace("int sep_", "long sep_"));
+
+    let sizes = |report: &serde_json::Value| -> Vec<u64> {
+        let mut sizes: Vec<u64> = duplicates(report)
+            .iter()
+            .map(|d| d["num_lines"].as_u64().unwrap())
+            .collect();
+        sizes.sort_unstable();
+        sizes
+    };
+
+    let report = t.json(&[
+        "-f",
+        "*.c",
+        "-l",
+        "3",
+        "--output-split-by-severity",
+        "split",
+    ]);
+    assert_eq!(sizes(&report), [3, 5, 6, 12, 24, 30]);
+    for (severity, expected) in [
+        ("critical", &[24, 30][..]),
+        ("major", &[12]),
+        ("minor", &[6]),
+        ("info", &[3, 5]),
+    ] {
+        let split: serde_json::Value =
+            serde_json::from_str(&t.read(&format!("split/{}.json", severity))).unwrap();
+        assert_eq!(sizes(&split), expected, "{}", severity);
+        assert_eq!(split["num_lines"], expected.iter().sum::<u64>());
+    }
+
+    // The text format names the files .txt, a severity without any is still written.
+    let out = t.run(&["-f", "*.c", "-l", "7", "--output-split-by-severity", "text"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(t.read("text/minor.txt").contains(&t.name("a.c")));
+    assert!(!t.read("text/critical.txt").contains(&t.name("a.c")));
+    assert!(!t.path("text/minor.json").exists());
+}
+
+#[test]
+fn report_relative_savings() {
+    let t = Tree::new("report_relative_savings");
+    let text = |sep: &str| {
+        [6, 8, 10, 12]
+            .iter()
+            .map(|&n| format!("{}{} sep_{};\n", block(&format!("size{}", n), n), sep, n))
+            .collect::<String>()
+    };
+    t.write("a.c", &text("int"));
+    t.write("b.c", &text("long"));
+    // A third copy of the 8 lines, which saves 16.
+    t.write("c.c", &block("size8", 8));
+    t.write("d.c", &block("size12", 12));
+    t.write("e.c", &block("size12", 12));
+
+    let report = t.json(&["-f", "*.c"]);
+    let ignored = duplicates(&report)
+        .iter()
+        .find(|d| d["num_lines"] == 12)
+        .unwrap()["key"]
+        .clone();
+    t.write("ignore.txt", &format!("{}\n", ignored));
+
+    let report = t.json(&["-f", "*.c", "-i", "ignore.txt", "--report-relative-savings"]);
+    let mut savings: Vec<(u64, Option<f64>)> = duplicates(&report)
+        .iter()
+        .map(|d| {
+            (
+                d["num_lines"].as_u64().unwrap(),
+                d["savings_percent"].as_f64(),
+            )
+        })
+        .collect();
+    savings.sort_by_key(|s| s.0);
+    assert_eq!(
+        savings,
+        vec![
+            (6, Some(18.75)),
+            (8, Some(50.0)),
+            (10, Some(31.25)),
+            (12, None)
+        ]
+    );
+    let total: f64 = savings.iter().filter_map(|s| s.1).sum();
+    assert!((total - 100.0).abs() < 1e-9);
+
+    let out = t.run(&["-f", "*.c", "-i", "ignore.txt", "--report-relative-savings"]);
+    assert!(stdout(&out).contains("\n50.0% of the duplicated lines\n"));
+    assert!(stdout(&out).contains("\n18.8% of the duplicated lines\n"));
+
+    let report = t.json(&["-f", "*.c"]);
+    assert!(duplicates(&report)
+        .iter()
+        .all(|d| d.get("savings_percent").is_none()));
+}
+
+#[test]
+fn location_hashes() {
+    let t = Tree::new("location_hashes");
+    let copy = block("copy", 8);
+    t.write("a.c", &copy);
+    t.write("b.c", &copy);
+    // Matches once trimmed, but isn't the same text.
+    let indented: String = copy.lines().map(|l| format!("    {}\n", l)).collect();
+    t.write("c.c", &indented);
+
+    let report = t.json(&["-f", "*.c", "--location-hashes"]);
+    let duplicate = &duplicates(&report)[0];
+    let names: Vec<String> = locations(duplicate).into_iter().map(|l| l.0).collect();
+    assert_eq!(names, [t.name("a.c"), t.name("b.c"), t.name("c.c")]);
+    let hashes: Vec<u64> = duplicate["location_hashes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|h| h.as_u64().unwrap())
+        .collect();
+    assert_eq!(hashes.len(), 3);
+    assert_eq!(hashes[0], hashes[1]);
+    assert_ne!(hashes[0], hashes[2]);
+
+    let report = t.json(&["-f", "*.c"]);
+    assert!(duplicates(&report)[0].get("location_hashes").is_none());
+}
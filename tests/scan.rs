@@ -0,0 +1,1136 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019 Tony Asleson <tony.asleson@gmail.com>
+
+//! Which files are scanned and how, checkpoints and resuming them.
+
+mod common;
+
+use common::*;
+
+use std::fs;
+
+/// Three files with the same 8 lines, each with a line of its own at the end.
+fn copies(t: &Tree) {
+    for f in ["a", "b", "c"] {
+        t.write(
+            &format!("{}.c", f),
+            &format!("{}int {};\n", block("x", 8), f),
+        );
+    }
+}
+
+#[test]
+fn resume_an_interrupted_scan() {
+    let t = Tree::new("resume_an_interrupted_scan");
+    copies(&t);
+    let plain = t.json(&["-f", "*.c"]);
+
+    t.json(&["-f", "*.c", "--checkpoint", "ck"]);
+    // Cut the checkpoint short as if the scan was killed while writing the second file's record.
+    let ck = t.read("ck");
+    let lines: Vec<&str> = ck.lines().collect();
+    assert_eq!(lines.len(), 4);
+    t.write(
+        "ck",
+        &format!(
+            "{}\n{}\n{}",
+            lines[0],
+            lines[1],
+            &lines[2][..lines[2].len() / 2]
+        ),
+    );
+
+    let resumed = t.json(&["-f", "*.c", "--resume", "ck", "--checkpoint", "ck"]);
+    assert_eq!(duplicates(&resumed), duplicates(&plain));
+    assert_eq!(t.read("ck").lines().count(), 4);
+}
+
+#[test]
+fn checkpoint_with_deny_list() {
+    let t = Tree::new("checkpoint_with_deny_list");
+    copies(&t);
+    t.write("deny.txt", &block("x", 6));
+
+    let out = t.run(&["-f", "*.c", "--deny-list", "deny.txt", "--checkpoint", "ck"]);
+    assert_eq!(out.status.code(), Some(3), "{}", stderr(&out));
+    assert!(stdout(&out).contains("deny.txt:snippet 1"));
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "--deny-list",
+        "deny.txt",
+        "--resume",
+        "ck",
+        "--checkpoint",
+        "ck",
+    ]);
+    assert_eq!(out.status.code(), Some(3), "{}", stderr(&out));
+    assert!(stdout(&out).contains("deny.txt:snippet 1"));
+}
+
+#[test]
+fn whole_file_of_min_lines() {
+    let t = Tree::new("whole_file_of_min_lines");
+    t.write("a.c", &block("x", 6));
+    t.write("b.c", &block("x", 6));
+
+    let report = t.json(&["-f", "*.c", "-l", "6"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 6);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.c"), 0), (t.name("b.c"), 0)]
+    );
+}
+
+#[test]
+fn copy_at_the_end_of_the_files() {
+    let t = Tree::new("copy_at_the_end_of_the_files");
+    t.write("a.c", &format!("int a;\n{}", block("x", 6)));
+    t.write("b.c", &format!("int b;\nint bb;\n{}", block("x", 6)));
+
+    let report = t.json(&["-f", "*.c", "-l", "6"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 6);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.c"), 1), (t.name("b.c"), 2)]
+    );
+}
+
+#[test]
+fn detect_rotations() {
+    let t = Tree::new("detect_rotations");
+    let lines: Vec<String> = (0..6).map(|i| format!("key{} = {}\n", i, i)).collect();
+    let rotated = [&lines[2..], &lines[..2]].concat();
+    let shuffled = [1, 0, 3, 2, 5, 4].map(|i| lines[i].clone());
+    t.write("a.conf", &lines.concat());
+    t.write("b.conf", &rotated.concat());
+    t.write("c.conf", &shuffled.concat());
+
+    let report = t.json(&["-f", "*.conf", "--detect-rotations"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["reordered"], true);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.conf"), 0), (t.name("b.conf"), 0)]
+    );
+}
+
+#[test]
+fn compare_normalized_scans_twice_but_warns_once() {
+    let t = Tree::new("compare_normalized_scans_twice_but_warns_once");
+    let spaced: String = block("x", 8).replace(" = ", "  =  ");
+    t.write("a.c", &block("x", 8));
+    t.write("b.c", &spaced);
+    t.write("c.c", &block("x", 8));
+    t.write("d.c", &spaced);
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "--compare-normalized",
+        "--normalize",
+        "collapse",
+    ]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let err = stderr(&out);
+    let rows: Vec<Vec<&str>> = err
+        .lines()
+        .map(|l| l.split_whitespace().collect())
+        .collect();
+    // Raw the two pairs, normalized all four as one, the lines which could be removed.
+    assert!(rows.contains(&vec!["raw", "2", "16"]), "{}", err);
+    assert!(rows.contains(&vec!["normalized", "1", "24"]), "{}", err);
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "--compare-normalized",
+        "--normalize",
+        "collapse",
+        "--max-pairs",
+        "1",
+    ]);
+    assert_eq!(
+        stderr(&out).matches("--max-pairs 1 reached").count(),
+        1,
+        "{}",
+        stderr(&out)
+    );
+}
+
+#[test]
+fn alpha_rename_but_not_keywords() {
+    let t = Tree::new("alpha_rename_but_not_keywords");
+    let body = |total: &str, check: &str| -> String {
+        (0..8)
+            .map(|i| {
+                format!(
+                    "{} ({} > {}) {} = {} + {};\n",
+                    check, total, i, total, total, i
+                )
+            })
+            .collect()
+    };
+    t.write("a.c", &body("total", "if"));
+    t.write("b.c", &body("acc", "if"));
+    t.write("c.c", &body("sum", "while"));
+
+    let plain = t.json(&["-f", "*.c"]);
+    assert!(duplicates(&plain).is_empty());
+
+    let out = t.run(&["-f", "*.c", "--alpha-rename", "--note-whitespace-diffs"]);
+    assert!(stdout(&out).contains("Copies differ, in whitespace or renamed identifiers"));
+
+    let report = t.json(&["-f", "*.c", "--alpha-rename"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 8);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.c"), 0), (t.name("b.c"), 0)]
+    );
+}
+
+#[test]
+fn suffix_array_matches_the_general_path() {
+    let t = Tree::new("suffix_array_matches_the_general_path");
+    // Blocks of different lengths, copied two and three times, with lines of their own between.
+    let text = [
+        block("a", 7),
+        "int one;\n".to_string(),
+        block("b", 12),
+        block("a", 7),
+        "int two;\n".to_string(),
+        block("c", 6),
+        "int three;\n".to_string(),
+        block("b", 12),
+        block("c", 6),
+        "int four;\n".to_string(),
+        block("a", 9),
+    ]
+    .concat();
+    t.write("one.c", &text);
+
+    let found = |args: &[&str]| {
+        let report = t.json(&[&["-f", "one.c"][..], args].concat());
+        let mut all: Vec<(u64, Vec<(String, u64)>)> = duplicates(&report)
+            .iter()
+            .map(|d| (d["num_lines"].as_u64().unwrap(), locations(d)))
+            .collect();
+        all.sort();
+        all
+    };
+    let general = found(&[]);
+    assert_eq!(general.len(), 3);
+    assert_eq!(found(&["--suffix-array"]), general);
+}
+
+#[test]
+fn max_files() {
+    let t = Tree::new("max_files");
+    copies(&t);
+
+    let out = t.run(&["-j", "-f", "*.c", "--max-files", "2"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(stderr(&out).matches("--max-files 2").count(), 1);
+    let report: serde_json::Value = serde_json::from_str(&stdout(&out)).unwrap();
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 2);
+
+    let report = t.json(&["-f", "*.c", "--max-files", "0"]);
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 3);
+}
+
+#[test]
+fn collapse_and_ignore_case_follow_unicode() {
+    fn lines(line: impl Fn(usize) -> String) -> String {
+        (0..8).map(line).collect()
+    }
+
+    let t = Tree::new("collapse_and_ignore_case_follow_unicode");
+    t.write(
+        "a.c",
+        &lines(|i| format!("d\u{e9}j\u{e0} {} = {};\n", i, i * 7)),
+    );
+    // Upper case, and non-breaking and em spaces.
+    t.write(
+        "b.c",
+        &lines(|i| {
+            format!(
+                "\u{a0}D\u{c9}J\u{c0}\u{a0}\u{a0}{} = {};\u{2003}\n",
+                i,
+                i * 7
+            )
+        }),
+    );
+    // The accents as combining characters.
+    t.write(
+        "c.c",
+        &lines(|i| format!("de\u{301}ja\u{300} {} = {};\n", i, i * 7)),
+    );
+
+    let found = |args: &[&str]| -> Vec<Vec<(String, u64)>> {
+        let report = t.json(&[&["-f", "*.c"][..], args].concat());
+        duplicates(&report).iter().map(locations).collect()
+    };
+    assert!(found(&[]).is_empty());
+    assert!(found(&["--normalize", "collapse"]).is_empty());
+    assert!(found(&["--ignore-case"]).is_empty());
+    assert_eq!(
+        found(&["--normalize", "collapse", "--ignore-case"]),
+        vec![vec![(t.name("a.c"), 0), (t.name("b.c"), 0)]]
+    );
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn normalize_unicode() {
+    let t = Tree::new("normalize_unicode");
+    // é as one code point and as e with a combining acute accent.
+    t.write("a.c", &block("caf\u{e9}", 8));
+    t.write("b.c", &block("cafe\u{301}", 8));
+
+    assert!(duplicates(&t.json(&["-f", "*.c"])).is_empty());
+    for form in ["nfc", "nfd"] {
+        let report = t.json(&["-f", "*.c", "--normalize-unicode", form]);
+        assert_eq!(duplicates(&report).len(), 1, "{}", form);
+    }
+}
+
+#[cfg(not(feature = "unicode"))]
+#[test]
+fn normalize_unicode_needs_the_unicode_feature() {
+    let t = Tree::new("normalize_unicode_needs_the_unicode_feature");
+    copies(&t);
+
+    let out = t.run(&["-f", "*.c", "--normalize-unicode", "nfc"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("unicode feature"), "{}", stderr(&out));
+    assert!(t
+        .run(&["-f", "*.c", "--normalize-unicode", "none"])
+        .status
+        .success());
+
+    t.write("duplihere.toml", "normalize-unicode = \"nfd\"\n");
+    let out = t.run(&["-f", "*.c"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("unicode feature"), "{}", stderr(&out));
+}
+
+#[test]
+fn every_scanned_file_is_in_the_manifest_once() {
+    let t = Tree::new("every_scanned_file_is_in_the_manifest_once");
+    copies(&t);
+    t.write("sub/d.c", &block("d", 3));
+    t.write("e.txt", &block("x", 8));
+
+    // The globs overlap, a.c to c.c are found by both, and a resumed scan reads the checkpoint.
+    let check = |args: &[&str]| {
+        let out = t.run(&[&["-j", "-f", "*.c", "-f", "**/*.c"][..], args].concat());
+        assert!(out.status.success(), "{}", stderr(&out));
+        let manifest: serde_json::Value = serde_json::from_str(&t.read("m.json")).unwrap();
+        let files: Vec<(String, u64, bool)> = manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                (
+                    f["file"].as_str().unwrap().to_string(),
+                    f["lines"].as_u64().unwrap(),
+                    f["duplicated"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (t.name("a.c"), 9, true),
+                (t.name("b.c"), 9, true),
+                (t.name("c.c"), 9, true),
+                (t.name("sub/d.c"), 3, false),
+            ],
+            "{:?}",
+            args
+        );
+    };
+    check(&["--files-manifest", "m.json", "--checkpoint", "ck"]);
+    check(&["--files-manifest", "m.json", "--resume", "ck"]);
+
+    let out = t.run(&[
+        "-f",
+        "*.c",
+        "--normalize",
+        "collapse",
+        "--compare-normalized",
+        "--files-manifest",
+        "m2.json",
+    ]);
+    assert_eq!(out.status.code(), Some(2), "{}", stderr(&out));
+    assert!(stderr(&out).contains("--files-manifest"));
+}
+
+#[test]
+fn progress_events() {
+    let t = Tree::new("progress_events");
+    copies(&t);
+
+    let out = t.run(&["-j", "-f", "*.c", "--progress-json"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert_eq!(
+        duplicates(&serde_json::from_str(&stdout(&out)).unwrap()).len(),
+        1
+    );
+    let events: Vec<serde_json::Value> = stderr(&out)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let phases: Vec<&str> = events
+        .iter()
+        .map(|e| e["phase"].as_str().unwrap())
+        .collect();
+    let scans = phases.iter().take_while(|p| **p == "scan").count();
+    assert!(scans >= 1, "{:?}", phases);
+    assert_eq!(phases[scans..], ["collide", "report"]);
+
+    let done: Vec<u64> = events
+        .iter()
+        .map(|e| e["files_done"].as_u64().unwrap())
+        .collect();
+    assert!(
+        done.windows(2).all(|w| w[0] < w[1] || w[0] == 3),
+        "{:?}",
+        done
+    );
+    for e in &events[scans - 1..] {
+        assert_eq!(e["files_done"], 3);
+        assert_eq!(e["files_total"], 3);
+        assert_eq!(e["percent"], 100.0);
+    }
+
+    let out = t.run(&["-f", "*.c", "--progress", "--progress-json"]);
+    assert_eq!(out.status.code(), Some(2), "{}", stderr(&out));
+}
+
+#[test]
+fn shared_license_header() {
+    let t = Tree::new("shared_license_header");
+    let license: String = (0..8)
+        .map(|i| format!("// License text line {}\n", i))
+        .collect();
+    for i in 0..5 {
+        t.write(
+            &format!("f{}.rs", i),
+            &format!("{}\n{}", license, block(&format!("f{}", i), 8)),
+        );
+    }
+    // Two files which also share their module doc comment and code.
+    let shared = format!("//! The module.\n{}", block("shared", 7));
+    t.write("g0.rs", &format!("{}\n{}", license, shared));
+    t.write("g1.rs", &format!("{}\n{}", license, shared));
+
+    let report = t.json(&["-f", "*.rs"]);
+    assert_eq!(duplicates(&report)[0]["files"].as_array().unwrap().len(), 7);
+
+    let report = t.json(&["-f", "*.rs", "--strip-license-header"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 8);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("g0.rs"), 9), (t.name("g1.rs"), 9)]
+    );
+}
+
+#[test]
+fn ignore_generated() {
+    let t = Tree::new("ignore_generated");
+    let text = block("x", 8);
+    t.write("a.c", &text);
+    t.write(
+        "b.pb.c",
+        &format!("// Code generated by protoc. DO NOT EDIT.\n{}", text),
+    );
+    // Only the first 10 lines are looked at.
+    t.write("c.c", &format!("{}// @generated\n{}", block("c", 10), text));
+    t.write("d.c", &format!("/* made by mkfoo */\n{}", text));
+
+    let files = |args: &[&str]| -> (Vec<String>, serde_json::Value) {
+        let report = t.json(&[&["-f", "*.c"][..], args].concat());
+        let mut names: Vec<String> = locations(&duplicates(&report)[0])
+            .into_iter()
+            .map(|l| l.0)
+            .collect();
+        names.sort();
+        (names, report["num_generated_skipped"].clone())
+    };
+
+    let (names, skipped) = files(&[]);
+    assert_eq!(names.len(), 4);
+    assert_eq!(skipped, 0);
+
+    let (names, skipped) = files(&["--ignore-generated"]);
+    assert_eq!(names, vec![t.name("a.c"), t.name("c.c"), t.name("d.c")]);
+    assert_eq!(skipped, 1);
+
+    // Given markers replace the built in ones.
+    let (names, skipped) = files(&["--ignore-generated", "--generated-marker", "made by mkfoo"]);
+    assert_eq!(names, vec![t.name("a.c"), t.name("b.pb.c"), t.name("c.c")]);
+    assert_eq!(skipped, 1);
+}
+
+#[test]
+fn record_separator() {
+    let t = Tree::new("record_separator");
+    for (sep, arg) in [("\u{1e}", "0x1e"), ("\0", "\\0")] {
+        let records = |prefix: &str| block(prefix, 8).replace('\n', sep);
+        t.write("a.dat", &format!("first{}{}", sep, records("x")));
+        t.write("b.dat", &format!("one{}two{}{}", sep, sep, records("x")));
+
+        // Without it each file is a single line.
+        assert!(duplicates(&t.json(&["-f", "*.dat"])).is_empty());
+
+        let report = t.json(&["-f", "*.dat", "--record-separator", arg]);
+        let all = duplicates(&report);
+        assert_eq!(all.len(), 1, "{}", arg);
+        assert_eq!(all[0]["num_lines"], 8);
+        assert_eq!(
+            locations(&all[0]),
+            vec![(t.name("a.dat"), 1), (t.name("b.dat"), 2)]
+        );
+    }
+
+    let out = t.run(&["-f", "*.dat", "--record-separator", "0x1g"]);
+    assert!(!out.status.success());
+    assert!(stderr(&out).contains("invalid record separator"));
+}
+
+#[test]
+fn ignore_tests() {
+    let t = Tree::new("ignore_tests");
+    t.write("tests/a.c", &block("t", 8));
+    t.write("tests/b.c", &block("t", 8));
+    t.write("src/a_test.c", &block("u", 8));
+    t.write("src/b_test.c", &block("u", 8));
+    // Copied out of a test into the source.
+    t.write("src/x.c", &block("s", 8));
+    t.write("tests/c.c", &block("s", 8));
+    t.write("fixtures/f1.c", &block("f", 8));
+    t.write("fixtures/f2.c", &block("f", 8));
+
+    let reported = |args: &[&str]| -> Vec<String> {
+        let report = t.json(&[&["-f", "**/*.c"][..], args].concat());
+        let mut first: Vec<String> = duplicates(&report)
+            .iter()
+            .map(|d| locations(d)[0].0.clone())
+            .collect();
+        first.sort();
+        first
+    };
+
+    assert_eq!(reported(&[]).len(), 4);
+    assert_eq!(
+        reported(&["--ignore-tests"]),
+        vec![t.name("fixtures/f1.c"), t.name("src/x.c")]
+    );
+    assert_eq!(
+        reported(&["--ignore-tests", "--test-pattern", "**/fixtures/**"]),
+        vec![t.name("src/x.c")]
+    );
+}
+
+#[test]
+fn skip_directive() {
+    let t = Tree::new("skip_directive");
+    let text = block("x", 8);
+    let (head, tail) = text.split_at(text.match_indices('\n').nth(3).unwrap().0 + 1);
+    t.write("a.c", &text);
+    t.write(
+        "b.c",
+        &format!("{}int b; // duplihere: skip\n{}", head, tail),
+    );
+    t.write("c.c", &format!("{}int c; // NODUP\n{}", head, tail));
+
+    let out = t.run(&["-f", "*.c"]);
+    let report = stdout(&out);
+    // The skipped line is left out of the comparison, not out of the line numbers.
+    assert!(report.contains(&format!("Between lines 1 and 8 in {}", t.name("a.c"))));
+    assert!(report.contains(&format!("Between lines 1 and 9 in {}", t.name("b.c"))));
+    assert!(!report.contains(&t.name("c.c")));
+
+    let report = t.json(&["-f", "*.c", "--skip-directive", "NODUP"]);
+    let names: Vec<String> = locations(&duplicates(&report)[0])
+        .into_iter()
+        .map(|l| l.0)
+        .collect();
+    assert_eq!(names, vec![t.name("a.c"), t.name("c.c")]);
+
+    assert!(duplicates(&t.json(&["-f", "*.c", "--skip-directive", ""])).is_empty());
+}
+
+// Only a case sensitive file system can have both a.c and A.c, on the others it's one file.
+#[cfg(target_os = "linux")]
+#[test]
+fn case_fold_paths() {
+    let t = Tree::new("case_fold_paths");
+    let text = block("x", 8);
+    t.write("a.c", &text);
+    t.write("A.c", &text);
+
+    let report = t.json(&["-f", "*.c", "--case-fold-paths", "false"]);
+    assert_eq!(duplicates(&report).len(), 1);
+
+    // The second name is taken for the first file, so there is nothing to compare it with.
+    let report = t.json(&["-f", "*.c", "--case-fold-paths", "true"]);
+    assert!(duplicates(&report).is_empty());
+}
+
+#[cfg(any(target_os = "macos", windows))]
+#[test]
+fn case_fold_paths() {
+    let t = Tree::new("case_fold_paths");
+    t.write("a.c", &block("x", 8));
+
+    let out = t.run(&["-f", "a.c", "-f", "A.c"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stdout(&out).contains(" in 1 files,"));
+}
+
+#[test]
+fn scan_stdin() {
+    let t = Tree::new("scan_stdin");
+    let repeated = block("piped", 7);
+    let piped = format!("{}int a;\n{}", repeated, repeated);
+
+    let out = t.run_with_stdin(&["-f", "-", "-p"], &piped);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let text = stdout(&out);
+    assert!(text.contains("Between lines 1 and 7 in <stdin>\n"));
+    assert!(text.contains("Between lines 9 and 15 in <stdin>\n"));
+    // stdin can't be read again, the text printed is what was kept of it.
+    assert!(text.contains(&repeated));
+
+    let out = t.run_with_stdin(&["-f", "-", "--scan-stdin-as", "piped.c", "-p"], &piped);
+    let text = stdout(&out);
+    assert!(text.contains("Between lines 1 and 7 in piped.c\n"));
+    assert!(!text.contains("<stdin>"));
+    assert!(text.contains(&repeated));
+}
+
+#[test]
+fn warnings_in_the_report() {
+    let t = Tree::new("warnings_in_the_report");
+    copies(&t);
+    t.write("ignore.txt", "not-a-signature\n");
+    fs::write(t.path("latin1.c"), b"caf\xe9;\n").unwrap();
+
+    let report = t.json(&["-f", "*.c", "-i", "ignore.txt"]);
+    let warnings = report["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 2);
+    assert!(warnings.contains(&serde_json::json!({
+        "kind": "invalid_ignore_entry",
+        "message": "Ignore file contains invalid hash value \"not-a-signature\"",
+    })));
+    assert!(warnings.contains(&serde_json::json!({
+        "kind": "invalid_utf8",
+        "message": format!(
+            "{} line 1 isn't valid UTF-8, invalid sequences were replaced",
+            t.name("latin1.c")
+        ),
+    })));
+    // The scan still reports what it could read.
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 3);
+}
+
+#[cfg(unix)]
+#[test]
+fn warning_for_an_unreadable_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let t = Tree::new("warning_for_an_unreadable_file");
+    copies(&t);
+    let locked = t.write("locked.c", &block("x", 8));
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    if fs::File::open(&locked).is_ok() {
+        // Running as root, nothing is unreadable.
+        return;
+    }
+
+    let out = t.run(&["-f", "*.c", "-j"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let warnings = report["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0]["kind"], "unreadable_file");
+    let message = warnings[0]["message"].as_str().unwrap();
+    assert!(message.starts_with(&format!("Unable to open {}, reason", t.name("locked.c"))));
+    assert!(stderr(&out).contains(message));
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 3);
+}
+
+#[test]
+fn scan_order() {
+    let t = Tree::new("scan_order");
+    let text = block("x", 8);
+    t.write("a.c", &text);
+    t.write("b.c", &format!("{}{}", text, block("b", 20)));
+    t.write("c.c", &format!("{}{}", text, block("c", 10)));
+    // c.c is the most recently modified, then a.c.
+    for (name, secs) in [("a.c", 1_500_000_000), ("b.c", 1_000_000_000)] {
+        let file = fs::File::options().write(true).open(t.path(name)).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    let order = |args: &[&str]| -> Vec<String> {
+        let report = t.json(args);
+        locations(&duplicates(&report)[0])
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    };
+    let names = |n: &[&str]| -> Vec<String> { n.iter().map(|n| t.name(n)).collect() };
+
+    let forward = ["-f", "a.c", "-f", "b.c", "-f", "c.c"];
+    let backward = ["-f", "c.c", "-f", "b.c", "-f", "a.c"];
+    assert_eq!(order(&forward), names(&["a.c", "b.c", "c.c"]));
+    assert_eq!(order(&backward), names(&["a.c", "b.c", "c.c"]));
+    assert_eq!(
+        order(&[&backward[..], &["--scan-order", "size"]].concat()),
+        names(&["b.c", "c.c", "a.c"])
+    );
+    assert_eq!(
+        order(&[&backward[..], &["--scan-order", "mtime"]].concat()),
+        names(&["c.c", "a.c", "b.c"])
+    );
+
+    // The file ids, and so the position signatures, don't depend on the order of the patterns.
+    let keys = |args: &[&str]| -> Vec<u64> {
+        let report = t.json(&[args, &["--fingerprint-mode", "position"]].concat());
+        let mut keys: Vec<u64> = duplicates(&report)
+            .iter()
+            .map(|d| d["key"].as_u64().unwrap())
+            .collect();
+        keys.sort_unstable();
+        keys
+    };
+    assert_eq!(keys(&forward), keys(&backward));
+}
+
+#[test]
+fn boilerplate() {
+    let t = Tree::new("boilerplate");
+    let header = "# SPDX-License-Identifier: MIT\n\
+                  #\n\
+                  # Copyright (C) 2020 Example Corp\n\
+                  # All rights reserved.\n\
+                  #\n\
+                  # See LICENSE for details.\n";
+    t.write("a.py", &format!("{}{}", header, block("a", 3)));
+    t.write("b.py", &format!("{}{}", header, block("shared", 8)));
+    t.write("c.py", &format!("{}{}", header, block("shared", 8)));
+    t.write(
+        "boilerplate.txt",
+        "# The license header every file starts with.\n\
+         \\# SPDX-License-Identifier: MIT\n\
+         \\#\n\
+         \n\
+         \x20   \\# Copyright (C) 2020 Example Corp\n\
+         \\# All rights reserved.\n\
+         \\# See LICENSE for details.\n",
+    );
+
+    let report = t.json(&["-f", "*.py"]);
+    assert!(duplicates(&report)
+        .iter()
+        .any(|d| d["num_lines"] == 6 && locations(d).len() == 3));
+
+    // The header is gone, the lines reported for the rest are still those of the file.
+    let report = t.json(&["-f", "*.py", "--boilerplate", "boilerplate.txt"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 8);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("b.py"), 6), (t.name("c.py"), 6)]
+    );
+}
+
+#[test]
+fn max_locations() {
+    let t = Tree::new("max_locations");
+    let header = block("header", 8);
+    for i in 0..200 {
+        t.write(&format!("{}.c", i), &format!("{}int f{};\n", header, i));
+    }
+
+    let report = t.json(&["-f", "*.c"]);
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 200);
+    assert!(report["warnings"].as_array().unwrap().is_empty());
+
+    let report = t.json(&["-f", "*.c", "--max-locations", "500"]);
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 200);
+    assert!(report["warnings"].as_array().unwrap().is_empty());
+
+    let out = t.run(&["-f", "*.c", "--max-locations", "50", "-j"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let kept = locations(&duplicates(&report)[0]);
+    assert!(kept.len() <= 50, "{} locations", kept.len());
+    let mut distinct = kept.clone();
+    distinct.dedup();
+    assert_eq!(distinct, kept);
+    assert_eq!(report["warnings"][0]["kind"], "location_limit");
+    assert!(stderr(&out).contains("--max-locations 50 reached"));
+}
+
+// Linux lets a file name be any bytes, other systems refuse to create this one.
+#[cfg(target_os = "linux")]
+#[test]
+fn non_utf8_file_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let t = Tree::new("non_utf8_file_names");
+    copies(&t);
+    // A * never matches the name, only ** gets into the directory.
+    let dir = t.root.join(OsStr::from_bytes(b"caf\xe9"));
+    fs::create_dir(&dir).unwrap();
+    fs::write(dir.join("d.c"), block("x", 8)).unwrap();
+
+    let out = t.run(&["-f", "**/*.c", "-j"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    let report: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(locations(&duplicates(&report)[0]).len(), 3);
+    let warnings = report["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0]["kind"], "non_utf8_name");
+    assert_eq!(
+        warnings[0]["message"],
+        "File name caf\u{fffd}/d.c isn't valid UTF-8, skipped"
+    );
+
+    let out = t.run(&["-f", "**/*.c", "--strict-utf8-paths"]);
+    assert_eq!(out.status.code(), Some(1));
+    let err = stderr(&out);
+    assert!(err.contains("isn't valid UTF-8"), "{}", err);
+    assert!(!err.contains("panicked"), "{}", err);
+}
+
+#[test]
+fn max_overlap_ratio() {
+    let t = Tree::new("max_overlap_ratio");
+    t.write("g.c", &block("x", 6).repeat(4));
+
+    let found = |ratio: &str| -> Vec<(u64, Vec<u64>)> {
+        let report = t.json(&["-f", "g.c", "--max-overlap-ratio", ratio]);
+        let mut found: Vec<(u64, Vec<u64>)> = duplicates(&report)
+            .iter()
+            .map(|d| {
+                let starts = locations(d).into_iter().map(|(_, line)| line).collect();
+                (d["num_lines"].as_u64().unwrap(), starts)
+            })
+            .collect();
+        found.sort();
+        found
+    };
+
+    // Copies of a duplicate are neither overlapping nor adjacent.
+    let strict = found("0");
+    assert!(!strict.is_empty());
+    for (num_lines, starts) in &strict {
+        assert!(starts.windows(2).all(|w| w[1] - w[0] > *num_lines));
+    }
+
+    // Copies sharing up to half their lines, the 6 line sequence is found at every repeat.
+    assert!(found("0.5").contains(&(6, vec![0, 6, 12, 18])));
+
+    // A duplicate for every multiple of the sequence.
+    assert_eq!(
+        found("1"),
+        vec![
+            (6, vec![0, 6, 12, 18]),
+            (12, vec![0, 6, 12]),
+            (18, vec![0, 6])
+        ]
+    );
+
+    let out = t.run(&["-f", "g.c", "--max-overlap-ratio", "1.5"]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn largest_files() {
+    let t = Tree::new("largest_files");
+    t.write("small.c", &block("small", 3));
+    t.write("large.c", &block("large", 40));
+    t.write(
+        "medium.c",
+        &format!("{}int x; // duplihere: skip\n", block("medium", 12)),
+    );
+    t.write("tie.c", &block("tie", 12));
+
+    let out = t.run(&["-f", "*.c", "--largest-files", "3"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    // The skipped line isn't counted, equal counts are in name order.
+    let expected: String = [("large.c", 40), ("medium.c", 12), ("tie.c", 12)]
+        .iter()
+        .map(|(name, lines)| format!("{:>10} {}\n", lines, t.name(name)))
+        .collect();
+    assert!(stderr(&out).contains(&format!(
+        "Largest files by lines scanned:\n{:>10} file\n{}",
+        "lines", expected
+    )));
+    assert!(!stderr(&out).contains("small.c"));
+
+    let out = t.run(&["-f", "*.c"]);
+    assert!(!stderr(&out).contains("Largest files"));
+}
+
+#[test]
+fn whole_functions() {
+    let t = Tree::new("whole_functions");
+    let total = "int total(int *v, int n)\n\
+                 {\n\
+                     int sum = 0;\n\
+                     for (int i = 0; i < n; i++) {\n\
+                         sum += v[i];\n\
+                     }\n\
+                     return sum;\n\
+                 }\n";
+    let body = block("    x", 8);
+    t.write(
+        "a.c",
+        &format!(
+            "#include <a.h>\n\n{}\nvoid fill(int *v)\n{{\n{}}}\n",
+            total, body
+        ),
+    );
+    // total is copied whole, fill only partly.
+    t.write(
+        "b.c",
+        &format!(
+            "{}\nvoid clear(int *v)\n{{\n{}    v[0] = 0;\n}}\n",
+            total, body
+        ),
+    );
+
+    let report = t.json(&["-f", "*.c", "--whole-functions", "c"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["function"], "total");
+    assert_eq!(all[0]["num_lines"], 8);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("b.c"), 0), (t.name("a.c"), 2)]
+    );
+
+    let out = t.run(&["-f", "*.c", "--whole-functions", "c"]);
+    assert!(stdout(&out).contains("The whole function total\n"));
+
+    // Lines find the body of fill too.
+    let report = t.json(&["-f", "*.c"]);
+    assert_eq!(duplicates(&report).len(), 2);
+}
+
+#[test]
+fn ignore_first_line() {
+    let t = Tree::new("ignore_first_line");
+    let header = "#!/usr/bin/python3\n# generated, do not edit\n";
+    let body = block("y", 6);
+    t.write("a.py", &format!("{}{}", header, body));
+    t.write("b.py", &format!("{}{}", header, body));
+
+    let report = t.json(&["-f", "*.py"]);
+    assert_eq!(duplicates(&report)[0]["num_lines"], 8);
+
+    // The header isn't part of the duplicate, lines are still numbered from the top of the file.
+    let report = t.json(&["-f", "*.py", "--ignore-first-line", "2"]);
+    let all = duplicates(&report);
+    assert_eq!(all.len(), 1);
+    assert_eq!(all[0]["num_lines"], 6);
+    assert_eq!(
+        locations(&all[0]),
+        vec![(t.name("a.py"), 2), (t.name("b.py"), 2)]
+    );
+
+    // The header lines count towards --first-n-lines, leaving 5 lines to compare.
+    let report = t.json(&[
+        "-f",
+        "*.py",
+        "--ignore-first-line",
+        "2",
+        "--first-n-lines",
+        "7",
+    ]);
+    assert!(duplicates(&report).is_empty());
+}
+
+#[test]
+fn max_pairs() {
+    let t = Tree::new("max_pairs");
+    // Every file is the same few lines over and over, every location shares every window.
+    let repeated = block("x", 6).repeat(5);
+    for i in 0..50 {
+        t.write(&format!("{}.c", i), &repeated);
+    }
+
+    let count = |report: &serde_json::Value| -> usize {
+        duplicates(report).iter().map(|d| locations(d).len()).sum()
+    };
+
+    let full = t.json(&["-f", "*.c"]);
+    assert!(full["warnings"].as_array().unwrap().is_empty());
+
+    let out = t.run(&["-f", "*.c", "--max-pairs", "10", "-j"]);
+    assert!(out.status.success(), "{}", stderr(&out));
+    assert!(stderr(&out).contains("--max-pairs 10 reached, the results are incomplete"));
+    let capped: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(capped["warnings"][0]["kind"], "pair_limit");
+    assert!(count(&capped) < count(&full));
+}
+
+#[test]
+fn treat_includes_as_boundaries() {
+    let t = Tree::new("treat_includes_as_boundaries");
+    let text = format!(
+        "{}#include <stdio.h>\n#include \"util.h\"\n{}",
+        block("setup", 4),
+        block("code", 7)
+    );
+    for name in ["a.c", "b.c", "a.py", "b.py"] {
+        t.write(name, &text);
+    }
+
+    let found = |glob: &str, args: &[&str]| -> Vec<(u64, Vec<(String, u64)>)> {
+        let report = t.json(&[&["-f", glob][..], args].concat());
+        duplicates(&report)
+            .iter()
+            .map(|d| (d["num_lines"].as_u64().unwrap(), locations(d)))
+            .collect()
+    };
+
+    let whole = |a: &str, b: &str| vec![(13, vec![(t.name(a), 0), (t.name(b), 0)])];
+    assert_eq!(found("*.c", &[]), whole("a.c", "b.c"));
+    // Only the code after the includes, what's before them is too short.
+    assert_eq!(
+        found("*.c", &["--treat-includes-as-boundaries"]),
+        vec![(7, vec![(t.name("a.c"), 6), (t.name("b.c"), 6)])]
+    );
+    // # is a comment in Python.
+    assert_eq!(
+        found("*.py", &["--treat-includes-as-boundaries"]),
+        whole("a.py", "b.py")
+    );
+}
+
+#[test]
+fn blank_line_boundary() {
+    let t = Tree::new("blank_line_boundary");
+    // Two groups split by a blank line, and one with whitespace, in both copies.
+    let text = format!(
+        "{}\n{}  \t\n{}",
+        block("first", 7),
+        block("second", 6),
+        block("short", 3)
+    );
+    t.write("a.c", &text);
+    t.write("b.c", &text);
+
+    let found = |args: &[&str]| -> Vec<(u64, u64)> {
+        let report = t.json(&[&["-f", "*.c"][..], args].concat());
+        let mut found: Vec<(u64, u64)> = duplicates(&report)
+            .iter()
+            .map(|d| (locations(d)[0].1, d["num_lines"].as_u64().unwrap()))
+            .collect();
+        found.sort();
+        found
+    };
+
+    assert_eq!(found(&[]), vec![(0, 18)]);
+    // Each group on its own, the one of 3 lines is too short.
+    assert_eq!(found(&["--blank-line-boundary"]), vec![(0, 7), (8, 6)]);
+
+    let out = t.run(&["-f", "*.c", "--blank-line-boundary", "--suffix-array"]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn base_dir() {
+    let t = Tree::new("base_dir");
+    t.write("proj/src/a.c", &block("proj", 8));
+    t.write("proj/src/b.c", &block("proj", 8));
+    // The same names in the current directory, which --base-dir looks past.
+    t.write("src/a.c", &block("here", 8));
+    t.write("src/b.c", &block("here", 8));
+
+    let scanned = |args: &[&str]| -> Vec<(String, u64)> {
+        let report = t.json(args);
+        duplicates(&report).iter().flat_map(locations).collect()
+    };
+
+    let proj = vec![(t.name("proj/src/a.c"), 0), (t.name("proj/src/b.c"), 0)];
+    assert_eq!(scanned(&["-f", "src/*.c", "--base-dir", "proj"]), proj);
+    assert_eq!(
+        scanned(&["-f", "src/*.c"]),
+        vec![(t.name("src/a.c"), 0), (t.name("src/b.c"), 0)]
+    );
+
+    // Absolute patterns are as given, other file names are relative to the current directory.
+    let absolute = format!("{}/*.c", t.name("proj/src"));
+    assert_eq!(scanned(&["-f", &absolute, "--base-dir", "src"]), proj);
+    let report = t.json(&["-f", "src/*.c", "--base-dir", "proj"]);
+    t.write(
+        "ignore.txt",
+        &format!("{}\n", duplicates(&report)[0]["key"]),
+    );
+    let report = t.json(&["-f", "src/*.c", "--base-dir", "proj", "-i", "ignore.txt"]);
+    assert_eq!(report["num_ignored"], 1);
+
+    let out = t.run(&["-f", "src/*.c", "--base-dir", "missing"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(stderr(&out).contains("--base-dir missing isn't a directory"));
+}
+
+#[cfg(unix)]
+#[test]
+fn skip_symlinked_dirs() {
+    use std::os::unix::fs::symlink;
+
+    let t = Tree::new("skip_symlinked_dirs");
+    t.write("src/a.c", &block("shared", 8));
+    t.write("vendor/b.c", &block("shared", 8));
+    t.write("vendor/c.c", &block("vendored", 8));
+    t.write("other/c.c", &block("vendored", 8));
+    symlink(t.path("vendor"), t.path("src/lib")).unwrap();
+    symlink(t.path("other"), t.path("src/other")).unwrap();
+
+    let scanned = |args: &[&str]| -> Vec<Vec<(String, u64)>> {
+        let report = t.json(&[&["-f", "src/**/*.c"][..], args].concat());
+        duplicates(&report).iter().map(locations).collect()
+    };
+
+    // Each file once, by its real name.
+    let mut linked = scanned(&[]);
+    linked.sort();
+    assert_eq!(
+        linked,
+        vec![
+            vec![(t.name("other/c.c"), 0), (t.name("vendor/c.c"), 0)],
+            vec![(t.name("src/a.c"), 0), (t.name("vendor/b.c"), 0)],
+        ]
+    );
+    assert!(scanned(&["--skip-symlinked-dirs"]).is_empty());
+
+    // A link to a file is still scanned.
+    symlink(t.path("vendor/b.c"), t.path("src/b.c")).unwrap();
+    assert_eq!(
+        scanned(&["--skip-symlinked-dirs"]),
+        vec![vec![(t.name("src/a.c"), 0), (t.name("vendor/b.c"), 0)]]
+    );
+}